@@ -0,0 +1,40 @@
+//! Parsing the tables of a DAT with many entries, which `extract_dat_files_with_handler` does before
+//! handing out the first entry.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use extract_dat_files::dat_repack::{repack_dat_files, DatRepackOptions};
+use extract_dat_files::extract_dat_files_with_handler;
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+
+/// Writes a DAT of `count` small entries with names of varying length to `dat_path`.
+fn write_many_entry_dat(dat_path: &Path, count: usize) {
+    let source_dir = tempfile::tempdir().unwrap();
+    let names: Vec<String> = (0..count).map(|i| format!("entry_{}_{}.bin", i, "x".repeat(i % 16))).collect();
+    for name in &names {
+        fs::write(source_dir.path().join(name), name.as_bytes()).unwrap();
+    }
+    fs::write(source_dir.path().join("dat_info.json"), json!({ "files": names }).to_string()).unwrap();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime
+        .block_on(repack_dat_files(source_dir.path().to_str().unwrap(), dat_path.to_str().unwrap(), &DatRepackOptions::default()))
+        .unwrap();
+}
+
+fn parse_tables(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let mut group = c.benchmark_group("dat_tables");
+    for count in [1_000, 10_000] {
+        let dat_path = dir.path().join(format!("{}.dat", count));
+        write_many_entry_dat(&dat_path, count);
+        let dat_path = dat_path.to_str().unwrap().to_string();
+        group.bench_with_input(BenchmarkId::from_parameter(count), &dat_path, |b, dat_path| {
+            b.iter(|| extract_dat_files_with_handler(dat_path, |_, _| Ok(())).unwrap());
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parse_tables);
+criterion_main!(benches);
@@ -0,0 +1,45 @@
+//! Extracting a DAT with one large entry using different `ExtractOptions::buffer_size`s.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use extract_dat_files::dat_repack::{repack_dat_files, DatRepackOptions};
+use extract_dat_files::{extract_dat_files_with_options, ExtractOptions};
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+
+const ENTRY_SIZE: usize = 64 * 1024 * 1024;
+
+/// Writes a DAT holding a single `ENTRY_SIZE` byte entry to `dat_path`.
+fn write_large_entry_dat(dat_path: &Path) {
+    let source_dir = tempfile::tempdir().unwrap();
+    let data: Vec<u8> = (0..ENTRY_SIZE).map(|i| (i % 251) as u8).collect();
+    fs::write(source_dir.path().join("large.bin"), data).unwrap();
+    fs::write(source_dir.path().join("dat_info.json"), json!({ "files": ["large.bin"] }).to_string()).unwrap();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime
+        .block_on(repack_dat_files(source_dir.path().to_str().unwrap(), dat_path.to_str().unwrap(), &DatRepackOptions::default()))
+        .unwrap();
+}
+
+fn write_buffer_sizes(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let dat_path = dir.path().join("large.dat");
+    write_large_entry_dat(&dat_path);
+    let dat_path = dat_path.to_str().unwrap();
+    let extract_dir = dir.path().join("out");
+    let extract_dir = extract_dir.to_str().unwrap();
+
+    let mut group = c.benchmark_group("write_buffer");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(ENTRY_SIZE as u64));
+    for buffer_size in [64 * 1024, 1024 * 1024, 8 * 1024 * 1024] {
+        let options = ExtractOptions { buffer_size: Some(buffer_size), quiet: true, ..Default::default() };
+        group.bench_with_input(BenchmarkId::from_parameter(buffer_size), &options, |b, options| {
+            b.iter(|| extract_dat_files_with_options(dat_path, extract_dir, options).unwrap());
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, write_buffer_sizes);
+criterion_main!(benches);
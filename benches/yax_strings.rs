@@ -0,0 +1,27 @@
+//! Converting a YAX with many long strings, where most of the time goes into splitting the string table.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use extract_dat_files::xml_to_yax_convert::xml_to_yax;
+use extract_dat_files::yax_to_xml_convert::{yax_bytes_to_xml, YaxConvertOptions};
+
+/// A YAX of `count` `text` nodes, each with a distinct string of about `length` bytes.
+fn long_string_yax(count: usize, length: usize) -> Vec<u8> {
+    let mut xml = String::from("<root>");
+    for i in 0..count {
+        xml.push_str(&format!("<text>{}{}</text>", i, "a".repeat(length)));
+    }
+    xml.push_str("</root>");
+    xml_to_yax(xml.as_bytes()).unwrap()
+}
+
+fn convert_long_strings(c: &mut Criterion) {
+    let yax = long_string_yax(1_000, 4_096);
+    let options = YaxConvertOptions::default();
+    let mut group = c.benchmark_group("yax_strings");
+    group.throughput(Throughput::Bytes(yax.len() as u64));
+    group.bench_function("1000x4KiB", |b| b.iter(|| yax_bytes_to_xml(&yax, &options).unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, convert_long_strings);
+criterion_main!(benches);
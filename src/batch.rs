@@ -0,0 +1,191 @@
+use crate::yax_to_xml_convert::collect_files_with_extension;
+use crate::{extract_dat_files_detailed, ExtractOptions, ExtractionResult};
+use futures::stream::{self, StreamExt};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+
+/// What extracting one DAT of an `extract_dat_directory` run produced.
+#[derive(Debug, Clone)]
+pub struct DatReport {
+    pub dat_path: String,
+    pub extract_dir: String,
+    pub files: usize,
+    /// Total size of the extracted entries.
+    pub bytes: u64,
+    pub paks_extracted: usize,
+    pub yax_converted: usize,
+}
+
+impl DatReport {
+    fn new(dat_path: &Path, extract_dir: &Path, result: &ExtractionResult) -> Self {
+        let yax_files = result.pak_results.iter().flat_map(|pak_result| &pak_result.files);
+        Self {
+            dat_path: dat_path.to_string_lossy().into_owned(),
+            extract_dir: extract_dir.to_string_lossy().into_owned(),
+            files: result.files.len(),
+            bytes: result.files.iter().map(|file| file.size).sum(),
+            paks_extracted: result.pak_results.len(),
+            yax_converted: yax_files.filter(|file| Path::new(&file.path).with_extension("xml").exists()).count(),
+        }
+    }
+}
+
+
+/// A DAT of an `extract_dat_directory` run that couldn't be extracted.
+#[derive(Debug, Clone)]
+pub struct DatFailure {
+    pub dat_path: String,
+    pub error: String,
+}
+
+
+/// Summary of an `extract_dat_directory` run: the totals over all extracted DATs, the report of each one
+/// and the DATs that failed.
+#[derive(Debug, Clone, Default)]
+pub struct BatchReport {
+    pub dats: Vec<DatReport>,
+    pub failures: Vec<DatFailure>,
+    pub total_files: usize,
+    pub total_bytes: u64,
+    pub paks_extracted: usize,
+    pub yax_converted: usize,
+    pub elapsed: Duration,
+}
+
+
+/// Extracts every `.dat` and `.dtt` file below `input_dir` (recursively) with `options`, each into
+/// `extract_dir/<path of the DAT relative to input_dir>`, and reports the totals. As many DATs as there are
+/// CPUs are extracted at a time. A DAT that fails is listed in `failures` and the others
+/// are still extracted, so only finding the DATs can fail the whole run.
+pub async fn extract_dat_directory(input_dir: &str, extract_dir: &str, options: &ExtractOptions) -> io::Result<BatchReport> {
+    let start = Instant::now();
+    let input_dir = PathBuf::from(input_dir);
+    let mut dat_paths = Vec::new();
+    for extension in ["dat", "dtt"] {
+        collect_files_with_extension(&input_dir, extension, &mut dat_paths)?;
+    }
+    dat_paths.sort();
+
+    let results: Vec<(PathBuf, io::Result<DatReport>)> = stream::iter(dat_paths)
+        .map(|dat_path| {
+            let dat_extract_dir = Path::new(extract_dir).join(dat_path.strip_prefix(&input_dir).unwrap_or(&dat_path));
+            let options = options.clone();
+            async move {
+                let task_dat_path = dat_path.clone();
+                let dat_report = tokio::task::spawn_blocking(move || -> io::Result<DatReport> {
+                    let result = extract_dat_files_detailed(
+                        &task_dat_path.to_string_lossy(),
+                        &dat_extract_dir.to_string_lossy(),
+                        &options,
+                    )?;
+                    Ok(DatReport::new(&task_dat_path, &dat_extract_dir, &result))
+                })
+                .await
+                .map_err(io::Error::other)
+                .and_then(|dat_report| dat_report);
+                (dat_path, dat_report)
+            }
+        })
+        .buffered(num_cpus::get().max(1))
+        .collect()
+        .await;
+
+    let mut report = BatchReport::default();
+    for (dat_path, dat_report) in results {
+        match dat_report {
+            Ok(dat_report) => {
+                report.total_files += dat_report.files;
+                report.total_bytes += dat_report.bytes;
+                report.paks_extracted += dat_report.paks_extracted;
+                report.yax_converted += dat_report.yax_converted;
+                report.dats.push(dat_report);
+            }
+            Err(e) => report.failures.push(DatFailure {
+                dat_path: dat_path.to_string_lossy().into_owned(),
+                error: e.to_string(),
+            }),
+        }
+    }
+
+    report.elapsed = start.elapsed();
+    Ok(report)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[tokio::test]
+    async fn totals_match_the_individual_extractions() {
+        let dir = temp_dir();
+        let input_dir = dir.path().join("in");
+        std::fs::create_dir_all(input_dir.join("sub")).unwrap();
+        let yax = yax_bytes("<root><text>a</text></root>");
+        let pak = pak_bytes(&[TestPakEntry::raw(&yax), TestPakEntry::raw(&yax)]);
+        write_dat(&input_dir, "a.dat", &[("a.bin", b"abc"), ("b.pak", &pak)]);
+        write_dat(&input_dir.join("sub"), "b.dtt", &[("c.bin", b"defgh")]);
+        write_file(&input_dir, "broken.dat", b"DAT\0");
+        let options = ExtractOptions { extract_pak_files: true, ..Default::default() };
+
+        let report = extract_dat_directory(&path_str(&input_dir), &path_str(&dir.path().join("out")), &options)
+            .await
+            .unwrap();
+
+        assert_eq!(report.failures.len(), 1);
+        assert!(report.failures[0].dat_path.ends_with("broken.dat"));
+        assert_eq!(report.dats.len(), 2);
+
+        let mut expected = BatchReport::default();
+        for (dat, extract_dir) in [("a.dat", "a"), ("sub/b.dtt", "b")] {
+            let dat_path = input_dir.join(dat);
+            let extract_dir = dir.path().join("single").join(extract_dir);
+            let result = extract_dat_files_detailed(&path_str(&dat_path), &path_str(&extract_dir), &options).unwrap();
+            let dat_report = DatReport::new(&dat_path, &extract_dir, &result);
+            let batch_dat_report = report.dats.iter().find(|r| r.dat_path == path_str(&dat_path)).unwrap();
+            assert_eq!(
+                (batch_dat_report.files, batch_dat_report.bytes, batch_dat_report.paks_extracted, batch_dat_report.yax_converted),
+                (dat_report.files, dat_report.bytes, dat_report.paks_extracted, dat_report.yax_converted),
+            );
+            expected.total_files += dat_report.files;
+            expected.total_bytes += dat_report.bytes;
+            expected.paks_extracted += dat_report.paks_extracted;
+            expected.yax_converted += dat_report.yax_converted;
+        }
+        assert_eq!(
+            (report.total_files, report.total_bytes, report.paks_extracted, report.yax_converted),
+            (expected.total_files, expected.total_bytes, expected.paks_extracted, expected.yax_converted),
+        );
+        assert_eq!((report.total_files, report.paks_extracted, report.yax_converted), (3, 1, 2));
+        assert_eq!(report.total_bytes, 3 + pak.len() as u64 + 5);
+    }
+
+    #[tokio::test]
+    async fn bad_dats_are_reported_and_the_others_extracted() {
+        let dir = temp_dir();
+        let input_dir = dir.path().join("in");
+        std::fs::create_dir(&input_dir).unwrap();
+        for name in ["a.dat", "c.dat", "e.dat"] {
+            write_dat(&input_dir, name, &[("x.bin", name.as_bytes())]);
+        }
+        write_file(&input_dir, "b.dat", b"not a DAT at all, just text");
+        let truncated = dat_bytes(&[("x.bin", &[7; 100])]);
+        write_file(&input_dir, "d.dat", &truncated[..truncated.len() - 50]);
+        let extract_dir = dir.path().join("out");
+
+        let report = extract_dat_directory(&path_str(&input_dir), &path_str(&extract_dir), &ExtractOptions::default())
+            .await
+            .unwrap();
+
+        let failed: Vec<&str> = report.failures.iter().map(|failure| failure.dat_path.rsplit(['/', '\\']).next().unwrap()).collect();
+        assert_eq!(failed, ["b.dat", "d.dat"]);
+        assert!(report.failures.iter().all(|failure| !failure.error.is_empty()));
+        assert_eq!(report.dats.len(), 3);
+        for name in ["a.dat", "c.dat", "e.dat"] {
+            assert_eq!(std::fs::read(extract_dir.join(name).join("x.bin")).unwrap(), name.as_bytes());
+        }
+    }
+}
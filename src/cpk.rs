@@ -0,0 +1,628 @@
+use crate::{extract_dir_result, extract_listing_to_sink, parse_dat_listing, unwrap_zlib_dat, ByteDataWrapper, ExtractOptions, ExtractionResult, FsSink, DEFAULT_BUFFER_SIZE};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+
+
+/// One file listed in the TOC of a CPK.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpkEntry {
+    /// `DirName/FileName`, or just the file name for files in the top directory.
+    pub path: String,
+    /// Absolute offset of the file in the CPK.
+    pub offset: u64,
+    /// Size of the file in the CPK.
+    pub size: u32,
+    /// Size of the file once extracted, larger than `size` for CRILAYLA compressed files.
+    pub extract_size: u32,
+}
+
+impl CpkEntry {
+    fn file_name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+
+    fn is_dat(&self) -> bool {
+        let extension = self.path.rsplit_once('.').map_or("", |(_, extension)| extension);
+        extension.eq_ignore_ascii_case("dat") || extension.eq_ignore_ascii_case("dtt")
+    }
+}
+
+
+fn invalid_cpk(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+
+/// A value of a `@UTF` table cell. The CPK tables are only read for integers and strings, other cells are skipped.
+#[derive(Debug, Clone)]
+enum UtfValue {
+    Int(u64),
+    String(String),
+    Other,
+}
+
+impl UtfValue {
+    fn as_u64(&self) -> Option<u64> {
+        match self {
+            UtfValue::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+
+/// Big endian reads from a `@UTF` table, bounds checked.
+struct UtfReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> UtfReader<'a> {
+    fn bytes(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        let bytes = self
+            .position
+            .checked_add(count)
+            .and_then(|end| self.data.get(self.position..end))
+            .ok_or_else(|| invalid_cpk(format!("@UTF table ends before offset {}", self.position + count)))?;
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_be_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    /// The null-terminated string at `offset` in the string area starting at `strings_offset`.
+    fn string_at(&self, strings_offset: usize, offset: u32) -> io::Result<String> {
+        let start = strings_offset + offset as usize;
+        let rest = self.data.get(start..).ok_or_else(|| invalid_cpk(format!("@UTF string offset {} is out of bounds", start)))?;
+        let end = rest.iter().position(|&byte| byte == 0).unwrap_or(rest.len());
+        Ok(String::from_utf8_lossy(&rest[..end]).into_owned())
+    }
+
+    /// Reads a value of the type in the low nibble of a column's flags.
+    fn value(&mut self, column_type: u8, strings_offset: usize) -> io::Result<UtfValue> {
+        Ok(match column_type {
+            0x0 | 0x1 => UtfValue::Int(u64::from(self.u8()?)),
+            0x2 | 0x3 => UtfValue::Int(u64::from(self.u16()?)),
+            0x4 | 0x5 => UtfValue::Int(u64::from(self.u32()?)),
+            0x6 | 0x7 => UtfValue::Int(self.u64()?),
+            0x8 => {
+                self.bytes(4)?;
+                UtfValue::Other
+            }
+            0x9 | 0xB => {
+                self.bytes(8)?;
+                UtfValue::Other
+            }
+            0xA => {
+                let offset = self.u32()?;
+                UtfValue::String(self.string_at(strings_offset, offset)?)
+            }
+            _ => return Err(invalid_cpk(format!("unknown @UTF column type 0x{:X}", column_type))),
+        })
+    }
+}
+
+
+const STORAGE_MASK: u8 = 0xF0;
+const STORAGE_ZERO: u8 = 0x10;
+const STORAGE_CONSTANT: u8 = 0x30;
+const STORAGE_PER_ROW: u8 = 0x50;
+const STORAGE_CONSTANT_2: u8 = 0x70;
+
+
+/// Undoes the XOR the tables of some CPKs are scrambled with.
+fn decrypt_utf(packet: &[u8]) -> Vec<u8> {
+    let mut key: u32 = 0x655F;
+    packet
+        .iter()
+        .map(|&byte| {
+            let decrypted = byte ^ key as u8;
+            key = key.wrapping_mul(0x4115);
+            decrypted
+        })
+        .collect()
+}
+
+
+/// Parses a `@UTF` table (the format of all CPK tables) into its rows, each a map of column name to value.
+/// All offsets in the table are relative to the byte after its `@UTF` magic and size.
+/// Columns stored as zero have no value, constant columns have the same one in every row.
+fn read_utf_table(packet: &[u8]) -> io::Result<Vec<HashMap<String, UtfValue>>> {
+    let decrypted;
+    let packet = if packet.starts_with(b"@UTF") {
+        packet
+    } else {
+        decrypted = decrypt_utf(packet);
+        if !decrypted.starts_with(b"@UTF") {
+            return Err(invalid_cpk("table doesn't start with @UTF"));
+        }
+        &decrypted
+    };
+
+    let mut reader = UtfReader { data: packet, position: 4 };
+    let table_size = reader.u32()? as usize;
+    let table = packet.get(8..8 + table_size).ok_or_else(|| invalid_cpk("@UTF table is larger than its packet"))?;
+    let mut reader = UtfReader { data: table, position: 0 };
+    let _version = reader.u16()?;
+    let rows_offset = reader.u16()? as usize;
+    let strings_offset = reader.u32()? as usize;
+    let _data_offset = reader.u32()?;
+    let _table_name = reader.u32()?;
+    let column_count = reader.u16()?;
+    let row_length = reader.u16()? as usize;
+    let row_count = reader.u32()?;
+
+    let mut columns = Vec::with_capacity(column_count as usize);
+    for _ in 0..column_count {
+        let flags = reader.u8()?;
+        let name_offset = reader.u32()?;
+        let name = reader.string_at(strings_offset, name_offset)?;
+        let constant = match flags & STORAGE_MASK {
+            STORAGE_CONSTANT | STORAGE_CONSTANT_2 => Some(reader.value(flags & 0x0F, strings_offset)?),
+            _ => None,
+        };
+        columns.push((flags, name, constant));
+    }
+
+    let rows_end = (row_count as usize).checked_mul(row_length.max(1)).and_then(|rows_len| rows_len.checked_add(rows_offset));
+    if rows_end.is_none_or(|rows_end| rows_end > table.len()) {
+        return Err(invalid_cpk(format!("{} @UTF rows of {} bytes don't fit in the table", row_count, row_length)));
+    }
+
+    let mut rows = Vec::with_capacity(row_count as usize);
+    for row in 0..row_count as usize {
+        reader.position = rows_offset + row * row_length;
+        let mut values = HashMap::new();
+        for (flags, name, constant) in &columns {
+            let value = match flags & STORAGE_MASK {
+                STORAGE_PER_ROW => reader.value(flags & 0x0F, strings_offset)?,
+                STORAGE_ZERO => continue,
+                _ => match constant {
+                    Some(constant) => constant.clone(),
+                    None => continue,
+                },
+            };
+            values.insert(name.clone(), value);
+        }
+        rows.push(values);
+    }
+    Ok(rows)
+}
+
+
+/// Reads the `@UTF` table of the chunk at `offset`: a 4 byte magic, 4 unknown bytes, the u64 little endian
+/// size of the table and the table itself.
+fn read_chunk_table(file: &mut File, offset: u64, magic: &[u8; 4]) -> io::Result<Vec<HashMap<String, UtfValue>>> {
+    let mut chunk_header = [0; 16];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut chunk_header)?;
+    if &chunk_header[..4] != magic {
+        return Err(invalid_cpk(format!("no {} chunk at offset {}", String::from_utf8_lossy(magic).trim(), offset)));
+    }
+
+    let size = u64::from_le_bytes(chunk_header[8..16].try_into().unwrap());
+    let file_len = file.metadata()?.len();
+    if size > file_len.saturating_sub(offset + 16) {
+        return Err(invalid_cpk(format!("{} chunk at offset {} is larger than the file", String::from_utf8_lossy(magic).trim(), offset)));
+    }
+    let mut packet = vec![0; size as usize];
+    file.read_exact(&mut packet)?;
+    read_utf_table(&packet)
+}
+
+
+/// Lists every file in the TOC of a CPK, reading only its header and TOC.
+pub fn read_cpk_toc(cpk_path: &str) -> io::Result<Vec<CpkEntry>> {
+    read_toc(&mut File::open(cpk_path)?)
+}
+
+
+fn read_toc(file: &mut File) -> io::Result<Vec<CpkEntry>> {
+    let header = read_chunk_table(file, 0, b"CPK ")?;
+    let header = header.first().ok_or_else(|| invalid_cpk("CPK header table has no row"))?;
+    let toc_offset = header
+        .get("TocOffset")
+        .and_then(UtfValue::as_u64)
+        .filter(|&offset| offset != 0)
+        .ok_or_else(|| invalid_cpk("CPK has no TOC"))?;
+    // File offsets count from the TOC, or from the content if that comes first
+    let base_offset = header
+        .get("ContentOffset")
+        .and_then(UtfValue::as_u64)
+        .map_or(toc_offset, |content_offset| content_offset.min(toc_offset));
+
+    read_chunk_table(file, toc_offset, b"TOC ")?
+        .into_iter()
+        .enumerate()
+        .map(|(index, row)| {
+            let string = |column: &str| match row.get(column) {
+                Some(UtfValue::String(value)) => value.clone(),
+                _ => String::new(),
+            };
+            let number = |column: &str| {
+                row.get(column)
+                    .and_then(UtfValue::as_u64)
+                    .ok_or_else(|| invalid_cpk(format!("CPK TOC row {} has no {}", index, column)))
+            };
+            let size_u32 = |column: &str, size: u64| {
+                u32::try_from(size).map_err(|_| invalid_cpk(format!("CPK TOC row {} has a {} of {}, which is too large", index, column, size)))
+            };
+
+            let (dir_name, file_name) = (string("DirName"), string("FileName"));
+            let size = size_u32("FileSize", number("FileSize")?)?;
+            let extract_size = match row.get("ExtractSize").and_then(UtfValue::as_u64) {
+                Some(extract_size) => size_u32("ExtractSize", extract_size)?,
+                None => size,
+            };
+            Ok(CpkEntry {
+                path: if dir_name.is_empty() { file_name } else { format!("{}/{}", dir_name, file_name) },
+                offset: base_offset + number("FileOffset")?,
+                size,
+                extract_size,
+            })
+        })
+        .collect()
+}
+
+
+/// The `.dat` and `.dtt` files of a CPK's TOC.
+pub fn list_cpk_dats(cpk_path: &str) -> io::Result<Vec<CpkEntry>> {
+    Ok(read_cpk_toc(cpk_path)?.into_iter().filter(CpkEntry::is_dat).collect())
+}
+
+
+/// Extracts the DAT `dat_name` (its path in the CPK, or just its file name if that is unique) out of a CPK into
+/// `extract_dir`, like `extract_dat_files_detailed` would extract it as a file. Only the CPK's tables and that DAT
+/// are read. CRILAYLA compressed files aren't supported.
+pub fn extract_dat_from_cpk(cpk_path: &str, dat_name: &str, extract_dir: &str, options: &ExtractOptions) -> io::Result<ExtractionResult> {
+    let mut file = File::open(cpk_path)?;
+    let toc = read_toc(&mut file)?;
+    let entry = match toc.iter().find(|entry| entry.path == dat_name) {
+        Some(entry) => entry,
+        None => {
+            let mut matches = toc.iter().filter(|entry| entry.file_name() == dat_name);
+            match (matches.next(), matches.next()) {
+                (Some(entry), None) => entry,
+                (Some(_), Some(_)) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("{} is in several directories of {}, pass its path", dat_name, cpk_path),
+                    ));
+                }
+                (None, _) => return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} has no file {}", cpk_path, dat_name))),
+            }
+        }
+    };
+    if entry.extract_size != entry.size {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{} is CRILAYLA compressed in {}, which isn't supported", entry.path, cpk_path),
+        ));
+    }
+
+    // The TOC sizes come from the file, so they are checked before allocating the DAT
+    let file_len = file.metadata()?.len();
+    if entry.offset.checked_add(u64::from(entry.size)).is_none_or(|end| end > file_len) {
+        return Err(invalid_cpk(format!("{} lies outside of {}, which is {} bytes long", entry.path, cpk_path, file_len)));
+    }
+    let mut data = vec![0; entry.size as usize];
+    file.seek(SeekFrom::Start(entry.offset))?;
+    file.read_exact(&mut data)?;
+
+    let file_names_sorted = if data.is_empty() {
+        Vec::new()
+    } else {
+        let (bytes, zlib_wrapped) = unwrap_zlib_dat(ByteDataWrapper { data, position: 0 })?;
+        let listing = parse_dat_listing(bytes, zlib_wrapped, options)?;
+        let mut sink = FsSink::new(extract_dir, options.resume)
+            .with_buffer_size(options.buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE))
+            .with_retries(options.max_retries, options.backoff_ms);
+        extract_listing_to_sink(&listing, entry.file_name(), &mut sink, options)?
+    };
+    extract_dir_result(file_names_sorted, extract_dir, options)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+    use std::fs;
+
+    /// A cell of `utf_packet`, stored as a u32, a u64 or a string.
+    #[derive(Clone)]
+    enum Cell {
+        U32(u32),
+        U64(u64),
+        Str(String),
+    }
+
+    impl Cell {
+        fn column_type(&self) -> u8 {
+            match self {
+                Cell::U32(_) => 0x4,
+                Cell::U64(_) => 0x6,
+                Cell::Str(_) => 0xA,
+            }
+        }
+    }
+
+    /// How `utf_packet` stores a column.
+    enum Column {
+        PerRow(Vec<Cell>),
+        Constant(Cell),
+        Zero,
+    }
+
+    /// A `@UTF` packet holding `row_count` rows of `columns`.
+    fn utf_packet(columns: &[(&str, Column)], row_count: usize) -> Vec<u8> {
+        let mut strings = b"<NULL>\0".to_vec();
+        let mut add_string = |value: &str| {
+            let offset = strings.len() as u32;
+            strings.extend_from_slice(value.as_bytes());
+            strings.push(0);
+            offset
+        };
+        let table_name = add_string("Table");
+        let mut encode = |cell: &Cell, out: &mut Vec<u8>| match cell {
+            Cell::U32(value) => out.extend_from_slice(&value.to_be_bytes()),
+            Cell::U64(value) => out.extend_from_slice(&value.to_be_bytes()),
+            Cell::Str(value) => out.extend_from_slice(&add_string(value).to_be_bytes()),
+        };
+
+        let mut column_bytes = Vec::new();
+        for (name, column) in columns {
+            let (storage, column_type) = match column {
+                Column::PerRow(cells) => (STORAGE_PER_ROW, cells[0].column_type()),
+                Column::Constant(cell) => (STORAGE_CONSTANT, cell.column_type()),
+                // A zero column's type doesn't matter
+                Column::Zero => (STORAGE_ZERO, 0x4),
+            };
+            column_bytes.push(storage | column_type);
+            encode(&Cell::Str(name.to_string()), &mut column_bytes);
+            if let Column::Constant(cell) = column {
+                encode(cell, &mut column_bytes);
+            }
+        }
+        let mut row_bytes = Vec::new();
+        for row in 0..row_count {
+            for (_, column) in columns {
+                if let Column::PerRow(cells) = column {
+                    encode(&cells[row], &mut row_bytes);
+                }
+            }
+        }
+
+        let rows_offset = 24 + column_bytes.len();
+        let strings_offset = rows_offset + row_bytes.len();
+        let mut table = Vec::new();
+        table.extend_from_slice(&1u16.to_be_bytes());
+        table.extend_from_slice(&(rows_offset as u16).to_be_bytes());
+        table.extend_from_slice(&(strings_offset as u32).to_be_bytes());
+        table.extend_from_slice(&((strings_offset + strings.len()) as u32).to_be_bytes());
+        table.extend_from_slice(&table_name.to_be_bytes());
+        table.extend_from_slice(&(columns.len() as u16).to_be_bytes());
+        table.extend_from_slice(&((row_bytes.len() / row_count.max(1)) as u16).to_be_bytes());
+        table.extend_from_slice(&(row_count as u32).to_be_bytes());
+        table.extend_from_slice(&column_bytes);
+        table.extend_from_slice(&row_bytes);
+        table.extend_from_slice(&strings);
+
+        let mut packet = b"@UTF".to_vec();
+        packet.extend_from_slice(&(table.len() as u32).to_be_bytes());
+        packet.extend_from_slice(&table);
+        packet
+    }
+
+    /// A chunk of `magic`, 4 unknown bytes, the packet's u64 size and the packet.
+    fn chunk(magic: &[u8; 4], packet: &[u8]) -> Vec<u8> {
+        let mut chunk = magic.to_vec();
+        chunk.extend_from_slice(&[0xFF; 4]);
+        chunk.extend_from_slice(&(packet.len() as u64).to_le_bytes());
+        chunk.extend_from_slice(packet);
+        chunk
+    }
+
+    /// One file of `cpk_bytes`, stored as `extract_size` bytes if that is set, to pass for a compressed one.
+    struct TestCpkFile<'a> {
+        path: &'a str,
+        data: &'a [u8],
+        /// `FileSize` in the TOC, `None` for the length of `data`.
+        size: Option<u64>,
+        extract_size: Option<u32>,
+    }
+
+    impl<'a> TestCpkFile<'a> {
+        fn new(path: &'a str, data: &'a [u8]) -> Self {
+            Self { path, data, size: None, extract_size: None }
+        }
+    }
+
+    const TEST_TOC_OFFSET: u64 = 0x800;
+    const TEST_CONTENT_OFFSET: u64 = 0x1000;
+
+    /// A CPK with its header at 0, the TOC at 0x800 and `files` from 0x1000 on, 0x800 aligned.
+    /// `encrypt` scrambles both tables like some CPKs do.
+    fn cpk_bytes(files: &[TestCpkFile], encrypt: bool) -> Vec<u8> {
+        let mut content = Vec::new();
+        let mut offsets = Vec::new();
+        for file in files {
+            offsets.push(TEST_CONTENT_OFFSET - TEST_TOC_OFFSET + content.len() as u64);
+            content.extend_from_slice(file.data);
+            content.resize(content.len().next_multiple_of(0x800), 0);
+        }
+        let (dir_names, file_names): (Vec<Cell>, Vec<Cell>) = files
+            .iter()
+            .map(|file| {
+                let (dir_name, file_name) = file.path.rsplit_once('/').unwrap_or(("", file.path));
+                (Cell::Str(dir_name.to_string()), Cell::Str(file_name.to_string()))
+            })
+            .unzip();
+        let toc = utf_packet(
+            &[
+                ("DirName", Column::PerRow(dir_names)),
+                ("FileName", Column::PerRow(file_names)),
+                ("FileSize", Column::PerRow(files.iter().map(|file| Cell::U64(file.size.unwrap_or(file.data.len() as u64))).collect())),
+                (
+                    "ExtractSize",
+                    Column::PerRow(files.iter().map(|file| Cell::U32(file.extract_size.unwrap_or(file.data.len() as u32))).collect()),
+                ),
+                ("FileOffset", Column::PerRow(offsets.into_iter().map(Cell::U64).collect())),
+                ("UserString", Column::Constant(Cell::Str("<NULL>".to_string()))),
+                ("CRC", Column::Zero),
+            ],
+            files.len(),
+        );
+        let header = utf_packet(
+            &[
+                ("ContentOffset", Column::PerRow(vec![Cell::U64(TEST_CONTENT_OFFSET)])),
+                ("TocOffset", Column::PerRow(vec![Cell::U64(TEST_TOC_OFFSET)])),
+                ("Files", Column::Constant(Cell::U32(files.len() as u32))),
+            ],
+            1,
+        );
+        let scramble = |packet: Vec<u8>| if encrypt { decrypt_utf(&packet) } else { packet };
+
+        let mut cpk = chunk(b"CPK ", &scramble(header));
+        cpk.resize(TEST_TOC_OFFSET as usize, 0);
+        cpk.extend_from_slice(&chunk(b"TOC ", &scramble(toc)));
+        assert!(cpk.len() <= TEST_CONTENT_OFFSET as usize);
+        cpk.resize(TEST_CONTENT_OFFSET as usize, 0);
+        cpk.extend_from_slice(&content);
+        cpk
+    }
+
+    /// A small CPK sample: two DATs sharing a file name, a DTT, a zlib-wrapped DAT, a compressed DAT and a text file.
+    fn sample_cpk(dir: &std::path::Path, encrypt: bool) -> String {
+        let a = dat_bytes(&[("a.bin", b"first"), ("b.bin", b"second")]);
+        let other_a = dat_bytes(&[("c.bin", b"other")]);
+        let dtt = dat_bytes(&[("tex.wtp", b"texture")]);
+        let wrapped = zlib(&dat_bytes(&[("z.bin", b"zlib wrapped")]));
+        let cpk = cpk_bytes(
+            &[
+                TestCpkFile::new("data/a.dat", &a),
+                TestCpkFile::new("other/a.dat", &other_a),
+                TestCpkFile::new("data/tex.dtt", &dtt),
+                TestCpkFile::new("wrapped.dat", &wrapped),
+                TestCpkFile { path: "data/packed.dat", data: b"CRILAYLA", size: None, extract_size: Some(0x100) },
+                TestCpkFile::new("readme.txt", b"not a dat"),
+            ],
+            encrypt,
+        );
+        write_file(dir, if encrypt { "encrypted.cpk" } else { "sample.cpk" }, &cpk)
+    }
+
+    #[test]
+    fn toc_lists_every_file_with_its_absolute_offset() {
+        let dir = temp_dir();
+        let cpk_path = sample_cpk(dir.path(), false);
+        let toc = read_cpk_toc(&cpk_path).unwrap();
+        let paths: Vec<&str> = toc.iter().map(|entry| entry.path.as_str()).collect();
+        assert_eq!(paths, ["data/a.dat", "other/a.dat", "data/tex.dtt", "wrapped.dat", "data/packed.dat", "readme.txt"]);
+        assert_eq!(toc[0].offset, TEST_CONTENT_OFFSET);
+        assert_eq!(toc[1].offset, TEST_CONTENT_OFFSET + 0x800);
+        assert_eq!(toc[4].size, 8);
+        assert_eq!(toc[4].extract_size, 0x100);
+
+        let cpk = fs::read(&cpk_path).unwrap();
+        let readme = &toc[5];
+        assert_eq!(&cpk[readme.offset as usize..][..readme.size as usize], b"not a dat");
+    }
+
+    #[test]
+    fn encrypted_tables_read_like_plain_ones() {
+        let dir = temp_dir();
+        assert_eq!(read_cpk_toc(&sample_cpk(dir.path(), true)).unwrap(), read_cpk_toc(&sample_cpk(dir.path(), false)).unwrap());
+    }
+
+    #[test]
+    fn only_dats_and_dtts_are_listed_as_dats() {
+        let dir = temp_dir();
+        let dats = list_cpk_dats(&sample_cpk(dir.path(), false)).unwrap();
+        let paths: Vec<&str> = dats.iter().map(|entry| entry.path.as_str()).collect();
+        assert_eq!(paths, ["data/a.dat", "other/a.dat", "data/tex.dtt", "wrapped.dat", "data/packed.dat"]);
+    }
+
+    #[test]
+    fn dat_is_extracted_by_its_path_or_unique_file_name() {
+        let dir = temp_dir();
+        let cpk_path = sample_cpk(dir.path(), false);
+        let options = ExtractOptions { quiet: true, ..Default::default() };
+
+        let out = dir.path().join("a");
+        let result = extract_dat_from_cpk(&cpk_path, "data/a.dat", &path_str(&out), &options).unwrap();
+        assert_eq!(result.files.len(), 2);
+        assert_eq!(fs::read(out.join("a.bin")).unwrap(), b"first");
+        assert_eq!(fs::read(out.join("b.bin")).unwrap(), b"second");
+
+        let out = dir.path().join("other");
+        extract_dat_from_cpk(&cpk_path, "other/a.dat", &path_str(&out), &options).unwrap();
+        assert_eq!(fs::read(out.join("c.bin")).unwrap(), b"other");
+
+        let out = dir.path().join("tex");
+        extract_dat_from_cpk(&cpk_path, "tex.dtt", &path_str(&out), &options).unwrap();
+        assert_eq!(fs::read(out.join("tex.wtp")).unwrap(), b"texture");
+
+        let out = dir.path().join("wrapped");
+        extract_dat_from_cpk(&cpk_path, "wrapped.dat", &path_str(&out), &options).unwrap();
+        assert_eq!(fs::read(out.join("z.bin")).unwrap(), b"zlib wrapped");
+    }
+
+    #[test]
+    fn ambiguous_missing_and_compressed_dats_are_rejected() {
+        let dir = temp_dir();
+        let cpk_path = sample_cpk(dir.path(), false);
+        let options = ExtractOptions { quiet: true, ..Default::default() };
+        let out = path_str(&dir.path().join("out"));
+        let kind = |name: &str| extract_dat_from_cpk(&cpk_path, name, &out, &options).unwrap_err().kind();
+        assert_eq!(kind("a.dat"), io::ErrorKind::InvalidInput);
+        assert_eq!(kind("missing.dat"), io::ErrorKind::NotFound);
+        assert_eq!(kind("packed.dat"), io::ErrorKind::Unsupported);
+        assert!(!dir.path().join("out").exists());
+    }
+
+    #[test]
+    fn files_that_are_no_cpk_are_invalid() {
+        let dir = temp_dir();
+        let not_a_cpk = write_dat(dir.path(), "test.dat", &[("a.bin", b"abc")]);
+        assert_eq!(read_cpk_toc(&not_a_cpk).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let mut truncated = fs::read(sample_cpk(dir.path(), false)).unwrap();
+        truncated.truncate(TEST_TOC_OFFSET as usize + 64);
+        let truncated = write_file(dir.path(), "truncated.cpk", &truncated);
+        assert_eq!(read_cpk_toc(&truncated).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn toc_sizes_past_the_file_or_u32_are_invalid() {
+        let dir = temp_dir();
+        let dat = dat_bytes(&[("a.bin", b"first")]);
+        let past_the_end = TestCpkFile { path: "big.dat", data: &dat, size: Some(0x10_0000), extract_size: Some(0x10_0000) };
+        let cpk_path = write_file(dir.path(), "past_the_end.cpk", &cpk_bytes(&[past_the_end], false));
+        let options = ExtractOptions { quiet: true, ..Default::default() };
+        let e = extract_dat_from_cpk(&cpk_path, "big.dat", &path_str(&dir.path().join("out")), &options).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(e.to_string().contains("big.dat lies outside of"), "{}", e);
+
+        let too_large = TestCpkFile { path: "big.dat", data: &dat, size: Some(1 << 32), extract_size: None };
+        let cpk_path = write_file(dir.path(), "too_large.cpk", &cpk_bytes(&[too_large], false));
+        let e = read_cpk_toc(&cpk_path).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(e.to_string().contains("FileSize of 4294967296, which is too large"), "{}", e);
+    }
+}
@@ -0,0 +1,109 @@
+use crate::{read_dat_listing, DatListing, ExtractOptions};
+use std::collections::HashMap;
+use std::io;
+
+
+/// Entry names that differ between two DATs, each list sorted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DatDiff {
+    /// Only in the second DAT.
+    pub added: Vec<String>,
+    /// Only in the first DAT.
+    pub removed: Vec<String>,
+    /// In both, with different contents.
+    pub changed: Vec<String>,
+}
+
+
+fn read_listing(dat_path: &str) -> io::Result<Option<DatListing>> {
+    let options = ExtractOptions {
+        quiet: true,
+        ..Default::default()
+    };
+    read_dat_listing(dat_path, &options)
+}
+
+
+fn entries_by_name(listing: &Option<DatListing>) -> io::Result<HashMap<&str, &[u8]>> {
+    let Some(listing) = listing else {
+        return Ok(HashMap::new());
+    };
+    listing
+        .entries
+        .iter()
+        .map(|entry| Ok((entry.name.as_str(), listing.bytes.slice(entry.offset as usize, entry.size as usize)?)))
+        .collect()
+}
+
+
+/// Compares the entries of the DATs `a` and `b` by name. Both DATs are in memory anyway, so entries
+/// present in both are compared by size and then byte for byte rather than through a content hash.
+pub async fn diff_dats(a: &str, b: &str) -> io::Result<DatDiff> {
+    let (a, b) = (a.to_string(), b.to_string());
+    tokio::task::spawn_blocking(move || {
+        let (listing_a, listing_b) = (read_listing(&a)?, read_listing(&b)?);
+        let (entries_a, entries_b) = (entries_by_name(&listing_a)?, entries_by_name(&listing_b)?);
+
+        let mut diff = DatDiff::default();
+        for (&name, &data_a) in &entries_a {
+            match entries_b.get(name) {
+                None => diff.removed.push(name.to_string()),
+                Some(&data_b) if data_a != data_b => diff.changed.push(name.to_string()),
+                Some(_) => {}
+            }
+        }
+        diff.added = entries_b
+            .keys()
+            .filter(|name| !entries_a.contains_key(*name))
+            .map(|name| name.to_string())
+            .collect();
+
+        diff.added.sort();
+        diff.removed.sort();
+        diff.changed.sort();
+        Ok(diff)
+    })
+    .await
+    .map_err(io::Error::other)?
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[tokio::test]
+    async fn diff_reports_added_removed_and_changed_entries() {
+        let dir = temp_dir();
+        let a = write_dat(dir.path(), "a.dat", &[("same.bin", b"same"), ("changed.bin", b"old"), ("removed.bin", b"r")]);
+        let b = write_dat(dir.path(), "b.dat", &[("changed.bin", b"new"), ("same.bin", b"same"), ("added.bin", b"a")]);
+        let diff = diff_dats(&a, &b).await.unwrap();
+        assert_eq!(
+            diff,
+            DatDiff {
+                added: vec!["added.bin".to_string()],
+                removed: vec!["removed.bin".to_string()],
+                changed: vec!["changed.bin".to_string()],
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn same_size_content_change_is_detected() {
+        let dir = temp_dir();
+        let a = write_dat(dir.path(), "a.dat", &[("a.bin", b"abcd")]);
+        let b = write_dat(dir.path(), "b.dat", &[("a.bin", b"abce")]);
+        assert_eq!(diff_dats(&a, &b).await.unwrap().changed, ["a.bin"]);
+        assert_eq!(diff_dats(&a, &a).await.unwrap(), DatDiff::default());
+    }
+
+    #[tokio::test]
+    async fn empty_dat_diffs_as_no_entries() {
+        let dir = temp_dir();
+        let empty = write_file(dir.path(), "empty.dat", b"");
+        let b = write_dat(dir.path(), "b.dat", &[("b.bin", b"b"), ("a.bin", b"a")]);
+        assert_eq!(diff_dats(&empty, &b).await.unwrap().added, ["a.bin", "b.bin"]);
+        assert_eq!(diff_dats(&b, &empty).await.unwrap().removed, ["a.bin", "b.bin"]);
+    }
+}
@@ -0,0 +1,769 @@
+use crate::{create_extract_dir, extract_dat_files_with_handler, from_hex, path_error, zlib_compress};
+use flate2::read::GzDecoder;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+
+/// What `merge_dats` does when several inputs contain an entry with the same name.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Keep the entry of the first input that has it.
+    #[default]
+    KeepFirst,
+    /// Keep the entry of the last input that has it, at the position of the first one.
+    KeepLast,
+    /// Fail with `io::ErrorKind::AlreadyExists`.
+    Error,
+}
+
+
+/// Options for `repack_dat_files`.
+#[derive(Debug, Clone)]
+pub struct DatRepackOptions {
+    /// Rebuild the DAT from the `layout` an extraction with `ExtractOptions::capture_padding` recorded: the
+    /// original header and tables, with only the offsets and sizes of the entries updated, and the original
+    /// padding bytes after every entry whose size didn't change. Unchanged files give a byte-exact DAT.
+    pub exact: bool,
+    /// Build the hash map table from the entry names. If `false` it is copied from `reference_dat` instead.
+    pub regenerate_hashmap: bool,
+    /// Build the extensions table from the entry names. If `false` it is copied from `reference_dat` instead.
+    pub regenerate_extensions: bool,
+    /// The DAT the preserved tables are copied from, usually the one the directory was extracted from. It has
+    /// to contain the same entry names in the same order, i.e. only file contents may have changed.
+    pub reference_dat: Option<String>,
+}
+
+impl Default for DatRepackOptions {
+    fn default() -> Self {
+        Self {
+            exact: false,
+            regenerate_hashmap: true,
+            regenerate_extensions: true,
+            reference_dat: None,
+        }
+    }
+}
+
+
+/// Tables `build_dat_with_tables` copies verbatim instead of building them.
+#[derive(Debug, Clone, Default)]
+struct PreservedTables {
+    hash_map: Option<Vec<u8>>,
+    extensions: Option<Vec<u8>>,
+}
+
+
+/// Size of the header `build_dat` writes, the fields `DatHeader::new` reads padded to 32 bytes.
+const DAT_HEADER_SIZE: usize = 32;
+const ENTRY_ALIGNMENT: usize = 16;
+
+
+fn too_large_error() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "DAT contents are larger than its 32 bit offsets can address")
+}
+
+
+fn to_u32(value: usize) -> io::Result<u32> {
+    u32::try_from(value).map_err(|_| too_large_error())
+}
+
+
+fn align(value: usize, alignment: usize) -> usize {
+    value.div_ceil(alignment) * alignment
+}
+
+
+/// The hash the game looks DAT entries up by, as listed in the hash map table: the CRC-32 of the lowercased
+/// name with the top bit cleared. Unlike YAX tag hashes it ignores case.
+pub fn dat_name_hash(name: &str) -> u32 {
+    crc32fast::hash(name.to_lowercase().as_bytes()) & 0x7FFF_FFFF
+}
+
+
+/// Builds the hash map table: a 16 byte header (pre hash shift and the offsets of the three arrays),
+/// then an i16 first-entry index per bucket, the sorted name hashes and the u16 entry index of each hash.
+fn build_hash_map(names: &[&str]) -> io::Result<Vec<u8>> {
+    let pre_hash_shift = (0..31).find(|shift| 1usize << shift >= names.len()).map_or(0, |shift| 31 - shift);
+    let bucket_count = 1usize << (31 - pre_hash_shift);
+
+    let mut hashes: Vec<(u32, u16)> = Vec::with_capacity(names.len());
+    for (index, name) in names.iter().enumerate() {
+        let index = u16::try_from(index)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "DATs hold at most 65536 entries"))?;
+        hashes.push((dat_name_hash(name), index));
+    }
+    hashes.sort_by_key(|&(hash, _)| hash >> pre_hash_shift);
+
+    let mut bucket_offsets = vec![-1i16; bucket_count];
+    for (position, &(hash, _)) in hashes.iter().enumerate() {
+        let bucket = &mut bucket_offsets[(hash >> pre_hash_shift) as usize];
+        if *bucket == -1 {
+            *bucket = position as i16;
+        }
+    }
+
+    let buckets_offset = 16;
+    let hashes_offset = buckets_offset + bucket_count * 2;
+    let indices_offset = hashes_offset + hashes.len() * 4;
+
+    let mut table = Vec::with_capacity(indices_offset + hashes.len() * 2);
+    for value in [pre_hash_shift, buckets_offset, hashes_offset, indices_offset] {
+        table.extend_from_slice(&to_u32(value)?.to_le_bytes());
+    }
+    for bucket_offset in bucket_offsets {
+        table.extend_from_slice(&bucket_offset.to_le_bytes());
+    }
+    for &(hash, _) in &hashes {
+        table.extend_from_slice(&hash.to_le_bytes());
+    }
+    for &(_, index) in &hashes {
+        table.extend_from_slice(&index.to_le_bytes());
+    }
+    Ok(table)
+}
+
+
+/// Builds a DAT from `(name, data)` entries in the layout the game uses: header, offsets, extensions,
+/// fixed width names, sizes and hash map tables, then the entries each aligned to 16 bytes.
+pub(crate) fn build_dat(entries: &[(String, Vec<u8>)]) -> io::Result<Vec<u8>> {
+    build_dat_with_tables(entries, &PreservedTables::default())
+}
+
+
+/// `build_dat`, with the tables in `preserved` copied instead of built from the entry names.
+fn build_dat_with_tables(entries: &[(String, Vec<u8>)], preserved: &PreservedTables) -> io::Result<Vec<u8>> {
+    let names: Vec<&str> = entries.iter().map(|(name, _)| name.as_str()).collect();
+    let name_length = names.iter().map(|name| name.len()).max().unwrap_or(0) + 1;
+
+    let offsets_offset = DAT_HEADER_SIZE;
+    let extensions_offset = offsets_offset + entries.len() * 4;
+    let names_offset = extensions_offset + entries.len() * 4;
+    let sizes_offset = align(names_offset + 4 + entries.len() * name_length, 4);
+    let hash_map_offset = sizes_offset + entries.len() * 4;
+    let hash_map = match &preserved.hash_map {
+        Some(hash_map) => hash_map.clone(),
+        None => build_hash_map(&names)?,
+    };
+    let data_offset = align(hash_map_offset + hash_map.len(), ENTRY_ALIGNMENT);
+
+    let mut dat = vec![0; data_offset];
+    dat[..4].copy_from_slice(b"DAT\0");
+    let header_fields = [entries.len(), offsets_offset, extensions_offset, names_offset, sizes_offset, hash_map_offset];
+    for (index, value) in header_fields.into_iter().enumerate() {
+        dat[4 + index * 4..8 + index * 4].copy_from_slice(&to_u32(value)?.to_le_bytes());
+    }
+    dat[names_offset..names_offset + 4].copy_from_slice(&to_u32(name_length)?.to_le_bytes());
+    dat[hash_map_offset..hash_map_offset + hash_map.len()].copy_from_slice(&hash_map);
+
+    for (index, (name, data)) in entries.iter().enumerate() {
+        let entry_offset = align(dat.len(), ENTRY_ALIGNMENT);
+        dat.resize(entry_offset, 0);
+        dat.extend_from_slice(data);
+
+        let offset_position = offsets_offset + index * 4;
+        dat[offset_position..offset_position + 4].copy_from_slice(&to_u32(entry_offset)?.to_le_bytes());
+        let size_position = sizes_offset + index * 4;
+        dat[size_position..size_position + 4].copy_from_slice(&to_u32(data.len())?.to_le_bytes());
+
+        // Extensions are stored null-terminated in 4 bytes
+        let extension_position = extensions_offset + index * 4;
+        if let Some(extensions) = &preserved.extensions {
+            dat[extension_position..extension_position + 4].copy_from_slice(&extensions[index * 4..index * 4 + 4]);
+        } else {
+            let extension = name.rsplit_once('.').map_or("", |(_, extension)| extension).as_bytes();
+            let extension = &extension[..extension.len().min(3)];
+            dat[extension_position..extension_position + extension.len()].copy_from_slice(extension);
+        }
+
+        let name_position = names_offset + 4 + index * name_length;
+        dat[name_position..name_position + name.len()].copy_from_slice(name.as_bytes());
+    }
+    to_u32(dat.len())?;
+
+    Ok(dat)
+}
+
+
+/// Path of the manifest `merge_dats` writes next to the merged DAT, listing the entries of every input.
+fn sources_manifest_path(merged_path: &str) -> String {
+    format!("{}.sources.json", merged_path)
+}
+
+
+/// Merges the entries of all `inputs` into one DAT at `out_path`, in input order. Entries with the same
+/// name (compared case-insensitively, like the game's hash lookup) are resolved by `on_conflict`.
+/// The file name and entry names of every input are recorded in `<out_path>.sources.json` for `split_dat`.
+pub async fn merge_dats(inputs: &[&str], out_path: &str, on_conflict: ConflictPolicy) -> io::Result<()> {
+    let inputs: Vec<String> = inputs.iter().map(|input| input.to_string()).collect();
+    let out_path = out_path.to_string();
+    tokio::task::spawn_blocking(move || {
+        let mut entries: Vec<(String, Vec<u8>)> = Vec::new();
+        let mut sources = Vec::new();
+        for input in &inputs {
+            let mut source_entries = Vec::new();
+            extract_dat_files_with_handler(input, |name, data| {
+                source_entries.push(name.to_string());
+                let existing = entries.iter().position(|(existing, _)| existing.eq_ignore_ascii_case(name));
+                match (existing, on_conflict) {
+                    (None, _) => entries.push((name.to_string(), data.to_vec())),
+                    (Some(_), ConflictPolicy::KeepFirst) => {}
+                    (Some(index), ConflictPolicy::KeepLast) => entries[index] = (name.to_string(), data.to_vec()),
+                    (Some(_), ConflictPolicy::Error) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::AlreadyExists,
+                            format!("{} contains {} which an earlier input already has", input, name),
+                        ))
+                    }
+                }
+                Ok(())
+            })?;
+            let file_name = Path::new(input).file_name().map_or_else(|| input.clone(), |name| name.to_string_lossy().into_owned());
+            sources.push(json!({ "dat": file_name, "entries": source_entries }));
+        }
+        fs::write(&out_path, build_dat(&entries)?)?;
+        let manifest = json!({ "version": 1, "sources": sources });
+        fs::write(sources_manifest_path(&out_path), serde_json::to_string_pretty(&manifest)?)
+    })
+    .await
+    .map_err(io::Error::other)?
+}
+
+
+/// Undoes `merge_dats`: rebuilds every input of the merge into `out_dir` from the merged DAT and its
+/// `.sources.json`, and returns the paths of the rebuilt DATs in input order. Inputs with the same file name
+/// get `_1`, `_2`, ... appended to their stem. An entry a conflict was resolved for is taken from the merged DAT
+/// for every input that had it, so only the input whose entry was kept gets it back unchanged.
+pub async fn split_dat(merged: &str, out_dir: &str) -> io::Result<Vec<String>> {
+    let (merged, out_dir) = (merged.to_string(), out_dir.to_string());
+    tokio::task::spawn_blocking(move || {
+        let manifest_path = sources_manifest_path(&merged);
+        let manifest: Value = match fs::read(&manifest_path) {
+            Ok(manifest) => serde_json::from_slice(&manifest)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{} has no {}, only DATs written by merge_dats can be split", merged, manifest_path),
+                ));
+            }
+            Err(e) => return Err(path_error(e, "read", Path::new(&manifest_path))),
+        };
+        let invalid_sources = |message: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", manifest_path, message));
+        let sources = manifest["sources"].as_array().ok_or_else(|| invalid_sources("no sources list"))?;
+
+        let mut merged_entries: Vec<(String, Vec<u8>)> = Vec::new();
+        extract_dat_files_with_handler(&merged, |name, data| {
+            merged_entries.push((name.to_string(), data.to_vec()));
+            Ok(())
+        })?;
+
+        create_extract_dir(Path::new(&out_dir))?;
+        let mut used_names = HashSet::new();
+        let mut written = Vec::with_capacity(sources.len());
+        for source in sources {
+            let dat_name = source["dat"].as_str().ok_or_else(|| invalid_sources("source without dat name"))?;
+            let names = source["entries"].as_array().ok_or_else(|| invalid_sources("source without entries"))?;
+            let entries = names
+                .iter()
+                .map(|name| {
+                    let name = name.as_str().ok_or_else(|| invalid_sources("non-string entry name"))?;
+                    let (_, data) = merged_entries.iter().find(|(merged_name, _)| merged_name.eq_ignore_ascii_case(name)).ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::InvalidData, format!("{} has no entry {} of {}", merged, name, dat_name))
+                    })?;
+                    Ok((name.to_string(), data.clone()))
+                })
+                .collect::<io::Result<Vec<_>>>()?;
+
+            let (stem, extension) = dat_name.rsplit_once('.').map_or((dat_name, String::new()), |(stem, extension)| (stem, format!(".{}", extension)));
+            let mut file_name = dat_name.to_string();
+            let mut suffix = 1;
+            while !used_names.insert(file_name.clone()) {
+                file_name = format!("{}_{}{}", stem, suffix, extension);
+                suffix += 1;
+            }
+            let path = Path::new(&out_dir).join(&file_name);
+            fs::write(&path, build_dat(&entries)?).map_err(|e| path_error(e, "write", &path))?;
+            written.push(path.to_string_lossy().into_owned());
+        }
+        Ok(written)
+    })
+    .await
+    .map_err(io::Error::other)?
+}
+
+
+fn read_u32(data: &[u8], offset: usize) -> Option<usize> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+}
+
+
+/// Reads the tables `options` doesn't regenerate from `options.reference_dat`, after checking that it has the
+/// entries of `entries` in the same order, which the copied hash map and extensions refer to by index.
+fn read_preserved_tables(entries: &[(String, Vec<u8>)], options: &DatRepackOptions) -> io::Result<PreservedTables> {
+    if options.regenerate_hashmap && options.regenerate_extensions {
+        return Ok(PreservedTables::default());
+    }
+    let reference_path = options.reference_dat.as_deref().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "preserving the hash map or extensions needs a reference_dat")
+    })?;
+    let invalid_reference = |message: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", reference_path, message));
+
+    let mut reference_names = Vec::new();
+    extract_dat_files_with_handler(reference_path, |name, _| {
+        reference_names.push(name.to_string());
+        Ok(())
+    })?;
+    if !reference_names.iter().map(String::as_str).eq(entries.iter().map(|(name, _)| name.as_str())) {
+        return Err(invalid_reference("the reference DAT doesn't have the same entries in the same order"));
+    }
+
+    let reference = fs::read(reference_path).map_err(|e| path_error(e, "read", Path::new(reference_path)))?;
+    let table_offset = |header_index: usize| read_u32(&reference, 4 + header_index * 4).filter(|&offset| offset != 0);
+    let table = |offset: Option<usize>, length: Option<usize>| -> io::Result<Vec<u8>> {
+        offset
+            .zip(length)
+            .and_then(|(offset, length)| reference.get(offset..offset.checked_add(length)?))
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| invalid_reference("the table to preserve is missing or truncated"))
+    };
+
+    let mut preserved = PreservedTables::default();
+    if !options.regenerate_extensions {
+        preserved.extensions = Some(table(table_offset(2), Some(entries.len() * 4))?);
+    }
+    if !options.regenerate_hashmap {
+        // The indices array comes last, one u16 per entry
+        let hash_map_offset = table_offset(5);
+        let indices_offset = hash_map_offset.and_then(|offset| read_u32(&reference, offset + 12));
+        preserved.hash_map = Some(table(hash_map_offset, indices_offset.map(|indices| indices + entries.len() * 2))?);
+    }
+    Ok(preserved)
+}
+
+
+fn invalid_manifest(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("dat_info.json: {}", message))
+}
+
+
+/// Entry names and the files they were extracted to, in DAT order if the manifest records it
+/// (`index_prefixed`, `normalized_case` or `entry_order`), otherwise in the order of `files`.
+pub(crate) fn manifest_entries(manifest: &Value) -> io::Result<Vec<(String, String)>> {
+    if let Some(renamed) = manifest["index_prefixed"].as_array().or(manifest["normalized_case"].as_array()) {
+        return renamed
+            .iter()
+            .map(|entry| match (entry["name"].as_str(), entry["extracted_as"].as_str()) {
+                (Some(name), Some(file)) => Ok((name.to_string(), file.to_string())),
+                _ => Err(invalid_manifest("renamed entry without name or extracted_as")),
+            })
+            .collect();
+    }
+    manifest["entry_order"]
+        .as_array()
+        .or(manifest["files"].as_array())
+        .ok_or_else(|| invalid_manifest("no files list"))?
+        .iter()
+        .map(|file| file.as_str().map(|file| (file.to_string(), file.to_string())).ok_or_else(|| invalid_manifest("non-string file name")))
+        .collect()
+}
+
+
+/// Reads the extracted `file`, which is `<file>.gz` if the manifest records the entries as `gzipped`.
+fn read_extracted_file(extract_dir: &Path, file: &str, gzipped: bool) -> io::Result<Vec<u8>> {
+    if !gzipped {
+        let file_path = extract_dir.join(file);
+        return fs::read(&file_path).map_err(|e| path_error(e, "read", &file_path));
+    }
+    let file_path = extract_dir.join(format!("{}.gz", file));
+    let mut data = Vec::new();
+    GzDecoder::new(fs::read(&file_path).map_err(|e| path_error(e, "read", &file_path))?.as_slice())
+        .read_to_end(&mut data)
+        .map_err(|e| path_error(e, "decompress", &file_path))?;
+    Ok(data)
+}
+
+
+/// The compression level of every entry the manifest lists as `decompressed_entries`, by the file it was
+/// extracted to. Entries without a level that reproduces the original stream get the default one.
+fn decompressed_levels(manifest: &Value) -> io::Result<HashMap<String, u32>> {
+    let Some(decompressed) = manifest["decompressed_entries"].as_array() else {
+        return Ok(HashMap::new());
+    };
+    let files: HashMap<String, String> = manifest_entries(manifest)?.into_iter().collect();
+    decompressed
+        .iter()
+        .map(|entry| {
+            let file = entry["name"]
+                .as_str()
+                .and_then(|name| files.get(name))
+                .ok_or_else(|| invalid_manifest("decompressed entry that isn't in the DAT"))?;
+            let level = entry["level"].as_u64().map_or(flate2::Compression::default().level(), |level| level.min(9) as u32);
+            Ok((file.clone(), level))
+        })
+        .collect()
+}
+
+
+/// Reads the extracted `file` like `read_extracted_file` and compresses it again if it's in `levels`.
+fn read_entry_data(extract_dir: &Path, file: &str, gzipped: bool, levels: &HashMap<String, u32>) -> io::Result<Vec<u8>> {
+    let data = read_extracted_file(extract_dir, file, gzipped)?;
+    match levels.get(file) {
+        Some(&level) => zlib_compress(&data, level),
+        None => Ok(data),
+    }
+}
+
+
+/// Replays a recorded `layout`: the original bytes before the first entry, then the entries in their
+/// original file order, each followed by its original padding if its size is unchanged and by zeros up to the
+/// next 16 byte boundary otherwise. The offsets and sizes tables are patched for the new positions.
+fn build_exact_dat(extract_dir: &Path, layout: &Value, gzipped: bool, levels: &HashMap<String, u32>) -> io::Result<Vec<u8>> {
+    let mut dat = layout["header"]
+        .as_str()
+        .and_then(from_hex)
+        .ok_or_else(|| invalid_manifest("layout has no valid header"))?;
+    let header_field = |dat: &[u8], index: usize| read_u32(dat, 4 + index * 4).ok_or_else(|| invalid_manifest("layout header is truncated"));
+    let (offsets_offset, sizes_offset) = (header_field(&dat, 1)?, header_field(&dat, 4)?);
+
+    let entries = layout["entries"].as_array().ok_or_else(|| invalid_manifest("layout has no entries"))?;
+    for entry in entries {
+        let (Some(index), Some(file), Some(size), Some(padding)) = (
+            entry["index"].as_u64(),
+            entry["file"].as_str(),
+            entry["size"].as_u64(),
+            entry["padding"].as_str().and_then(from_hex),
+        ) else {
+            return Err(invalid_manifest("layout entry without a valid index, file, size or padding"));
+        };
+        let data = read_entry_data(extract_dir, file, gzipped, levels)?;
+
+        let entry_offset = dat.len();
+        for (table_offset, value) in [(offsets_offset, entry_offset), (sizes_offset, data.len())] {
+            let position = table_offset + index as usize * 4;
+            dat.get_mut(position..position + 4)
+                .ok_or_else(|| invalid_manifest("layout header doesn't contain the offsets and sizes tables"))?
+                .copy_from_slice(&to_u32(value)?.to_le_bytes());
+        }
+        dat.extend_from_slice(&data);
+        if data.len() as u64 == size {
+            dat.extend_from_slice(&padding);
+        } else if !padding.is_empty() {
+            dat.resize(align(dat.len(), ENTRY_ALIGNMENT), 0);
+        }
+    }
+    to_u32(dat.len())?;
+
+    Ok(dat)
+}
+
+
+/// Repacks a directory written by DAT extraction into the DAT at `dat_path`, from the files its
+/// `dat_info.json` lists. Without `options.exact` the DAT is laid out like `build_dat` does, with
+/// `options.exact` the recorded `layout` is replayed. Entries listed as `decompressed_entries` are zlib
+/// compressed again.
+pub async fn repack_dat_files(extract_dir: &str, dat_path: &str, options: &DatRepackOptions) -> io::Result<()> {
+    let extract_dir = Path::new(extract_dir).to_path_buf();
+    let dat_path = dat_path.to_string();
+    let options = options.clone();
+    tokio::task::spawn_blocking(move || {
+        let manifest_path = extract_dir.join("dat_info.json");
+        let manifest = fs::read_to_string(&manifest_path).map_err(|e| path_error(e, "read", &manifest_path))?;
+        let manifest: Value = serde_json::from_str(&manifest)?;
+        let gzipped = manifest["gzipped"].as_bool().unwrap_or(false);
+        let levels = decompressed_levels(&manifest)?;
+
+        let dat = if options.exact {
+            if manifest["layout"].is_null() {
+                return Err(invalid_manifest("no layout recorded, extract with capture_padding for an exact repack"));
+            }
+            build_exact_dat(&extract_dir, &manifest["layout"], gzipped, &levels)?
+        } else {
+            let mut entries = Vec::new();
+            for (name, file) in manifest_entries(&manifest)? {
+                let data = read_entry_data(&extract_dir, &file, gzipped, &levels)?;
+                entries.push((name, data));
+            }
+            let mut dat = build_dat_with_tables(&entries, &read_preserved_tables(&entries, &options)?)?;
+            if let Some(reserved) = manifest["header"]["reserved"].as_u64() {
+                // The u32 after the header fields, recorded on extraction
+                dat[28..32].copy_from_slice(&to_u32(reserved as usize)?.to_le_bytes());
+            }
+            dat
+        };
+        fs::write(&dat_path, dat).map_err(|e| path_error(e, "write", Path::new(&dat_path)))
+    })
+    .await
+    .map_err(io::Error::other)?
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+    use crate::{extract_dat_files_with_options, ExtractOptions};
+
+    #[tokio::test]
+    async fn decompressed_entries_are_compressed_again_on_repack() {
+        let dir = temp_dir();
+        let dat = dat_bytes(&[("a.bin", &zlib(&b"compressed text ".repeat(20))), ("b.bin", b"raw")]);
+        let dat_path = write_file(dir.path(), "test.dat", &dat);
+        let extract_dir = path_str(&dir.path().join("out"));
+        let options = ExtractOptions { decompress_entries: true, ..Default::default() };
+        extract_dat_files_with_options(&dat_path, &extract_dir, &options).unwrap();
+
+        let repacked_path = path_str(&dir.path().join("repacked.dat"));
+        repack_dat_files(&extract_dir, &repacked_path, &DatRepackOptions::default()).await.unwrap();
+        assert_eq!(fs::read(&repacked_path).unwrap(), dat);
+    }
+
+    fn dat_entries(dat_path: &str) -> Vec<(String, Vec<u8>)> {
+        let mut entries = Vec::new();
+        extract_dat_files_with_handler(dat_path, |name, data| {
+            entries.push((name.to_string(), data.to_vec()));
+            Ok(())
+        })
+        .unwrap();
+        entries
+    }
+
+    /// Merges `a.dat` (`shared.bin`, `a.bin`) and `b.dat` (`SHARED.bin`, `b.bin`) with `on_conflict`.
+    async fn merge_overlapping(dir: &Path, on_conflict: ConflictPolicy) -> io::Result<String> {
+        let a = write_dat(dir, "a.dat", &[("shared.bin", b"from a"), ("a.bin", b"a")]);
+        let b = write_dat(dir, "b.dat", &[("SHARED.bin", b"from b"), ("b.bin", b"b")]);
+        let merged = path_str(&dir.join("merged.dat"));
+        merge_dats(&[&a, &b], &merged, on_conflict).await?;
+        Ok(merged)
+    }
+
+    fn entry(name: &str, data: &[u8]) -> (String, Vec<u8>) {
+        (name.to_string(), data.to_vec())
+    }
+
+    #[tokio::test]
+    async fn merge_keeps_the_first_of_overlapping_entries() {
+        let dir = temp_dir();
+        let merged = merge_overlapping(dir.path(), ConflictPolicy::KeepFirst).await.unwrap();
+        assert_eq!(dat_entries(&merged), [entry("shared.bin", b"from a"), entry("a.bin", b"a"), entry("b.bin", b"b")]);
+
+        let sources: Value = serde_json::from_slice(&fs::read(sources_manifest_path(&merged)).unwrap()).unwrap();
+        assert_eq!(sources["sources"][1], json!({ "dat": "b.dat", "entries": ["SHARED.bin", "b.bin"] }));
+    }
+
+    #[tokio::test]
+    async fn merge_keeps_the_last_of_overlapping_entries_at_the_first_position() {
+        let dir = temp_dir();
+        let merged = merge_overlapping(dir.path(), ConflictPolicy::KeepLast).await.unwrap();
+        assert_eq!(dat_entries(&merged), [entry("SHARED.bin", b"from b"), entry("a.bin", b"a"), entry("b.bin", b"b")]);
+    }
+
+    #[tokio::test]
+    async fn merge_conflict_with_error_policy_writes_nothing() {
+        let dir = temp_dir();
+        let e = merge_overlapping(dir.path(), ConflictPolicy::Error).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+        assert!(e.to_string().contains("SHARED.bin"), "{}", e);
+        assert!(!dir.path().join("merged.dat").exists());
+    }
+
+    /// `dat_bytes(entries)` with the padding after every entry filled with `byte` instead of zeros.
+    fn dat_with_padding_byte(entries: &[(&str, &[u8])], byte: u8) -> Vec<u8> {
+        let mut dat = dat_bytes(entries);
+        dat.resize(align(dat.len(), ENTRY_ALIGNMENT) + ENTRY_ALIGNMENT, 0);
+        let offsets_offset = read_u32(&dat, 8).unwrap();
+        let sizes_offset = read_u32(&dat, 20).unwrap();
+        let offsets: Vec<usize> = (0..entries.len()).map(|index| read_u32(&dat, offsets_offset + index * 4).unwrap()).collect();
+        let next_offsets: Vec<usize> = offsets.iter().skip(1).copied().chain([dat.len()]).collect();
+        for (index, (offset, next_offset)) in offsets.into_iter().zip(next_offsets).enumerate() {
+            let entry_end = offset + read_u32(&dat, sizes_offset + index * 4).unwrap();
+            dat[entry_end..next_offset].fill(byte);
+        }
+        dat
+    }
+
+    #[tokio::test]
+    async fn exact_repack_replays_non_zero_padding() {
+        let dir = temp_dir();
+        let dat = dat_with_padding_byte(&[("a.bin", b"abc"), ("b.txt", b"defgh"), ("c.bin", b"i")], 0xAB);
+        let dat_path = write_file(dir.path(), "test.dat", &dat);
+        let extract_dir = path_str(&dir.path().join("out"));
+        let options = ExtractOptions { capture_padding: true, ..Default::default() };
+        extract_dat_files_with_options(&dat_path, &extract_dir, &options).unwrap();
+
+        let repacked_path = path_str(&dir.path().join("repacked.dat"));
+        let exact = DatRepackOptions { exact: true, ..Default::default() };
+        repack_dat_files(&extract_dir, &repacked_path, &exact).await.unwrap();
+        assert_eq!(fs::read(&repacked_path).unwrap(), dat);
+
+        // A plain repack pads with zeros
+        repack_dat_files(&extract_dir, &repacked_path, &DatRepackOptions::default()).await.unwrap();
+        assert_ne!(fs::read(&repacked_path).unwrap(), dat);
+    }
+
+    #[tokio::test]
+    async fn exact_repack_zero_pads_changed_entries() {
+        let dir = temp_dir();
+        let dat = dat_with_padding_byte(&[("a.bin", b"abc"), ("b.bin", b"d")], 0xAB);
+        let dat_path = write_file(dir.path(), "test.dat", &dat);
+        let extract_dir = dir.path().join("out");
+        let options = ExtractOptions { capture_padding: true, ..Default::default() };
+        extract_dat_files_with_options(&dat_path, &path_str(&extract_dir), &options).unwrap();
+        fs::write(extract_dir.join("a.bin"), b"abcdef").unwrap();
+
+        let repacked_path = path_str(&dir.path().join("repacked.dat"));
+        let exact = DatRepackOptions { exact: true, ..Default::default() };
+        repack_dat_files(&path_str(&extract_dir), &repacked_path, &exact).await.unwrap();
+        assert_eq!(dat_entries(&repacked_path), [entry("a.bin", b"abcdef"), entry("b.bin", b"d")]);
+        let repacked = fs::read(&repacked_path).unwrap();
+        let offsets_offset = read_u32(&repacked, 8).unwrap();
+        let (a_offset, b_offset) = (read_u32(&repacked, offsets_offset).unwrap(), read_u32(&repacked, offsets_offset + 4).unwrap());
+        assert!(repacked[a_offset + 6..b_offset].iter().all(|&byte| byte == 0));
+        assert_eq!(b_offset % ENTRY_ALIGNMENT, 0);
+        // The unchanged last entry keeps its padding
+        assert_eq!(repacked.last(), Some(&0xAB));
+    }
+
+    /// The extensions and hash map tables of `dat`, up to the first entry.
+    fn dat_tables(dat: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let (extensions_offset, hash_map_offset) = (read_u32(dat, 12).unwrap(), read_u32(dat, 24).unwrap());
+        let first_entry = read_u32(dat, read_u32(dat, 8).unwrap()).unwrap();
+        (dat[extensions_offset..extensions_offset + 8].to_vec(), dat[hash_map_offset..first_entry].to_vec())
+    }
+
+    #[tokio::test]
+    async fn preserved_tables_are_copied_from_the_reference() {
+        let dir = temp_dir();
+        let mut dat = dat_bytes(&[("a.json", b"{}"), ("b.bin", b"b")]);
+        // A 4 character extension without terminator and a hash map that differs from a rebuilt one
+        let (extensions_offset, hash_map_offset) = (read_u32(&dat, 12).unwrap(), read_u32(&dat, 24).unwrap());
+        dat[extensions_offset..extensions_offset + 4].copy_from_slice(b"json");
+        dat[hash_map_offset + 16] ^= 0x55;
+        let dat_path = write_file(dir.path(), "test.dat", &dat);
+        let extract_dir = dir.path().join("out");
+        extract_dat_files_with_options(&dat_path, &path_str(&extract_dir), &ExtractOptions::default()).unwrap();
+        fs::write(extract_dir.join("b.bin"), b"changed").unwrap();
+
+        let repacked_path = path_str(&dir.path().join("repacked.dat"));
+        let options = DatRepackOptions {
+            regenerate_hashmap: false,
+            regenerate_extensions: false,
+            reference_dat: Some(dat_path.clone()),
+            ..Default::default()
+        };
+        repack_dat_files(&path_str(&extract_dir), &repacked_path, &options).await.unwrap();
+        let repacked = fs::read(&repacked_path).unwrap();
+        assert_eq!(dat_tables(&repacked), dat_tables(&dat));
+        assert_eq!(dat_entries(&repacked_path), [entry("a.json", b"{}"), entry("b.bin", b"changed")]);
+
+        repack_dat_files(&path_str(&extract_dir), &repacked_path, &DatRepackOptions::default()).await.unwrap();
+        let (extensions, hash_map) = dat_tables(&fs::read(&repacked_path).unwrap());
+        assert_eq!(&extensions[..4], b"jso\0");
+        assert_ne!(hash_map, dat_tables(&dat).1);
+    }
+
+    #[tokio::test]
+    async fn preserving_tables_needs_a_matching_reference() {
+        let dir = temp_dir();
+        let dat_path = write_dat(dir.path(), "test.dat", &[("a.bin", b"a"), ("b.bin", b"b")]);
+        let extract_dir = path_str(&dir.path().join("out"));
+        extract_dat_files_with_options(&dat_path, &extract_dir, &ExtractOptions::default()).unwrap();
+        let repacked_path = path_str(&dir.path().join("repacked.dat"));
+
+        let options = DatRepackOptions { regenerate_hashmap: false, ..Default::default() };
+        let error = repack_dat_files(&extract_dir, &repacked_path, &options).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+        let other_path = write_dat(dir.path(), "other.dat", &[("b.bin", b"b"), ("a.bin", b"a")]);
+        let options = DatRepackOptions { regenerate_hashmap: false, reference_dat: Some(other_path), ..Default::default() };
+        let error = repack_dat_files(&extract_dir, &repacked_path, &options).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("same entries in the same order"), "{}", error);
+    }
+
+    #[test]
+    fn dat_name_hash_is_the_crc32_of_the_lowercased_name_without_the_top_bit() {
+        // CRC-32 check values: "123456789" is 0xCBF43926, "abc" is 0x352441C2
+        assert_eq!(dat_name_hash("123456789"), 0x4BF4_3926);
+        assert_eq!(dat_name_hash("abc"), 0x3524_41C2);
+        assert_eq!(dat_name_hash("ABC"), dat_name_hash("abc"));
+        assert_eq!(dat_name_hash(""), 0);
+    }
+
+    #[test]
+    fn dat_name_hash_finds_the_entries_of_a_dat() {
+        let dir = temp_dir();
+        let names = ["core_hap.bin", "Quest.bxm", "ph1000.pak", "em0000.dtt"];
+        let entries: Vec<(&str, &[u8])> = names.iter().map(|name| (*name, name.as_bytes())).collect();
+        let dat_path = write_dat(dir.path(), "test.dat", &entries);
+        assert!(crate::verify_dat_hashmap(&dat_path).unwrap());
+        for name in names {
+            assert_eq!(crate::find_dat_entry_by_hash(&dat_path, dat_name_hash(name)).unwrap().as_deref(), Some(name));
+        }
+        assert_eq!(crate::find_dat_entry_by_hash(&dat_path, dat_name_hash("missing.bin")).unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn split_rebuilds_the_merged_dats() {
+        let dir = temp_dir();
+        let first = dat_bytes(&[("a.bin", b"first a"), ("b.wmb", b"first b")]);
+        let second = dat_bytes(&[("c.bin", b"second c")]);
+        let inputs = [write_file(dir.path(), "first.dat", &first), write_file(dir.path(), "second.dat", &second)];
+        let merged_path = path_str(&dir.path().join("merged.dat"));
+        merge_dats(&[&inputs[0], &inputs[1]], &merged_path, ConflictPolicy::Error).await.unwrap();
+
+        let split = split_dat(&merged_path, &path_str(&dir.path().join("split"))).await.unwrap();
+        assert_eq!(split, [path_str(&dir.path().join("split/first.dat")), path_str(&dir.path().join("split/second.dat"))]);
+        assert_eq!(fs::read(&split[0]).unwrap(), first);
+        assert_eq!(fs::read(&split[1]).unwrap(), second);
+    }
+
+    #[tokio::test]
+    async fn inputs_with_the_same_file_name_are_split_under_unique_names() {
+        let dir = temp_dir();
+        for sub in ["x", "y"] {
+            fs::create_dir(dir.path().join(sub)).unwrap();
+        }
+        let first = write_dat(&dir.path().join("x"), "common.dat", &[("a.bin", b"a")]);
+        let second = write_dat(&dir.path().join("y"), "common.dat", &[("b.bin", b"b")]);
+        let merged_path = path_str(&dir.path().join("merged.dat"));
+        merge_dats(&[&first, &second], &merged_path, ConflictPolicy::Error).await.unwrap();
+
+        let split = split_dat(&merged_path, &path_str(&dir.path().join("split"))).await.unwrap();
+        assert_eq!(split, [path_str(&dir.path().join("split/common.dat")), path_str(&dir.path().join("split/common_1.dat"))]);
+        assert_eq!(fs::read(&split[0]).unwrap(), fs::read(&first).unwrap());
+        assert_eq!(fs::read(&split[1]).unwrap(), fs::read(&second).unwrap());
+    }
+
+    #[tokio::test]
+    async fn conflicts_are_resolved_by_the_policy() {
+        let dir = temp_dir();
+        let first = write_dat(dir.path(), "first.dat", &[("a.bin", b"first")]);
+        let second = write_dat(dir.path(), "second.dat", &[("A.BIN", b"second")]);
+        let merged_path = path_str(&dir.path().join("merged.dat"));
+
+        let e = merge_dats(&[&first, &second], &merged_path, ConflictPolicy::Error).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+        merge_dats(&[&first, &second], &merged_path, ConflictPolicy::KeepFirst).await.unwrap();
+        assert_eq!(crate::read_dat_entry(&merged_path, "a.bin").unwrap().unwrap(), b"first");
+        merge_dats(&[&first, &second], &merged_path, ConflictPolicy::KeepLast).await.unwrap();
+        assert_eq!(crate::read_dat_entry(&merged_path, "A.BIN").unwrap().unwrap(), b"second");
+
+        // Both inputs get the kept entry back under their own name
+        let split = split_dat(&merged_path, &path_str(&dir.path().join("split"))).await.unwrap();
+        assert_eq!(crate::read_dat_entry(&split[0], "a.bin").unwrap().unwrap(), b"second");
+        assert_eq!(fs::read(&split[1]).unwrap(), fs::read(&second).unwrap());
+    }
+
+    #[tokio::test]
+    async fn dat_without_sources_cannot_be_split() {
+        let dir = temp_dir();
+        let dat_path = write_dat(dir.path(), "test.dat", &[("a.bin", b"abc")]);
+        let e = split_dat(&dat_path, &path_str(&dir.path().join("split"))).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+        assert!(e.to_string().contains("only DATs written by merge_dats can be split"), "{}", e);
+        assert!(!dir.path().join("split").exists());
+    }
+}
@@ -0,0 +1,138 @@
+use crate::{extract_dat_files_to_sink, ExtractOptions, OutputSink, DEFAULT_BUFFER_SIZE};
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+
+
+/// An `OutputSink` writing every entry into an already opened directory with `openat`, so no path is
+/// resolved outside of it. Names that could leave the directory (`..`, anything with a slash) are rejected,
+/// and symlinks in it aren't followed.
+pub struct DirHandleSink {
+    dir: File,
+    buffer_size: usize,
+}
+
+impl DirHandleSink {
+    pub fn new(dir: File) -> Self {
+        Self { dir, buffer_size: DEFAULT_BUFFER_SIZE }
+    }
+
+    pub fn with_buffer_size(mut self, buffer_size: usize) -> Self {
+        self.buffer_size = buffer_size.max(1);
+        self
+    }
+
+    fn create_file(&self, name: &str) -> io::Result<File> {
+        if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?} isn't a plain file name inside the extract directory", name),
+            ));
+        }
+        let c_name = CString::new(name)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("{:?} contains a null byte", name)))?;
+
+        let flags = libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+        let fd = unsafe { libc::openat(self.dir.as_raw_fd(), c_name.as_ptr(), flags, 0o644 as libc::c_uint) };
+        if fd < 0 {
+            let e = io::Error::last_os_error();
+            return Err(io::Error::new(e.kind(), format!("Failed to create {} in the extract directory: {}", name, e)));
+        }
+        Ok(unsafe { File::from_raw_fd(fd) })
+    }
+
+    fn write(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        let mut file = self.create_file(name)?;
+        data.chunks(self.buffer_size).try_for_each(|chunk| file.write_all(chunk))
+    }
+}
+
+impl OutputSink for DirHandleSink {
+    fn write_entry(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        self.write(name, data)
+    }
+
+    fn write_manifest(&mut self, json: &str) -> io::Result<()> {
+        self.write("dat_info.json", json.as_bytes())
+    }
+}
+
+
+/// Extracts a DAT into `root`, a directory opened by the host (e.g. `tokio::fs::File::open(dir)`), instead of
+/// a path, for hosts that hand out directory capabilities rather than filesystem access. Every write stays
+/// inside `root`. `options.extract_pak_files` and `options.resume` need paths and are ignored.
+/// Returns the names of the extracted files, see `extract_dat_files_to_sink`.
+pub async fn extract_dat_files_into_handle(
+    dat_path: &str,
+    root: tokio::fs::File,
+    options: &ExtractOptions,
+) -> io::Result<Vec<String>> {
+    let root = root.into_std().await;
+    if !root.metadata()?.is_dir() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "the extract root handle isn't a directory"));
+    }
+    let dat_path = dat_path.to_string();
+    let options = options.clone();
+    tokio::task::spawn_blocking(move || {
+        let mut sink = DirHandleSink::new(root).with_buffer_size(options.buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE));
+        extract_dat_files_to_sink(&dat_path, &mut sink, &options)
+    })
+    .await
+    .map_err(io::Error::other)?
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    #[tokio::test]
+    async fn entries_are_written_into_the_handle() {
+        let dir = temp_dir();
+        let dat_path = write_dat(dir.path(), "test.dat", &[("a.bin", b"abc"), ("b.txt", b"defg")]);
+        let root_dir = dir.path().join("root");
+        std::fs::create_dir(&root_dir).unwrap();
+        let root = tokio::fs::File::open(&root_dir).await.unwrap();
+
+        let names = extract_dat_files_into_handle(&dat_path, root, &ExtractOptions::default()).await.unwrap();
+        assert_eq!(names, ["a.bin", "b.txt"]);
+        assert_eq!(std::fs::read(root_dir.join("a.bin")).unwrap(), b"abc");
+        assert_eq!(std::fs::read(root_dir.join("b.txt")).unwrap(), b"defg");
+        assert!(root_dir.join("dat_info.json").exists());
+    }
+
+    #[tokio::test]
+    async fn a_file_handle_is_not_a_root() {
+        let dir = temp_dir();
+        let dat_path = write_dat(dir.path(), "test.dat", &[("a.bin", b"abc")]);
+        let root = tokio::fs::File::open(&dat_path).await.unwrap();
+        let error = extract_dat_files_into_handle(&dat_path, root, &ExtractOptions::default()).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn names_leaving_the_directory_are_rejected() {
+        let dir = temp_dir();
+        let root_dir = dir.path().join("root");
+        std::fs::create_dir(&root_dir).unwrap();
+        let mut sink = DirHandleSink::new(File::open(&root_dir).unwrap());
+        for name in ["..", ".", "", "../escape.bin", "sub/a.bin", "sub\\a.bin"] {
+            assert_eq!(sink.write_entry(name, b"x").unwrap_err().kind(), io::ErrorKind::InvalidInput, "{:?}", name);
+        }
+        assert!(!dir.path().join("escape.bin").exists());
+    }
+
+    #[test]
+    fn symlinks_in_the_directory_are_not_followed() {
+        let dir = temp_dir();
+        let root_dir = dir.path().join("root");
+        std::fs::create_dir(&root_dir).unwrap();
+        let outside = write_file(dir.path(), "outside.bin", b"original");
+        std::os::unix::fs::symlink(&outside, root_dir.join("a.bin")).unwrap();
+        let mut sink = DirHandleSink::new(File::open(&root_dir).unwrap());
+        assert!(sink.write_entry("a.bin", b"overwritten").is_err());
+        assert_eq!(std::fs::read(&outside).unwrap(), b"original");
+    }
+}
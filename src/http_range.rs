@@ -0,0 +1,255 @@
+use crate::{parse_dat_listing, required_table_len, ByteDataWrapper, DatListing, ExtractOptions, FsSink, OutputSink};
+use reqwest::blocking::Client;
+use reqwest::header::{CONTENT_RANGE, RANGE};
+use reqwest::StatusCode;
+use std::io::{self, Read};
+use std::path::Path;
+use std::time::Duration;
+
+/// How long connecting to the server may take.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long the server may keep a response, or the next part of its body, waiting.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+
+/// A failed request as `io::Error`, `TimedOut` if it ran into `CONNECT_TIMEOUT` or `READ_TIMEOUT`.
+fn request_error(e: reqwest::Error) -> io::Error {
+    let kind = if e.is_timeout() { io::ErrorKind::TimedOut } else { io::ErrorKind::Other };
+    io::Error::new(kind, e)
+}
+
+
+/// The first byte position of a `Content-Range: bytes first-last/length` header.
+fn content_range_start(value: &str) -> Option<u64> {
+    value.strip_prefix("bytes ")?.split_once('-')?.0.trim().parse().ok()
+}
+
+
+/// Fetches the `length` bytes at `offset` with a range request. Fewer bytes come back if the file ends
+/// earlier. A server that doesn't answer with the requested range is an error, so a server ignoring ranges
+/// doesn't make every read download the whole file.
+fn fetch_range(client: &Client, url: &str, offset: u64, length: u64) -> io::Result<Vec<u8>> {
+    if length == 0 {
+        return Ok(Vec::new());
+    }
+    let response = client
+        .get(url)
+        .header(RANGE, format!("bytes={}-{}", offset, offset + length - 1))
+        .send()
+        .map_err(request_error)?;
+    match response.status() {
+        StatusCode::PARTIAL_CONTENT => {}
+        // The range starts at or after the end of the file
+        StatusCode::RANGE_NOT_SATISFIABLE => return Ok(Vec::new()),
+        StatusCode::OK => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{} answered a range request with the whole file, it doesn't support ranges", url),
+            ));
+        }
+        status => return Err(io::Error::other(format!("HTTP status {} for {}", status, url))),
+    }
+    let start = response.headers().get(CONTENT_RANGE).and_then(|value| value.to_str().ok()).and_then(content_range_start);
+    if start != Some(offset) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} answered the range request for offset {} with a different range", url, offset),
+        ));
+    }
+
+    let mut body = Vec::new();
+    response.take(length).read_to_end(&mut body)?;
+    Ok(body)
+}
+
+
+/// A DAT on an HTTP server that supports range requests. Opening it fetches just the header and tables,
+/// every entry read fetches just that entry, so single entries of a large remote DAT can be extracted without
+/// downloading all of it. Zlib-wrapped DATs can't be read this way. Requests block, so async code should
+/// use it from `tokio::task::spawn_blocking`. Only `http://` URLs are supported, there is no TLS.
+pub struct HttpDat {
+    client: Client,
+    url: String,
+    listing: DatListing,
+}
+
+impl HttpDat {
+    pub fn open(url: &str, options: &ExtractOptions) -> io::Result<Self> {
+        let client = Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(READ_TIMEOUT)
+            .build()
+            .map_err(io::Error::other)?;
+        let url = reqwest::Url::parse(url)
+            .ok()
+            .filter(|parsed| parsed.scheme() == "http")
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{} isn't an http:// URL", url)))?
+            .to_string();
+        let mut bytes = ByteDataWrapper { data: fetch_range(&client, &url, 0, options.offset_width.header_size())?, position: 0 };
+        // The name length is only known once its table is there, so the tables may take a second round
+        loop {
+            let required = required_table_len(&mut bytes, options)?;
+            if bytes.data.len() >= required {
+                break;
+            }
+            let fetched = bytes.data.len();
+            let rest = fetch_range(&client, &url, fetched as u64, (required - fetched) as u64)?;
+            if rest.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("{} is {} bytes long but its tables need {} bytes", url, fetched, required),
+                ));
+            }
+            bytes.data.extend_from_slice(&rest);
+        }
+        bytes.set_position(0);
+        let listing = parse_dat_listing(bytes, false, options)?;
+        Ok(Self { client, url, listing })
+    }
+
+    /// Names and sizes of the entries, in DAT order.
+    pub fn entries(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.listing.entries.iter().map(|entry| (entry.name.as_str(), entry.size))
+    }
+
+    /// Fetches the entry `name`.
+    pub fn read_entry(&self, name: &str) -> io::Result<Vec<u8>> {
+        let entry = self.listing.entries.iter().find(|entry| entry.name == name).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("{} has no entry {}", self.url, name))
+        })?;
+        let data = fetch_range(&self.client, &self.url, entry.offset, entry.size)?;
+        if data.len() != entry.size as usize {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("DAT entry {} lies outside of the file", name)));
+        }
+        Ok(data)
+    }
+
+    /// Fetches the entries `names` (all if `None`) into `extract_dir` and returns their paths.
+    pub fn extract_entries(&self, extract_dir: &str, names: Option<&[&str]>) -> io::Result<Vec<String>> {
+        let mut sink = FsSink::new(extract_dir, false);
+        let mut paths = Vec::new();
+        for (name, _) in self.entries() {
+            if names.is_some_and(|names| !names.contains(&name)) {
+                continue;
+            }
+            sink.write_entry(name, &self.read_entry(name)?)?;
+            paths.push(Path::new(extract_dir).join(name).to_string_lossy().into_owned());
+        }
+        Ok(paths)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    /// The `(first, last)` byte ranges a `serve` server was asked for.
+    type RequestedRanges = Arc<Mutex<Vec<(u64, u64)>>>;
+
+    /// A local HTTP server for `data`. It answers range requests with 206, or with 200 and the whole file if
+    /// `ranges` is false. Returns its URL and the ranges it was asked for.
+    fn serve(data: Vec<u8>, ranges: bool) -> (String, RequestedRanges) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/test.dat", listener.local_addr().unwrap());
+        let requested = RequestedRanges::default();
+        let server_requested = requested.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut range = None;
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    let lowercase = line.to_ascii_lowercase();
+                    if let Some(value) = lowercase.strip_prefix("range: bytes=") {
+                        let (first, last) = value.trim().split_once('-').unwrap();
+                        range = Some((first.parse::<u64>().unwrap(), last.parse::<u64>().unwrap()));
+                    }
+                }
+                let len = data.len() as u64;
+                let (status, headers, body) = match range {
+                    Some((first, last)) if ranges => {
+                        server_requested.lock().unwrap().push((first, last));
+                        if first >= len {
+                            ("416 Range Not Satisfiable", format!("Content-Range: bytes */{}\r\n", len), &data[..0])
+                        } else {
+                            let last = last.min(len - 1);
+                            let headers = format!("Content-Range: bytes {}-{}/{}\r\n", first, last, len);
+                            ("206 Partial Content", headers, &data[first as usize..=last as usize])
+                        }
+                    }
+                    _ => ("200 OK", String::new(), &data[..]),
+                };
+                let head = format!(
+                    "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                    status, headers, body.len()
+                );
+                // The client may hang up once it has read what it asked for
+                let _ = stream.write_all(head.as_bytes()).and_then(|_| stream.write_all(body));
+            }
+        });
+        (url, requested)
+    }
+
+    #[test]
+    fn single_entries_are_fetched_by_range() {
+        let large = vec![7; 64 * 1024];
+        let dat = dat_bytes(&[("a.bin", b"abc"), ("large.bin", &large), ("c.txt", b"defg")]);
+        let (url, requested) = serve(dat.clone(), true);
+
+        let http_dat = HttpDat::open(&url, &ExtractOptions::default()).unwrap();
+        let entries: Vec<(&str, u64)> = http_dat.entries().collect();
+        assert_eq!(entries, [("a.bin", 3), ("large.bin", large.len() as u64), ("c.txt", 4)]);
+        assert_eq!(http_dat.read_entry("c.txt").unwrap(), b"defg");
+
+        // Only the tables and the one entry were fetched
+        let fetched: u64 = requested.lock().unwrap().iter().map(|(first, last)| last - first + 1).sum();
+        assert!(fetched < 1024, "fetched {} of {} bytes", fetched, dat.len());
+        assert_eq!(http_dat.read_entry("missing.bin").unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn chosen_entries_are_extracted() {
+        let dir = temp_dir();
+        let (url, _) = serve(dat_bytes(&[("a.bin", b"abc"), ("b.bin", b"def"), ("c.txt", b"ghij")]), true);
+        let http_dat = HttpDat::open(&url, &ExtractOptions::default()).unwrap();
+        let extract_dir = dir.path().join("out");
+        let paths = http_dat.extract_entries(&path_str(&extract_dir), Some(&["a.bin", "c.txt"])).unwrap();
+        assert_eq!(paths, [path_str(&extract_dir.join("a.bin")), path_str(&extract_dir.join("c.txt"))]);
+        assert_eq!(std::fs::read(extract_dir.join("c.txt")).unwrap(), b"ghij");
+        assert!(!extract_dir.join("b.bin").exists());
+    }
+
+    #[test]
+    fn server_without_range_support_is_unsupported() {
+        let (url, _) = serve(dat_bytes(&[("a.bin", b"abc")]), false);
+        let error = HttpDat::open(&url, &ExtractOptions::default()).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn truncated_remote_dat_is_unexpected_eof() {
+        let dat = dat_bytes(&[("a.bin", b"abc")]);
+        let (url, _) = serve(dat[..40].to_vec(), true);
+        let error = HttpDat::open(&url, &ExtractOptions::default()).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn only_http_urls_are_opened() {
+        for url in ["https://example.com/test.dat", "file:///test.dat", "not a url"] {
+            let error = HttpDat::open(url, &ExtractOptions::default()).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput, "{}", url);
+        }
+    }
+}
@@ -1,47 +1,263 @@
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
 
 pub mod hash_map;
 pub mod yax_to_xml_convert;
+pub mod xml_to_yax_convert;
 pub mod pak_extract;
+pub mod pak_repack;
+pub mod dat_repack;
+pub mod dat_diff;
+pub mod tar_writer;
+pub mod batch;
+pub mod verify;
+pub mod cpk;
+#[cfg(unix)]
+pub mod dir_handle;
+#[cfg(feature = "http")]
+pub mod http_range;
+#[cfg(test)]
+mod test_util;
 
-use pak_extract::extract_pak_files;
-use tokio::runtime::Runtime;
+use pak_extract::{extract_pak_files_with_options, pak_extracted_size, PakExtractOptions};
+use globset::GlobBuilder;
 
-use std::path::Path;
-use std::fs::File;
-use std::io::{self, Read};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
 use byteorder::{LittleEndian, ReadBytesExt};
 use serde_json::json;
-use tokio::fs;
-use tokio::io::AsyncWriteExt;
+use siphasher::sip128::{Hasher128, SipHasher13};
+use std::hash::Hasher;
+use flate2::read::ZlibDecoder;
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
 use std::ffi::{CStr, CString};
-use std::os::raw::{c_char, c_uint};
+use std::os::raw::{c_char, c_int, c_uint};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 const PAK_EXTRACT_SUBDIR: &str = "pakExtracted";
+const DAT_EXTRACT_SUBDIR: &str = "datExtracted";
+/// Default for `ExtractOptions::max_nesting_depth`.
+pub const DEFAULT_MAX_NESTING_DEPTH: usize = 8;
+/// Extensions of the file formats found in NieR DATs.
+pub const KNOWN_DAT_EXTENSIONS: &[&str] = &[
+    "bin", "bnk", "bxm", "col", "csv", "ctx", "eff", "est", "ftb", "lay", "ly2", "mot", "pak", "sae",
+    "scr", "sop", "syn", "uid", "uvd", "wem", "wmb", "wsp", "wta", "wtb", "wtp", "z",
+];
+/// Size of the fields `DatHeader::new` reads.
+const DAT_HEADER_SIZE: u64 = 28;
+/// Largest DAT a zlib-wrapped file is inflated to. Game DAT offsets are u32, so no game DAT is larger, and a
+/// decompression bomb stops here instead of exhausting memory.
+const MAX_INFLATED_DAT_SIZE: u64 = u32::MAX as u64;
+/// Default for `ExtractOptions::buffer_size`.
+pub const DEFAULT_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Width of the table offsets in the header and of the entries of the offsets and sizes tables.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OffsetWidth {
+    /// u32, what the game uses.
+    #[default]
+    U32,
+    /// u64, for modded DATs larger than 4 GB. The magic, file count, name and extension tables and the hash
+    /// map table stay as in the 32 bit layout. Both layouts start alike, so this can't be detected and
+    /// has to be given.
+    U64,
+}
+
+impl OffsetWidth {
+    /// Size of the fields `DatHeader::new` reads.
+    fn header_size(self) -> u64 {
+        match self {
+            OffsetWidth::U32 => DAT_HEADER_SIZE,
+            OffsetWidth::U64 => 48,
+        }
+    }
+
+    fn bytes(self) -> usize {
+        match self {
+            OffsetWidth::U32 => 4,
+            OffsetWidth::U64 => 8,
+        }
+    }
+}
 
 struct DatHeader {
     id: String,                      
     file_number: u32,                
-    file_offsets_offset: u32,       
-    file_extensions_offset: u32,   
-    file_names_offset: u32,         
-    file_sizes_offset: u32,       
-    hash_map_offset: u32,           
+    offset_width: OffsetWidth,
+    file_offsets_offset: u64,       
+    file_extensions_offset: u64,   
+    file_names_offset: u64,         
+    file_sizes_offset: u64,       
+    hash_map_offset: u64,           
 }
 
 impl DatHeader {
 
 
-    fn new(bytes: &mut ByteDataWrapper) -> io::Result<Self> {
+    fn new(bytes: &mut ByteDataWrapper, offset_width: OffsetWidth) -> io::Result<Self> {
         Ok(Self {
             id: bytes.read_string(4)?,                   
             file_number: bytes.read_u32()?,               
-            file_offsets_offset: bytes.read_u32()?,        
-            file_extensions_offset: bytes.read_u32()?,      
-            file_names_offset: bytes.read_u32()?,           
-            file_sizes_offset: bytes.read_u32()?,         
-            hash_map_offset: bytes.read_u32()?,           
+            offset_width,
+            file_offsets_offset: bytes.read_offset(offset_width)?,        
+            file_extensions_offset: bytes.read_offset(offset_width)?,      
+            file_names_offset: bytes.read_offset(offset_width)?,           
+            file_sizes_offset: bytes.read_offset(offset_width)?,         
+            hash_map_offset: bytes.read_offset(offset_width)?,           
         })
     }
+
+
+    /// Every entry takes at least 9 bytes of tables (offset, size and a name byte), so a corrupt `file_number`
+    /// is caught here instead of sizing the table reads.
+    fn check_file_number(&self, file_len: usize) -> io::Result<()> {
+        if u64::from(self.file_number) * 9 > file_len as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("DAT header claims {} entries, more than fit in its {} bytes", self.file_number, file_len),
+            ));
+        }
+        Ok(())
+    }
+
+
+    /// The u32 after the header fields, present when no table starts right after them (game DATs place their
+    /// first table at 32). The game's tools write 0 there and no DAT is known to use it as flags, but it is
+    /// kept in `dat_info.json` so a repack can write the same value.
+    fn reserved_field(&self, bytes: &ByteDataWrapper) -> Option<u32> {
+        let tables = [
+            self.file_offsets_offset,
+            self.file_extensions_offset,
+            self.file_names_offset,
+            self.file_sizes_offset,
+            self.hash_map_offset,
+        ];
+        let first_table = tables.into_iter().filter(|&offset| offset != 0).min()?;
+        let header_size = self.offset_width.header_size();
+        if first_table < header_size + 4 {
+            return None;
+        }
+        let field = bytes.slice(header_size as usize, 4).ok()?;
+        Some(u32::from_le_bytes(field.try_into().unwrap()))
+    }
+
+
+    /// Tables are read at their absolute offsets, so reserved space after the header fields (a larger header)
+    /// is skipped on its own. A table starting inside the header fields, though, means a corrupt or foreign header.
+    /// An extensions offset of 0 marks a missing table and is allowed.
+    fn check_table_offsets(&self) -> io::Result<()> {
+        if self.file_number == 0 {
+            return Ok(());
+        }
+        let tables = [
+            ("offsets", self.file_offsets_offset),
+            ("names", self.file_names_offset),
+            ("sizes", self.file_sizes_offset),
+            ("extensions", self.file_extensions_offset),
+        ];
+        let header_size = self.offset_width.header_size();
+        for (table, offset) in tables {
+            if offset < header_size && !(table == "extensions" && offset == 0) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("DAT {} table offset {} lies inside the {} byte header", table, offset, header_size),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+
+/// How the names are stored in the DAT name table.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NameTableFormat {
+    /// A u32 name length followed by every name null-padded to that length. This is what the game uses.
+    #[default]
+    FixedWidth,
+    /// Names stored back to back, each terminated by a single null byte.
+    NullTerminated,
+}
+
+
+/// Options for `extract_dat_files_with_options`.
+#[derive(Debug, Clone, Default)]
+pub struct ExtractOptions {
+    /// Also extract the `.pak` files inside the DAT and convert their YAX files to XML. PAK entries are always
+    /// treated as YAX, never as further DATs or PAKs.
+    pub extract_pak_files: bool,
+    pub name_table_format: NameTableFormat,
+    /// Skip entries whose output file already exists with the expected size, e.g. to continue an interrupted extraction.
+    pub resume: bool,
+    /// Don't print warnings (empty DAT, extension mismatches, PAK entries that don't decompress). They go to
+    /// stderr, apart from the output of the host the library is loaded into.
+    pub quiet: bool,
+    /// Fail on names that aren't valid UTF-8 instead of replacing the invalid bytes with U+FFFD.
+    pub strict_utf8: bool,
+    /// Write entries that are a complete zlib stream decompressed, listing them under `decompressed_entries` in
+    /// `dat_info.json` with the compression level that reproduces them, so `repack_dat_files` compresses them again.
+    pub decompress_entries: bool,
+    /// For entries with an extension not in `KNOWN_DAT_EXTENSIONS`, also write a `<name>.hex` dump of their first this many bytes.
+    pub dump_unknown: Option<usize>,
+    /// Prefix every output file with its zero-padded index in the DAT (`000_name.wmb`), which keeps the original
+    /// order and makes colliding names unique. The mapping is recorded as `index_prefixed` in `dat_info.json`.
+    pub index_prefix: bool,
+    /// Write every entry under its lowercased name, so names differing only in case don't overwrite each other on
+    /// case-insensitive file systems. Names that still collide get `_1`, `_2`, ... appended to their stem.
+    /// The original names are recorded as `normalized_case` in `dat_info.json` and used by `repack_dat_files`.
+    pub normalize_case: bool,
+    /// Size of the individual writes extracted files are written with, `DEFAULT_BUFFER_SIZE` if `None`.
+    /// Larger writes can help on spinning disks.
+    pub buffer_size: Option<usize>,
+    /// How often to retry reading the DAT or writing a file after a transient error (`Interrupted`, `TimedOut`,
+    /// `WouldBlock`), e.g. on a flaky network drive. Other errors, like `NotFound`, fail immediately.
+    pub max_retries: u32,
+    /// Wait before the first retry, doubled for every further one.
+    pub backoff_ms: u64,
+    /// Treat the DAT as a file another process is still writing: while it's shorter than its tables and entries
+    /// need, re-read it until it is complete or `tail_timeout_ms` passed.
+    pub tail: bool,
+    pub tail_timeout_ms: u64,
+    /// Also write `dat_filelist.txt`, the extracted file names one per line in DAT order, for tools that
+    /// don't read `dat_info.json`.
+    pub write_filelist: bool,
+    /// Record the bytes of the DAT outside its entries (header, tables and the padding after every entry) as
+    /// `layout` in `dat_info.json`, which `repack_dat_files` with `exact` replays for a byte-exact DAT.
+    /// Off by default since it makes the manifest as large as the tables.
+    pub capture_padding: bool,
+    /// Write every entry gzip compressed as `<name>.gz`, recorded as `gzipped` in `dat_info.json` so
+    /// `repack_dat_files` decompresses them again. PAKs aren't extracted from gzipped entries.
+    pub gzip_entries: bool,
+    /// Width of the offsets in the header and tables, `OffsetWidth::U32` for the game's DATs.
+    pub offset_width: OffsetWidth,
+    /// Record where the extraction came from as `provenance` in `dat_info.json`: this crate's version, the size
+    /// and CRC-32 of the DAT and when it was extracted, see `verify::verify_provenance`. Off by default since
+    /// the timestamp makes the manifest differ between otherwise identical extractions.
+    pub provenance: bool,
+    /// Also extract the entries that are DATs themselves (plain or zlib-wrapped) into `datExtracted/<name>`,
+    /// and the DATs inside those in turn, with the same options. Since untrusted mods can nest DATs without
+    /// end, this fails with `InvalidData` past `max_nesting_depth` levels and on a DAT nested inside a DAT
+    /// with the same bytes, which would otherwise repeat forever.
+    pub recursive: bool,
+    /// How many levels of DATs `recursive` extracts below the DAT itself, `None` for `DEFAULT_MAX_NESTING_DEPTH`.
+    pub max_nesting_depth: Option<usize>,
+    /// Checked before every entry: once set, the extraction stops with `ErrorKind::Interrupted` without writing
+    /// further entries. The PAKs and nested DATs it extracts check it too.
+    pub cancel: Option<Arc<AtomicBool>>,
+}
+
+
+/// Fails with `ErrorKind::Interrupted` once `cancel` is set, see `ExtractOptions::cancel`.
+pub(crate) fn check_cancelled(cancel: &Option<Arc<AtomicBool>>) -> io::Result<()> {
+    if cancel.as_ref().is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+        return Err(io::Error::new(io::ErrorKind::Interrupted, "extraction was cancelled"));
+    }
+    Ok(())
 }
 
 
@@ -52,7 +268,7 @@ struct ByteDataWrapper {
 
 impl ByteDataWrapper {
 
-    async fn from_file(path: &str) -> io::Result<Self> {
+    fn from_file(path: &str) -> io::Result<Self> {
         let mut file = File::open(path)?;  
         let mut data = Vec::new();       
         file.read_to_end(&mut data)?;     
@@ -61,32 +277,76 @@ impl ByteDataWrapper {
 
 
     fn read_u32(&mut self) -> io::Result<u32> {
-        let value = (&self.data[self.position..]).read_u32::<LittleEndian>()?;
-        self.position += 4; 
-        Ok(value) 
+        ReadBytesExt::read_u32::<LittleEndian>(self)
+    }
+
+
+    fn read_offset(&mut self, offset_width: OffsetWidth) -> io::Result<u64> {
+        match offset_width {
+            OffsetWidth::U32 => self.read_u32().map(u64::from),
+            OffsetWidth::U64 => ReadBytesExt::read_u64::<LittleEndian>(self),
+        }
     }
 
 
     fn read_string(&mut self, length: usize) -> io::Result<String> {
-        let bytes = &self.data[self.position..self.position + length]; 
-        self.position += length; 
+        let bytes = self.read_bytes(length)?; 
         Ok(String::from_utf8_lossy(bytes).to_string())
     }
 
 
-    fn read_u8_list(&mut self, length: usize) -> io::Result<Vec<u8>> {
-        let mut list = Vec::with_capacity(length);
-        for _ in 0..length { 
-            list.push(self.read_u8()?); 
-        }
-        Ok(list)
+    fn read_bytes(&mut self, length: usize) -> io::Result<&[u8]> {
+        let position = self.position;
+        self.position = position.saturating_add(length);
+        self.slice(position, length)
+    }
+
+
+    fn read_cstring(&mut self, strict_utf8: bool) -> io::Result<String> {
+        let remaining = self.data.get(self.position..).unwrap_or_default();
+        let length = remaining.iter().position(|&byte| byte == 0).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::UnexpectedEof, "unterminated string")
+        })?;
+        let string = decode_name(&remaining[..length], strict_utf8)?;
+        self.position += length + 1;
+        Ok(string)
     }
 
 
-    fn read_u8(&mut self) -> io::Result<u8> {
-        let value = self.data[self.position];
-        self.position += 1; 
-        Ok(value) 
+    fn slice(&self, offset: usize, length: usize) -> io::Result<&[u8]> {
+        offset
+            .checked_add(length)
+            .and_then(|end| self.data.get(offset..end))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "entry lies outside of the file"))
+    }
+
+    /// The `count` consecutive `entry_size` byte entries of the table at `offset`, from one bounds check.
+    fn table(&self, offset: usize, count: usize, entry_size: usize) -> io::Result<Vec<&[u8]>> {
+        let length = count
+            .checked_mul(entry_size)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "table size overflows"))?;
+        let table = self.slice(offset, length)?;
+        Ok((0..count).map(|index| &table[index * entry_size..(index + 1) * entry_size]).collect())
+    }
+
+    fn read_u32_table(&self, offset: usize, count: usize) -> io::Result<Vec<u32>> {
+        Ok(self
+            .table(offset, count, 4)?
+            .into_iter()
+            .map(|entry| u32::from_le_bytes(entry.try_into().unwrap()))
+            .collect())
+    }
+
+    /// The offsets or sizes table at `offset`, with `offset_width` byte entries.
+    fn read_offset_table(&self, offset: usize, count: usize, offset_width: OffsetWidth) -> io::Result<Vec<u64>> {
+        Ok(self
+            .table(offset, count, offset_width.bytes())?
+            .into_iter()
+            .map(|entry| match offset_width {
+                OffsetWidth::U32 => u64::from(u32::from_le_bytes(entry.try_into().unwrap())),
+                OffsetWidth::U64 => u64::from_le_bytes(entry.try_into().unwrap()),
+            })
+            .collect())
     }
 
     fn set_position(&mut self, position: usize) {
@@ -94,96 +354,3107 @@ impl ByteDataWrapper {
     }
 }
 
+impl Read for ByteDataWrapper {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.data.get(self.position..).unwrap_or_default();
+        let length = remaining.len().min(buf.len());
+        buf[..length].copy_from_slice(&remaining[..length]);
+        self.position += length;
+        Ok(length)
+    }
+}
+
+/// Like `io::Cursor`, seeking past the end is allowed and reads there return nothing.
+impl Seek for ByteDataWrapper {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(offset) => (0, i64::try_from(offset).unwrap_or(i64::MAX)),
+            SeekFrom::End(offset) => (self.data.len(), offset),
+            SeekFrom::Current(offset) => (self.position, offset),
+        };
+        self.position = isize::try_from(offset)
+            .ok()
+            .and_then(|offset| base.checked_add_signed(offset))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative or overflowing position"))?;
+        Ok(self.position as u64)
+    }
+}
+
+/// Decodes a name from the name table. Fixed width names are null-padded, so the name ends at the first null
+/// byte and everything after it is ignored. An all-null name decodes to an empty string. Invalid UTF-8 is
+/// replaced with U+FFFD, or with `strict_utf8` reported as an error since it would only produce unopenable
+/// mojibake filenames.
+fn decode_name(bytes: &[u8], strict_utf8: bool) -> io::Result<String> {
+    let bytes = bytes.split(|&byte| byte == 0).next().unwrap_or_default();
+    if strict_utf8 {
+        std::str::from_utf8(bytes).map(str::to_string).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("DAT name {:?} is not valid UTF-8: {}", String::from_utf8_lossy(bytes), e),
+            )
+        })
+    } else {
+        Ok(String::from_utf8_lossy(bytes).to_string())
+    }
+}
+
+
+/// The extensions table is authoritative, so when a name's own extension disagrees with it
+/// (e.g. a DAT mis-repacked by another tool) the name is rewritten to the table's extension.
+/// Returns the name to extract as, and the mismatch to record in `dat_info.json` if there was one.
+fn reconcile_extension(name: String, table_extension: &str) -> (String, Option<serde_json::Value>) {
+    let Some((stem, name_extension)) = name.rsplit_once('.') else {
+        return (name, None);
+    };
+    if table_extension.is_empty() || name_extension == table_extension {
+        return (name, None);
+    }
+
+    let reconciled = format!("{}.{}", stem, table_extension);
+    let mismatch = json!({
+        "name": name,
+        "name_ext": name_extension,
+        "table_ext": table_extension,
+        "extracted_as": reconciled,
+    });
+    (reconciled, Some(mismatch))
+}
+
+
+/// Writes `data` to a temporary file next to `path`, in writes of at most `buffer_size` bytes, and renames it
+/// into place, so a crash mid-write never leaves a truncated file under the final name.
+pub(crate) fn write_file_atomic(path: &Path, data: &[u8], buffer_size: usize) -> io::Result<()> {
+    let file_name = path.file_name().and_then(|name| name.to_str()).unwrap_or("entry");
+    let temp_path = path.with_file_name(format!(".{}.tmp", file_name));
+
+    let written = File::create(&temp_path)
+        .and_then(|mut temp_file| data.chunks(buffer_size.max(1)).try_for_each(|chunk| temp_file.write_all(chunk)));
+    if let Err(e) = written.and_then(|_| fs::rename(&temp_path, path)) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(path_error(e, "write", path));
+    }
+    Ok(())
+}
+
+
+/// Adds what was done to which path to `error`, keeping its kind, so a bare "Permission denied" names the file.
+pub(crate) fn path_error(error: io::Error, action: &str, path: &Path) -> io::Error {
+    io::Error::new(error.kind(), format!("Failed to {} {}: {}", action, path.display(), error))
+}
+
+
+/// Fails if the directory files are to be extracted to is an existing file, e.g. the DAT itself given by
+/// mistake, with an error saying so instead of the bare one `create_dir_all` gives.
+pub(crate) fn check_extract_dir(dir: &Path) -> io::Result<()> {
+    if dir.exists() && !dir.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotADirectory,
+            format!("Can't extract to {}: it is a file, not a directory", dir.display()),
+        ));
+    }
+    Ok(())
+}
+
+
+/// `fs::create_dir_all` for the directory files are extracted to, see `check_extract_dir`.
+pub(crate) fn create_extract_dir(dir: &Path) -> io::Result<()> {
+    check_extract_dir(dir)?;
+    fs::create_dir_all(dir).map_err(|e| path_error(e, "create directory", dir))
+}
+
+
+fn is_transient(error: &io::Error) -> bool {
+    matches!(error.kind(), io::ErrorKind::Interrupted | io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock)
+}
+
+
+/// Runs `operation`, retrying it up to `max_retries` times after transient errors with a doubling backoff.
+pub(crate) fn with_retries<T>(
+    max_retries: u32,
+    backoff_ms: u64,
+    mut operation: impl FnMut() -> io::Result<T>,
+) -> io::Result<T> {
+    let mut attempt = 0;
+    loop {
+        match operation() {
+            Err(e) if attempt < max_retries && is_transient(&e) => {
+                thread::sleep(Duration::from_millis(backoff_ms.saturating_mul(1 << attempt.min(16))));
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+
+/// Formats `data` like `xxd`: offset, 16 hex bytes per line and their printable ASCII.
+fn hex_dump(data: &[u8]) -> String {
+    let mut dump = String::new();
+    for (line, chunk) in data.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let ascii: String = chunk
+            .iter()
+            .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' })
+            .collect();
+        dump.push_str(&format!("{:08x}  {:<47}  |{}|\n", line * 16, hex.join(" "), ascii));
+    }
+    dump
+}
+
+
+/// Lowercase hex of `data` without separators, for raw bytes in `dat_info.json` and `pakInfo.json`.
+pub(crate) fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+
+/// Reverses `to_hex`. `None` for an odd length or a non-hex digit.
+pub(crate) fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok()).collect()
+}
+
+
+fn is_already_extracted(output_path: &Path, expected_size: u64) -> bool {
+    fs::metadata(output_path).is_ok_and(|metadata| metadata.is_file() && metadata.len() == expected_size)
+}
+
+
+/// Decompresses `data` if the whole entry is one zlib stream. Besides a valid zlib header the stream has to
+/// end exactly at the end of the entry (and flate2 checks its Adler-32), so raw data that merely starts
+/// with a zlib-looking header is left alone.
+fn decompress_zlib_entry(data: &[u8]) -> Option<Vec<u8>> {
+    if !starts_with_zlib_header(data) {
+        return None;
+    }
+
+    let mut decoder = ZlibDecoder::new(data);
+    let mut decompressed = Vec::new();
+    decoder.read_to_end(&mut decompressed).ok()?;
+    (decoder.total_in() == data.len() as u64).then_some(decompressed)
+}
+
+
+/// The flate2 level that compresses `decompressed` back to exactly `original`, trying the default level first.
+fn recompression_level(original: &[u8], decompressed: &[u8]) -> Option<u32> {
+    [Compression::default().level()]
+        .into_iter()
+        .chain(0..=9)
+        .find(|&level| zlib_compress(decompressed, level).is_ok_and(|compressed| compressed == original))
+}
+
+
+/// Compresses `data` into a zlib stream, for entries `ExtractOptions::decompress_entries` wrote decompressed.
+pub(crate) fn zlib_compress(data: &[u8], level: u32) -> io::Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(level));
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+
+/// Whether `data` starts with a deflate zlib header (CMF and FLG with a valid check value).
+fn starts_with_zlib_header(data: &[u8]) -> bool {
+    let [cmf, flg, ..] = *data else {
+        return false;
+    };
+    cmf & 0x0F == 8 && cmf >> 4 <= 7 && (u16::from(cmf) << 8 | u16::from(flg)).is_multiple_of(31)
+}
+
+
+/// A zlib header instead of a container magic means the whole DAT is a zlib stream.
+fn is_zlib_wrapped_dat(data: &[u8]) -> bool {
+    !matches!(data.get(..4), Some(b"DAT\0") | Some(b"EFF\0")) && starts_with_zlib_header(data)
+}
+
+
+/// Inflates a DAT that is wrapped in zlib as a whole, recognised by a zlib header instead of a container
+/// magic. Other data is returned as it is. Returns whether the data was wrapped.
+fn unwrap_zlib_dat(bytes: ByteDataWrapper) -> io::Result<(ByteDataWrapper, bool)> {
+    if !is_zlib_wrapped_dat(&bytes.data) {
+        return Ok((bytes, false));
+    }
+
+    let mut inflated = Vec::new();
+    ZlibDecoder::new(bytes.data.as_slice())
+        .take(MAX_INFLATED_DAT_SIZE + 1)
+        .read_to_end(&mut inflated)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Failed to inflate zlib-wrapped DAT: {}", e)))?;
+    if inflated.len() as u64 > MAX_INFLATED_DAT_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("zlib-wrapped DAT inflates to more than {} bytes", MAX_INFLATED_DAT_SIZE),
+        ));
+    }
+    Ok((ByteDataWrapper { data: inflated, position: 0 }, true))
+}
+
+
+/// Returns the number of entries in a DAT by reading only its header, e.g. for a progress total.
+/// An empty file counts as a DAT without entries, same as in `extract_dat_files`.
+pub async fn dat_file_count(dat_path: &str) -> io::Result<u32> {
+    use tokio::io::AsyncReadExt;
+
+    let mut data = Vec::with_capacity(DAT_HEADER_SIZE as usize);
+    tokio::fs::File::open(dat_path).await?.take(DAT_HEADER_SIZE).read_to_end(&mut data).await?;
+    if data.is_empty() {
+        return Ok(0);
+    }
+    if is_zlib_wrapped_dat(&data) {
+        // Only the header of the wrapped DAT has to be inflated
+        let dat_path = dat_path.to_string();
+        data = tokio::task::spawn_blocking(move || -> io::Result<Vec<u8>> {
+            let mut header = Vec::with_capacity(DAT_HEADER_SIZE as usize);
+            ZlibDecoder::new(File::open(dat_path)?).take(DAT_HEADER_SIZE).read_to_end(&mut header)?;
+            Ok(header)
+        })
+        .await
+        .map_err(io::Error::other)??;
+    }
+
+    let mut bytes = ByteDataWrapper { data, position: 0 };
+    Ok(DatHeader::new(&mut bytes, OffsetWidth::U32)?.file_number)
+}
+
+
+/// Sums the sizes of the files extracting the DAT writes, e.g. to check for enough free disk space before
+/// extracting. With `include_pak` the YAX files its PAKs extract to are added, by their uncompressed sizes.
+/// The manifests and converted XML files aren't counted.
+pub async fn estimate_extracted_size(dat_path: &str, include_pak: bool) -> io::Result<u64> {
+    let dat_path = dat_path.to_string();
+    tokio::task::spawn_blocking(move || {
+        let options = ExtractOptions {
+            quiet: true,
+            ..Default::default()
+        };
+        let Some(listing) = read_dat_listing(&dat_path, &options)? else {
+            return Ok(0);
+        };
+        let mut total = 0;
+        for entry in &listing.entries {
+            total += entry.size;
+            if include_pak && entry.name.ends_with(".pak") {
+                let pak = listing.bytes.slice(entry.offset as usize, entry.size as usize)?;
+                total += pak_extracted_size(pak.to_vec())
+                    .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", entry.name, e)))?;
+            }
+        }
+        Ok(total)
+    })
+    .await
+    .map_err(io::Error::other)?
+}
+
+
+/// Checks that the hash map table agrees with the name table: every name's hash has to be listed
+/// with that name's index, in the bucket the table's pre hash shift puts it in. Repacks that rewrite
+/// names without regenerating the table break this, and the game then can't find those entries.
+/// Returns false for a DAT without a hash map table.
+pub fn verify_dat_hashmap(dat_path: &str) -> io::Result<bool> {
+    let mut bytes = ByteDataWrapper::from_file(dat_path)?;
+    let header = DatHeader::new(&mut bytes, OffsetWidth::U32)?;
+    header.check_file_number(bytes.data.len())?;
+    header.check_table_offsets()?;
+    if header.hash_map_offset == 0 {
+        return Ok(false);
+    }
+
+    let file_count = header.file_number as usize;
+    bytes.set_position(header.file_names_offset as usize);
+    let name_length = bytes.read_u32()? as usize;
+    let names = bytes
+        .table(bytes.position, file_count, name_length)?
+        .into_iter()
+        .map(|name| decode_name(name, false))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let hash_map_offset = header.hash_map_offset as usize;
+    bytes.set_position(hash_map_offset);
+    let pre_hash_shift = bytes.read_u32()?;
+    let buckets_offset = hash_map_offset + bytes.read_u32()? as usize;
+    let hashes_offset = hash_map_offset + bytes.read_u32()? as usize;
+    let indices_offset = hash_map_offset + bytes.read_u32()? as usize;
+    if pre_hash_shift > 31 {
+        return Ok(false);
+    }
+
+    let hashes = bytes.read_u32_table(hashes_offset, file_count)?;
+    let indices = bytes
+        .table(indices_offset, file_count, 2)?
+        .into_iter()
+        .map(|index| u16::from_le_bytes([index[0], index[1]]) as usize)
+        .collect::<Vec<_>>();
+
+    for (index, name) in names.iter().enumerate() {
+        let hash = dat_repack::dat_name_hash(name);
+        let Some(position) = (0..hashes.len()).find(|&position| hashes[position] == hash && indices[position] == index) else {
+            return Ok(false);
+        };
+
+        let bucket = bytes.slice(buckets_offset + (hash >> pre_hash_shift) as usize * 2, 2)?;
+        let bucket_start = i16::from_le_bytes([bucket[0], bucket[1]]);
+        let in_bucket = usize::try_from(bucket_start)
+            .is_ok_and(|start| start <= position && hashes[start..=position].iter().all(|&h| h >> pre_hash_shift == hash >> pre_hash_shift));
+        if !in_bucket {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+
+/// Finds the name of the entry of a DAT whose `dat_repack::dat_name_hash` is `hash`, e.g. one a hash map
+/// table or a game file refers to.
+pub fn find_dat_entry_by_hash(dat_path: &str, hash: u32) -> io::Result<Option<String>> {
+    let options = ExtractOptions {
+        quiet: true,
+        ..Default::default()
+    };
+    let Some(listing) = read_dat_listing(dat_path, &options)? else {
+        return Ok(None);
+    };
+    Ok(listing
+        .entries
+        .into_iter()
+        .map(|entry| entry.name)
+        .find(|name| dat_repack::dat_name_hash(name) == hash))
+}
+
+
+/// Reads the entry `name` of a DAT, e.g. to preview one file, reading only the header, the tables and that entry
+/// instead of the whole DAT. Zlib-wrapped DATs still have to be inflated as a whole.
+/// Returns `None` if the DAT is empty or has no such entry.
+pub fn read_dat_entry(dat_path: &str, name: &str) -> io::Result<Option<Vec<u8>>> {
+    let options = ExtractOptions {
+        quiet: true,
+        ..Default::default()
+    };
+    let mut file = File::open(dat_path)?;
+    let mut bytes = ByteDataWrapper { data: Vec::new(), position: 0 };
+    (&mut file).take(options.offset_width.header_size()).read_to_end(&mut bytes.data)?;
+    if bytes.data.is_empty() {
+        return Ok(None);
+    }
+    if is_zlib_wrapped_dat(&bytes.data) {
+        let Some(listing) = read_dat_listing(dat_path, &options)? else {
+            return Ok(None);
+        };
+        let Some(entry) = listing.entries.iter().find(|entry| entry.name == name) else {
+            return Ok(None);
+        };
+        return Ok(Some(listing.bytes.slice(entry.offset as usize, entry.size as usize)?.to_vec()));
+    }
+
+    // The name length is only known once its table is there, so the tables may take a second read
+    loop {
+        let required = required_table_len(&mut bytes, &options)?;
+        let read = bytes.data.len();
+        if read >= required {
+            break;
+        }
+        if (&mut file).take((required - read) as u64).read_to_end(&mut bytes.data)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("{} is {} bytes long but its tables need {} bytes", dat_path, read, required),
+            ));
+        }
+    }
+    bytes.set_position(0);
+    let listing = parse_dat_listing(bytes, false, &options)?;
+    let Some(entry) = listing.entries.iter().find(|entry| entry.name == name) else {
+        return Ok(None);
+    };
+
+    // The sizes come from the file, so they are checked before allocating the entry
+    let file_len = file.metadata()?.len();
+    if entry.offset.checked_add(entry.size).is_none_or(|end| end > file_len) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("DAT entry {} lies outside of {}, which is {} bytes long", name, dat_path, file_len),
+        ));
+    }
+    let mut data = vec![0; entry.size as usize];
+    file.seek(SeekFrom::Start(entry.offset))?;
+    file.read_exact(&mut data)?;
+    Ok(Some(data))
+}
+
+
 pub async fn extract_dat_files(
     dat_path: &str,
     extract_dir: &str,
     should_extract_pak_files: bool,
 ) -> io::Result<Vec<String>> {
-    let mut bytes = ByteDataWrapper::from_file(dat_path).await?;  
-    if bytes.data.is_empty() { 
-        println!("Warning: Empty DAT file"); 
-        return Ok(vec![]); 
+    let dat_path = dat_path.to_string();
+    let extract_dir = extract_dir.to_string();
+    tokio::task::spawn_blocking(move || extract_dat_files_sync(&dat_path, &extract_dir, should_extract_pak_files))
+        .await
+        .map_err(io::Error::other)?
+}
+
+
+/// Blocking version of `extract_dat_files` for callers that don't run a tokio runtime.
+pub fn extract_dat_files_sync(
+    dat_path: &str,
+    extract_dir: &str,
+    should_extract_pak_files: bool,
+) -> io::Result<Vec<String>> {
+    let options = ExtractOptions {
+        extract_pak_files: should_extract_pak_files,
+        ..Default::default()
+    };
+    extract_dat_files_with_options(dat_path, extract_dir, &options)
+}
+
+
+pub fn extract_dat_files_with_options(
+    dat_path: &str,
+    extract_dir: &str,
+    options: &ExtractOptions,
+) -> io::Result<Vec<String>> {
+    let result = extract_dat_files_detailed(dat_path, extract_dir, options)?;
+    Ok(result.files.into_iter().map(|file| file.path).collect())
+}
+
+
+/// Extracts only the entries of a DAT whose name matches the glob `pattern` (`globset` syntax, ignoring case as
+/// the game's name lookup does), e.g. `*.wtp`, and returns the paths of the written files. `dat_info.json` lists
+/// just those entries, so repacking the directory gives a DAT of only them. A DAT without matching entries
+/// writes nothing.
+pub fn extract_dat_files_matching(dat_path: &str, extract_dir: &str, pattern: &str) -> io::Result<Vec<String>> {
+    let pattern = GlobBuilder::new(pattern)
+        .case_insensitive(true)
+        .backslash_escape(true)
+        .build()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?
+        .compile_matcher();
+    check_extract_dir(Path::new(extract_dir))?;
+    let options = ExtractOptions::default();
+    let Some(mut listing) = read_dat_listing(dat_path, &options)? else {
+        return Ok(vec![]);
+    };
+    listing.entries.retain(|entry| pattern.is_match(&entry.name));
+    if listing.entries.is_empty() {
+        return Ok(vec![]);
     }
 
-    let header = DatHeader::new(&mut bytes)?; 
-    bytes.set_position(header.file_offsets_offset as usize); 
-    let file_offsets = (0..header.file_number)  
-        .map(|_| bytes.read_u32())
-        .collect::<io::Result<Vec<_>>>()?;
+    let mut sink = FsSink::new(extract_dir, false);
+    let file_names_sorted = extract_listing_to_sink(&listing, dat_path, &mut sink, &options)?;
+    let result = extract_dir_result(file_names_sorted, extract_dir, &options)?;
+    Ok(result.files.into_iter().map(|file| file.path).collect())
+}
 
-    bytes.set_position(header.file_sizes_offset as usize); 
-    let file_sizes = (0..header.file_number) 
-        .map(|_| bytes.read_u32())
-        .collect::<io::Result<Vec<_>>>()?;
 
-    bytes.set_position(header.file_names_offset as usize); 
-    let name_length = bytes.read_u32()? as usize; 
-    let file_names = (0..header.file_number)
-        .map(|_| {
-            let name = bytes.read_string(name_length)?; 
-            Ok(name.split('\u{0000}').next().unwrap().to_string())  
+/// A file written by an extraction.
+#[derive(Debug, Clone)]
+pub struct ExtractedFile {
+    pub path: String,
+    /// Name inside the container, e.g. `0.yax` for PAK entries.
+    pub name: String,
+    pub size: u64,
+    /// Extension without the dot, empty if the name has none.
+    pub ext: String,
+}
+
+impl ExtractedFile {
+    fn from_path(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            path: path.to_string_lossy().into_owned(),
+            name: path.file_name().map_or_else(String::new, |name| name.to_string_lossy().into_owned()),
+            size: fs::metadata(path)?.len(),
+            ext: path.extension().map_or_else(String::new, |ext| ext.to_string_lossy().into_owned()),
         })
-        .collect::<io::Result<Vec<_>>>()?;
+    }
+}
+
+
+/// Detailed result of `extract_dat_files_detailed`.
+#[derive(Debug, Clone)]
+pub struct ExtractionResult {
+    /// The extracted entries, sorted like the `files` of the manifest.
+    pub files: Vec<ExtractedFile>,
+    /// Path of `dat_info.json` (or `pakInfo.json` for PAK results). An empty DAT writes no manifest.
+    pub manifest_path: String,
+    /// One result per extracted `.pak`, empty unless `options.extract_pak_files` is set.
+    pub pak_results: Vec<ExtractionResult>,
+    /// One result per nested DAT, empty unless `options.recursive` is set.
+    pub nested_results: Vec<ExtractionResult>,
+}
+
+
+/// Like `extract_dat_files_with_options`, but returns the size and extension of every written file
+/// and the results of the PAK extractions.
+pub fn extract_dat_files_detailed(
+    dat_path: &str,
+    extract_dir: &str,
+    options: &ExtractOptions,
+) -> io::Result<ExtractionResult> {
+    let mut sink = FsSink::new(extract_dir, options.resume)
+        .with_buffer_size(options.buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE))
+        .with_retries(options.max_retries, options.backoff_ms);
+    extract_dat_files_into_dir(dat_path, extract_dir, &mut sink, options)
+}
 
-    fs::create_dir_all(extract_dir).await?;
 
-    for i in 0..header.file_number as usize { 
-        bytes.set_position(file_offsets[i] as usize);  
-        let mut extracted_file = fs::File::create(Path::new(extract_dir).join(&file_names[i])).await?;  
-        extracted_file.write_all(&bytes.read_u8_list(file_sizes[i] as usize)?).await?; 
+/// The body of `extract_dat_files_detailed` for a `sink` that writes into `extract_dir`.
+fn extract_dat_files_into_dir<S: OutputSink>(
+    dat_path: &str,
+    extract_dir: &str,
+    sink: &mut S,
+    options: &ExtractOptions,
+) -> io::Result<ExtractionResult> {
+    check_extract_dir(Path::new(extract_dir))?;
+    let file_names_sorted = extract_dat_files_to_sink(dat_path, sink, options)?;
+    let mut ancestors = Vec::new();
+    if options.recursive {
+        let data = fs::read(dat_path).map_err(|e| path_error(e, "read", Path::new(dat_path)))?;
+        ancestors.push((content_hash(&data), dat_path.to_string()));
     }
+    extract_dir_result_nested(file_names_sorted, extract_dir, options, 0, &mut ancestors)
+}
 
-    let mut file_names_sorted = file_names.clone();
-    file_names_sorted.sort_by(|a, b| { 
-        let a_parts: Vec<&str> = a.split('.').collect();
-        let b_parts: Vec<&str> = b.split('.').collect(); 
-        match a_parts[0].to_lowercase().cmp(&b_parts[0].to_lowercase()) {
-            std::cmp::Ordering::Equal => a_parts[1].to_lowercase().cmp(&b_parts[1].to_lowercase()), 
-            other => other,
-        }
-    });
 
-    let json_metadata = json!({ 
-        "version": 1,
-        "files": file_names_sorted,
-        "basename": Path::new(dat_path).file_stem().unwrap().to_str().unwrap(),
-        "ext": Path::new(dat_path).extension().unwrap().to_str().unwrap(),
-    });
+/// Extracts the PAKs among the entries written into `extract_dir` if `options.extract_pak_files` is set,
+/// and collects the result of the extraction.
+fn extract_dir_result(file_names_sorted: Vec<String>, extract_dir: &str, options: &ExtractOptions) -> io::Result<ExtractionResult> {
+    extract_dir_result_nested(file_names_sorted, extract_dir, options, 0, &mut Vec::new())
+}
+
+
+/// Identifies a DAT for the circularity check of `ExtractOptions::recursive`.
+pub(crate) fn content_hash(data: &[u8]) -> u128 {
+    let mut hasher = SipHasher13::new();
+    hasher.write(data);
+    hasher.finish128().as_u128()
+}
+
+
+/// Whether the extracted entry at `path` is a DAT `ExtractOptions::recursive` extracts, from its first bytes.
+fn is_nested_dat(path: &Path) -> io::Result<bool> {
+    let mut magic = Vec::with_capacity(4);
+    File::open(path)?.take(4).read_to_end(&mut magic)?;
+    if is_zlib_wrapped_dat(&magic) {
+        magic.clear();
+        // Inflating just the magic, a broken stream is no DAT
+        if ZlibDecoder::new(File::open(path)?).take(4).read_to_end(&mut magic).is_err() {
+            return Ok(false);
+        }
+    }
+    Ok(matches!(magic.as_slice(), b"DAT\0" | b"EFF\0"))
+}
 
-    let json_path = Path::new(extract_dir).join("dat_info.json");  
-    let mut json_file = fs::File::create(json_path).await?; 
-    json_file.write_all(serde_json::to_string_pretty(&json_metadata)?.as_bytes()).await?; 
 
-    if should_extract_pak_files { 
+/// `extract_dir_result` for the DAT extracted into `extract_dir` at `depth` levels below the outermost one.
+/// `ancestors` holds the content hashes and paths of the DATs it is nested in, itself included.
+fn extract_dir_result_nested(
+    file_names_sorted: Vec<String>,
+    extract_dir: &str,
+    options: &ExtractOptions,
+    depth: usize,
+    ancestors: &mut Vec<(u128, String)>,
+) -> io::Result<ExtractionResult> {
+    let mut pak_results = Vec::new();
+    if options.extract_pak_files { 
         let pak_files: Vec<&String> = file_names_sorted.iter().filter(|file| file.ends_with(".pak")).collect(); 
         for pak_file in pak_files {
             let pak_path = Path::new(extract_dir).join(pak_file); 
             let pak_extract_dir = Path::new(extract_dir).join(PAK_EXTRACT_SUBDIR).join(pak_file); 
-            extract_pak_files(pak_path.to_str().unwrap(), pak_extract_dir.to_str().unwrap(), true).await?;
+            let pak_options = PakExtractOptions { yax_to_xml: true, quiet: options.quiet, cancel: options.cancel.clone(), ..Default::default() };
+            let yax_files = extract_pak_files_with_options(pak_path.to_str().unwrap(), pak_extract_dir.to_str().unwrap(), &pak_options)?;
+            pak_results.push(ExtractionResult {
+                files: yax_files.iter().map(|file| ExtractedFile::from_path(Path::new(file))).collect::<io::Result<_>>()?,
+                manifest_path: pak_extract_dir.join("pakInfo.json").to_string_lossy().into_owned(),
+                pak_results: Vec::new(),
+                nested_results: Vec::new(),
+            });
+        }
+    }
+
+    let mut nested_results = Vec::new();
+    if options.recursive {
+        let max_depth = options.max_nesting_depth.unwrap_or(DEFAULT_MAX_NESTING_DEPTH);
+        for file in file_names_sorted.iter().filter(|file| !file.ends_with(".pak")) {
+            let nested_path = Path::new(extract_dir).join(file);
+            if !is_nested_dat(&nested_path).map_err(|e| path_error(e, "read", &nested_path))? {
+                continue;
+            }
+            let nested_path_string = nested_path.to_string_lossy().into_owned();
+            if depth >= max_depth {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: DATs are nested deeper than the limit of {} levels", nested_path_string, max_depth),
+                ));
+            }
+            let hash = content_hash(&fs::read(&nested_path).map_err(|e| path_error(e, "read", &nested_path))?);
+            if let Some((_, ancestor)) = ancestors.iter().find(|(ancestor_hash, _)| *ancestor_hash == hash) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} is the same DAT as {}, which it is nested in", nested_path_string, ancestor),
+                ));
+            }
+
+            let nested_extract_dir = Path::new(extract_dir).join(DAT_EXTRACT_SUBDIR).join(file).to_string_lossy().into_owned();
+            ancestors.push((hash, nested_path_string.clone()));
+            let nested_result = check_extract_dir(Path::new(&nested_extract_dir)).and_then(|_| {
+                let mut sink = FsSink::new(&nested_extract_dir, options.resume)
+                    .with_buffer_size(options.buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE))
+                    .with_retries(options.max_retries, options.backoff_ms);
+                let nested_files = extract_dat_files_to_sink(&nested_path_string, &mut sink, options)?;
+                extract_dir_result_nested(nested_files, &nested_extract_dir, options, depth + 1, ancestors)
+            });
+            ancestors.pop();
+            nested_results.push(nested_result?);
         }
     }
 
-    let extracted_files = file_names_sorted 
+    let files = file_names_sorted 
         .iter()
-        .map(|file| Path::new(extract_dir).join(file).to_str().unwrap().to_string())
-        .collect();
+        .map(|file| ExtractedFile::from_path(&Path::new(extract_dir).join(file)))
+        .collect::<io::Result<_>>()?;
 
-    Ok(extracted_files)
+    Ok(ExtractionResult {
+        files,
+        manifest_path: Path::new(extract_dir).join("dat_info.json").to_string_lossy().into_owned(),
+        pak_results,
+        nested_results,
+    })
 }
 
 
-#[no_mangle]
-pub extern "C" fn extract_dat_files_ffi(dat_path: *const c_char, extract_dir: *const c_char, should_extract_pak_files: c_uint) -> *mut c_char {
-    let dat_path = unsafe { CStr::from_ptr(dat_path).to_str().unwrap() };
-    let extract_dir = unsafe { CStr::from_ptr(extract_dir).to_str().unwrap() }; 
-    let should_extract_pak_files = should_extract_pak_files != 0; 
+/// Destination of an extraction: receives every entry and finally the `dat_info.json` manifest.
+pub trait OutputSink {
+    /// Called once before any entry is written, with the number of entries the DAT lists.
+    fn start(&mut self, _entry_count: usize) -> io::Result<()> {
+        Ok(())
+    }
 
-    let rt = Runtime::new().unwrap();
-    match rt.block_on(extract_dat_files(dat_path, extract_dir, should_extract_pak_files)) {  
-        Ok(files) => {
-            let json_files = json!(files).to_string();
-            CString::new(json_files).unwrap().into_raw() 
+    fn write_entry(&mut self, name: &str, data: &[u8]) -> io::Result<()>;
+    fn write_manifest(&mut self, json: &str) -> io::Result<()>;
+
+    /// Receives the `dat_filelist.txt` of `ExtractOptions::write_filelist`.
+    fn write_filelist(&mut self, filelist: &str) -> io::Result<()> {
+        self.write_entry("dat_filelist.txt", filelist.as_bytes())
+    }
+
+    /// Receives the `<name>.hex` dumps of `ExtractOptions::dump_unknown`.
+    fn write_dump(&mut self, name: &str, dump: &str) -> io::Result<()> {
+        self.write_entry(name, dump.as_bytes())
+    }
+}
+
+
+/// The default sink, writing entries and `dat_info.json` into a directory.
+pub struct FsSink {
+    extract_dir: PathBuf,
+    /// Skip entries whose output file already exists with the expected size.
+    resume: bool,
+    buffer_size: usize,
+    max_retries: u32,
+    backoff_ms: u64,
+    dir_created: bool,
+}
+
+impl FsSink {
+    pub fn new(extract_dir: impl Into<PathBuf>, resume: bool) -> Self {
+        Self {
+            extract_dir: extract_dir.into(),
+            resume,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            max_retries: 0,
+            backoff_ms: 0,
+            dir_created: false,
         }
-        Err(_) => std::ptr::null_mut(), 
+    }
+
+    /// Retries writes after transient errors, see `ExtractOptions::max_retries`.
+    pub fn with_retries(mut self, max_retries: u32, backoff_ms: u64) -> Self {
+        self.max_retries = max_retries;
+        self.backoff_ms = backoff_ms;
+        self
+    }
+
+    /// Creates the extract dir if needed, then writes `data` to `name` in it.
+    fn write(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        let output_path = self.output_path(name)?;
+        with_retries(self.max_retries, self.backoff_ms, || write_file_atomic(&output_path, data, self.buffer_size))
+    }
+
+    /// Writes files in writes of at most `buffer_size` bytes instead of `DEFAULT_BUFFER_SIZE`.
+    pub fn with_buffer_size(mut self, buffer_size: usize) -> Self {
+        self.buffer_size = buffer_size;
+        self
+    }
+
+    fn output_path(&mut self, name: &str) -> io::Result<PathBuf> {
+        if !self.dir_created {
+            create_extract_dir(&self.extract_dir)?;
+            self.dir_created = true;
+        }
+        Ok(self.extract_dir.join(name))
+    }
+}
+
+impl OutputSink for FsSink {
+    fn write_entry(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        let output_path = self.output_path(name)?;
+        if self.resume && is_already_extracted(&output_path, data.len() as u64) {
+            return Ok(());
+        }
+        self.write(name, data)
+    }
+
+    fn write_manifest(&mut self, json: &str) -> io::Result<()> {
+        self.write("dat_info.json", json.as_bytes())
+    }
+}
+
+
+/// Extracts a DAT into `sink` and returns the names the entries were written as, sorted like the `files`
+/// of `dat_info.json` (in DAT order with `options.index_prefix`, sorted lowercased names with `options.normalize_case`).
+/// `options.extract_pak_files` and `options.resume` are left to the caller and the sink.
+pub fn extract_dat_files_to_sink<S: OutputSink>(
+    dat_path: &str,
+    sink: &mut S,
+    options: &ExtractOptions,
+) -> io::Result<Vec<String>> {
+    let Some(listing) = read_dat_listing(dat_path, options)? else {
+        sink.start(0)?;
+        return Ok(vec![]);
+    };
+    extract_listing_to_sink(&listing, dat_path, sink, options)
+}
+
+
+/// The body of `extract_dat_files_to_sink` for an already parsed DAT. `dat_path` only names the DAT in the manifest.
+fn extract_listing_to_sink<S: OutputSink>(
+    listing: &DatListing,
+    dat_path: &str,
+    sink: &mut S,
+    options: &ExtractOptions,
+) -> io::Result<Vec<String>> {
+    sink.start(listing.entries.len())?;
+    let index_width = listing.entries.len().saturating_sub(1).to_string().len().max(3);
+    let mut used_names = HashSet::new();
+    let output_names: Vec<String> = listing
+        .entries
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            let name = if options.normalize_case {
+                unique_lowercase_name(&entry.name, &mut used_names)
+            } else {
+                entry.name.clone()
+            };
+            if options.index_prefix {
+                format!("{:0width$}_{}", index, name, width = index_width)
+            } else {
+                name
+            }
+        })
+        .collect();
+
+    let mut decompressed_entries = Vec::new();
+    let mut output_name_iter = output_names.iter().enumerate();
+    for_each_dat_entry(listing, |name, data| {
+        check_cancelled(&options.cancel)?;
+        let (index, output_name) = output_name_iter.next().map_or((0, name), |(index, name)| (index, name.as_str()));
+        let entry_error = |e: io::Error| io::Error::new(e.kind(), format!("DAT entry {} ({}): {}", index, name, e));
+        let decompressed = options.decompress_entries.then(|| decompress_zlib_entry(data)).flatten();
+        if let Some(decompressed) = &decompressed {
+            // Without a level that gives the original bytes the repacked entry is still a valid zlib stream
+            decompressed_entries.push(json!({ "name": name, "level": recompression_level(data, decompressed) }));
+        }
+        let data = decompressed.as_deref().unwrap_or(data);
+
+        if options.gzip_entries {
+            sink.write_entry(&format!("{}.gz", output_name), &gzip(data)?).map_err(entry_error)?;
+        } else {
+            sink.write_entry(output_name, data).map_err(entry_error)?;
+        }
+
+        if let Some(dump_length) = options.dump_unknown {
+            let extension = name.rsplit_once('.').map_or("", |(_, extension)| extension);
+            if !KNOWN_DAT_EXTENSIONS.iter().any(|known| known.eq_ignore_ascii_case(extension)) {
+                sink.write_dump(&format!("{}.hex", output_name), &hex_dump(&data[..data.len().min(dump_length)])).map_err(entry_error)?;
+            }
+        }
+        Ok(())
+    })?;
+
+    let mut file_names_sorted: Vec<String> = listing.entries.iter().map(|entry| entry.name.clone()).collect();
+    sort_dat_file_names(&mut file_names_sorted);
+
+    let mut json_metadata = json!({ 
+        "version": 1,
+        "files": file_names_sorted,
+        // `files` is sorted, repacking needs the order of the entries in the DAT
+        "entry_order": listing.entries.iter().map(|entry| entry.name.as_str()).collect::<Vec<_>>(),
+        "basename": Path::new(dat_path).file_stem().map_or_else(String::new, |stem| stem.to_string_lossy().into_owned()),
+        // Empty for an input without extension, e.g. a file named `data`
+        "ext": Path::new(dat_path).extension().map_or_else(String::new, |ext| ext.to_string_lossy().into_owned()),
+        "container_kind": listing.header_info.container_kind.as_str(),
+        "header": listing.header_info.to_json(),
+    });
+    if listing.zlib_wrapped {
+        json_metadata["zlib_wrapped"] = json!(true);
+    }
+    if !listing.unnamed_entries.is_empty() {
+        json_metadata["unnamed_entries"] = json!(listing.unnamed_entries);
+    }
+    if !listing.extension_mismatches.is_empty() {
+        json_metadata["extension_mismatches"] = json!(listing.extension_mismatches);
+    }
+    if !decompressed_entries.is_empty() {
+        json_metadata["decompressed_entries"] = json!(decompressed_entries);
+    }
+    let renamed: serde_json::Value = listing
+        .entries
+        .iter()
+        .zip(&output_names)
+        .map(|(entry, output_name)| json!({ "name": entry.name, "extracted_as": output_name }))
+        .collect();
+    if options.index_prefix {
+        json_metadata["index_prefixed"] = renamed.clone();
+    }
+    if options.normalize_case {
+        json_metadata["normalized_case"] = renamed;
+    }
+    if options.capture_padding {
+        json_metadata["layout"] = dat_layout(listing, &output_names);
+    }
+    if options.gzip_entries {
+        json_metadata["gzipped"] = json!(true);
+    }
+    if options.provenance {
+        json_metadata["provenance"] = dat_provenance(&listing.bytes.data, dat_path);
+    }
+    sink.write_manifest(&serde_json::to_string_pretty(&json_metadata)?)?;
+    if options.write_filelist {
+        let filelist: String = output_names.iter().map(|name| format!("{}\n", name)).collect();
+        sink.write_filelist(&filelist)?;
+    }
+
+    // Index prefixed names already sort in DAT order
+    let mut written_names = if options.index_prefix {
+        output_names
+    } else if options.normalize_case {
+        let mut output_names = output_names;
+        sort_dat_file_names(&mut output_names);
+        output_names
+    } else {
+        file_names_sorted
+    };
+    if options.gzip_entries {
+        for name in &mut written_names {
+            name.push_str(".gz");
+        }
+    }
+    Ok(written_names)
+}
+
+
+/// The `provenance` record of `ExtractOptions::provenance` for the DAT `data` read from `dat_path`. The size and
+/// CRC-32 are those of the DAT itself, so for a zlib-wrapped DAT they describe the inflated DAT. The record has
+/// its own `version` to grow independently of the manifest.
+fn dat_provenance(data: &[u8], dat_path: &str) -> serde_json::Value {
+    let extracted_at = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+    json!({
+        "version": 1,
+        "tool": env!("CARGO_PKG_NAME"),
+        "tool_version": env!("CARGO_PKG_VERSION"),
+        "source": {
+            "path": dat_path,
+            "size": data.len(),
+            "crc32": source_crc32(data),
+        },
+        "extracted_at_unix": extracted_at,
+    })
+}
+
+
+/// The CRC-32 recorded in `provenance`, as 8 lowercase hex digits.
+pub(crate) fn source_crc32(data: &[u8]) -> String {
+    format!("{:08x}", crc32fast::hash(data))
+}
+
+
+/// Compresses an entry for `ExtractOptions::gzip_entries`.
+fn gzip(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+
+/// Lowercases `name` for `ExtractOptions::normalize_case`. If that name is already in `used`, `_1`, `_2`, ...
+/// is appended to its stem until it isn't.
+fn unique_lowercase_name(name: &str, used: &mut HashSet<String>) -> String {
+    let lowercase = name.to_lowercase();
+    let (stem, extension) = match lowercase.rsplit_once('.') {
+        Some((stem, extension)) => (stem, format!(".{}", extension)),
+        None => (lowercase.as_str(), String::new()),
+    };
+    let mut unique_name = lowercase.clone();
+    let mut suffix = 1;
+    while !used.insert(unique_name.clone()) {
+        unique_name = format!("{}_{}{}", stem, suffix, extension);
+        suffix += 1;
+    }
+    unique_name
+}
+
+
+/// The bytes of a DAT outside its entries: everything before the first entry and, for the entries in file
+/// order, the bytes between the end of each and the start of the next one (or the end of the file).
+fn dat_layout(listing: &DatListing, output_names: &[String]) -> serde_json::Value {
+    let data = &listing.bytes.data;
+    let mut file_order: Vec<usize> = (0..listing.entries.len()).collect();
+    file_order.sort_by_key(|&index| listing.entries[index].offset);
+
+    let entry_start = |index: usize| (listing.entries[index].offset as usize).min(data.len());
+    let header_end = file_order.first().map_or(data.len(), |&index| entry_start(index));
+    let entries: Vec<serde_json::Value> = file_order
+        .iter()
+        .enumerate()
+        .map(|(position, &index)| {
+            let entry = &listing.entries[index];
+            let end = (entry.offset as usize + entry.size as usize).min(data.len());
+            let next = file_order.get(position + 1).map_or(data.len(), |&next| entry_start(next));
+            json!({
+                "index": index,
+                "file": output_names[index],
+                "size": entry.size,
+                "padding": to_hex(data.get(end..next).unwrap_or_default()),
+            })
+        })
+        .collect();
+    json!({ "header": to_hex(&data[..header_end]), "entries": entries })
+}
+
+
+/// Sorts names like the `files` of `dat_info.json`: case-insensitively by the part before the first dot,
+/// then by the part after it.
+fn sort_dat_file_names(names: &mut [String]) {
+    names.sort_by(|a, b| { 
+        let a_parts: Vec<&str> = a.split('.').collect();
+        let b_parts: Vec<&str> = b.split('.').collect(); 
+        match a_parts[0].to_lowercase().cmp(&b_parts[0].to_lowercase()) {
+            std::cmp::Ordering::Equal => a_parts.get(1).map(|ext| ext.to_lowercase()).cmp(&b_parts.get(1).map(|ext| ext.to_lowercase())), 
+            other => other,
+        }
+    });
+}
+
+
+/// Rebuilds `dat_info.json` in an extracted directory whose manifest was lost or broken, listing every file
+/// in it (sorted as on extraction) as an entry of the DAT `basename.ext`. Subdirectories, like the
+/// `pakExtracted` one, `dat_filelist.txt` and leftover temporary files aren't entries and are skipped.
+/// What only extraction knows, like `index_prefixed` or `layout`, can't be recovered.
+pub fn regenerate_dat_manifest(extract_dir: &str, basename: &str, ext: &str) -> io::Result<()> {
+    let extract_dir = Path::new(extract_dir);
+    let mut files = Vec::new();
+    for dir_entry in fs::read_dir(extract_dir).map_err(|e| path_error(e, "read directory", extract_dir))? {
+        let dir_entry = dir_entry?;
+        if !dir_entry.file_type()?.is_file() {
+            continue;
+        }
+        let name = dir_entry.file_name().to_string_lossy().into_owned();
+        let is_temp_file = name.starts_with('.') && name.ends_with(".tmp");
+        if name != "dat_info.json" && name != "dat_filelist.txt" && !is_temp_file {
+            files.push(name);
+        }
+    }
+    sort_dat_file_names(&mut files);
+
+    let container_kind = if ext.eq_ignore_ascii_case("eff") { ContainerKind::Eff } else { ContainerKind::Dat };
+    let json_metadata = json!({
+        "version": 1,
+        "files": files,
+        "basename": basename,
+        "ext": ext,
+        "container_kind": container_kind.as_str(),
+    });
+    let manifest_path = extract_dir.join("dat_info.json");
+    write_file_atomic(&manifest_path, serde_json::to_string_pretty(&json_metadata)?.as_bytes(), DEFAULT_BUFFER_SIZE)
+}
+
+
+/// Calls `handler` with the name and bytes of every DAT entry, in table order, instead of writing any files.
+pub fn extract_dat_files_with_handler<F>(dat_path: &str, handler: F) -> io::Result<()>
+where
+    F: FnMut(&str, &[u8]) -> io::Result<()>,
+{
+    let options = ExtractOptions {
+        quiet: true,
+        ..Default::default()
+    };
+    match read_dat_listing(dat_path, &options)? {
+        Some(listing) => for_each_dat_entry(&listing, handler),
+        None => Ok(()),
+    }
+}
+
+
+struct DatEntry {
+    name: String,
+    offset: u64,
+    size: u64,
+}
+
+
+/// A DAT file loaded into memory together with its parsed entry table.
+struct DatListing {
+    bytes: ByteDataWrapper,
+    /// The file was a zlib stream holding the DAT, `bytes` is the inflated DAT.
+    zlib_wrapped: bool,
+    header_info: DatHeaderInfo,
+    entries: Vec<DatEntry>,
+    extension_mismatches: Vec<serde_json::Value>,
+    /// Entries whose name is empty (all null bytes) and the `unnamed_<index>` name they are extracted as.
+    unnamed_entries: Vec<serde_json::Value>,
+}
+
+
+/// Header values of a DAT beyond the table offsets, for repacking it faithfully and for format research.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DatHeaderInfo {
+    pub container_kind: ContainerKind,
+    pub file_count: u32,
+    /// The u32 following the header fields if the tables leave room for it, `None` otherwise. No flags are
+    /// known in it, game DATs have 0 there.
+    pub reserved: Option<u32>,
+    /// The largest power of two (up to 4096) all non-empty entries are aligned to. The game's DATs use 16.
+    /// `None` if there are no non-empty entries.
+    pub entry_alignment: Option<u32>,
+}
+
+impl DatHeaderInfo {
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "reserved": self.reserved,
+            "entry_alignment": self.entry_alignment,
+        })
+    }
+}
+
+
+/// Largest power of two up to 4096 that divides every offset, `None` for no offsets.
+fn common_alignment(offsets: impl Iterator<Item = u64>) -> Option<u32> {
+    offsets.map(|offset| offset | 4096).reduce(|a, b| a | b).map(|bits| 1 << bits.trailing_zeros())
+}
+
+
+/// Reads the header values of the DAT at `dat_path` that extraction doesn't need, `None` for an empty file.
+pub fn read_dat_header_info(dat_path: &str) -> io::Result<Option<DatHeaderInfo>> {
+    let options = ExtractOptions {
+        quiet: true,
+        ..Default::default()
+    };
+    Ok(read_dat_listing(dat_path, &options)?.map(|listing| listing.header_info))
+}
+
+
+/// The container flavour, detected from the magic. Effect archives (`.eff`) use the DAT layout with an
+/// `EFF\0` magic, and some of them leave out the extensions table (its offset is 0).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContainerKind {
+    Dat,
+    Eff,
+    /// Any other magic, parsed with the DAT layout.
+    Unknown,
+}
+
+impl ContainerKind {
+    fn from_magic(magic: &str) -> Self {
+        match magic {
+            "DAT\0" => ContainerKind::Dat,
+            "EFF\0" => ContainerKind::Eff,
+            _ => ContainerKind::Unknown,
+        }
+    }
+
+    /// The name recorded as `container_kind` in `dat_info.json`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContainerKind::Dat => "dat",
+            ContainerKind::Eff => "eff",
+            ContainerKind::Unknown => "unknown",
+        }
+    }
+}
+
+
+/// Reads the DAT and parses its tables. Returns `None` for an empty file.
+fn read_dat_listing(dat_path: &str, options: &ExtractOptions) -> io::Result<Option<DatListing>> {
+    let mut bytes = with_retries(options.max_retries, options.backoff_ms, || ByteDataWrapper::from_file(dat_path))?;  
+    if options.tail {
+        bytes = wait_for_complete_dat(dat_path, bytes, options)?;
+    }
+    if bytes.data.is_empty() { 
+        if !options.quiet {
+            eprintln!("Warning: Empty DAT file"); 
+        }
+        return Ok(None); 
+    }
+    let (bytes, zlib_wrapped) = unwrap_zlib_dat(bytes)?;
+    parse_dat_listing(bytes, zlib_wrapped, options).map(Some)
+}
+
+
+/// Parses the tables of a DAT. `bytes` has to hold the header and tables, but may end before the entries.
+fn parse_dat_listing(mut bytes: ByteDataWrapper, zlib_wrapped: bool, options: &ExtractOptions) -> io::Result<DatListing> {
+    let header = DatHeader::new(&mut bytes, options.offset_width)?; 
+    header.check_file_number(bytes.data.len())?;
+    header.check_table_offsets()?;
+    let file_count = header.file_number as usize;
+    let file_offsets = bytes.read_offset_table(header.file_offsets_offset as usize, file_count, options.offset_width)?;
+    let file_sizes = bytes.read_offset_table(header.file_sizes_offset as usize, file_count, options.offset_width)?;
+
+    bytes.set_position(header.file_names_offset as usize); 
+    let file_names = match options.name_table_format {
+        NameTableFormat::FixedWidth => {
+            let name_length = bytes.read_u32()? as usize; 
+            bytes
+                .table(bytes.position, file_count, name_length)?
+                .into_iter()
+                .map(|name| decode_name(name, options.strict_utf8))
+                .collect::<io::Result<Vec<_>>>()?
+        }
+        NameTableFormat::NullTerminated => (0..file_count)
+            .map(|_| bytes.read_cstring(options.strict_utf8))
+            .collect::<io::Result<Vec<_>>>()?,
+    };
+
+    // An offset of 0 would point at the header itself, it means the extensions table is missing
+    let file_extensions = if header.file_extensions_offset == 0 {
+        vec![String::new(); file_count]
+    } else {
+        bytes
+            .table(header.file_extensions_offset as usize, file_count, 4)?
+            .into_iter()
+            .map(|extension| {
+                let extension = extension.split(|&byte| byte == 0).next().unwrap_or_default();
+                String::from_utf8_lossy(extension).into_owned()
+            })
+            .collect()
+    };
+
+    let mut extension_mismatches = Vec::new();
+    let mut unnamed_entries = Vec::new();
+    let entries = file_names
+        .into_iter()
+        .zip(&file_extensions)
+        .zip(file_offsets.into_iter().zip(file_sizes))
+        .enumerate()
+        .map(|(index, ((mut name, table_extension), (offset, size)))| {
+            if name.is_empty() {
+                // An empty name would be written as the extract dir itself, or collide with other empty names
+                name = format!("unnamed_{}", index);
+                if !table_extension.is_empty() {
+                    name = format!("{}.{}", name, table_extension);
+                }
+                if !options.quiet {
+                    eprintln!("Warning: DAT entry {} has an empty name, extracting it as {}", index, name);
+                }
+                unnamed_entries.push(json!({ "index": index, "extracted_as": name }));
+            }
+            let (reconciled, mismatch) = reconcile_extension(name, table_extension);
+            if let Some(mismatch) = mismatch {
+                if !options.quiet {
+                    eprintln!(
+                        "Warning: {} has extension '{}' but the extensions table says '{}'",
+                        mismatch["name"].as_str().unwrap(), mismatch["name_ext"].as_str().unwrap(), table_extension
+                    );
+                }
+                extension_mismatches.push(mismatch);
+            }
+            DatEntry { name: reconciled, offset, size }
+        })
+        .collect::<Vec<DatEntry>>();
+
+    let header_info = DatHeaderInfo {
+        container_kind: ContainerKind::from_magic(&header.id),
+        file_count: header.file_number,
+        reserved: header.reserved_field(&bytes),
+        entry_alignment: common_alignment(entries.iter().filter(|entry| entry.size > 0).map(|entry| entry.offset)),
+    };
+    Ok(DatListing { bytes, zlib_wrapped, header_info, entries, extension_mismatches, unnamed_entries })
+}
+
+
+/// How long the DAT in `bytes` has to be to hold everything its header and tables point at, as far as what's
+/// already there tells. Parts that are still missing count as needed up to where they start.
+fn required_dat_len(bytes: &mut ByteDataWrapper, options: &ExtractOptions) -> io::Result<usize> {
+    let required = required_table_len(bytes, options)?;
+    if bytes.data.len() < required {
+        return Ok(required);
+    }
+
+    bytes.set_position(0);
+    let header = DatHeader::new(bytes, options.offset_width)?;
+    let file_count = header.file_number as usize;
+    let offsets = bytes.read_offset_table(header.file_offsets_offset as usize, file_count, options.offset_width)?;
+    let sizes = bytes.read_offset_table(header.file_sizes_offset as usize, file_count, options.offset_width)?;
+    let entries_end = offsets.iter().zip(&sizes).map(|(&offset, &size)| offset as usize + size as usize).max();
+    Ok(required.max(entries_end.unwrap_or(0)))
+}
+
+
+/// `required_dat_len` for just the header and the tables the listing is parsed from, without the entries.
+fn required_table_len(bytes: &mut ByteDataWrapper, options: &ExtractOptions) -> io::Result<usize> {
+    bytes.set_position(0);
+    let header = match DatHeader::new(bytes, options.offset_width) {
+        Ok(header) => header,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(options.offset_width.header_size() as usize),
+        Err(e) => return Err(e),
+    };
+    let table_len = header.file_number as usize * options.offset_width.bytes();
+    let extensions_len = header.file_number as usize * 4;
+    let offsets_end = header.file_offsets_offset as usize + table_len;
+    let sizes_end = header.file_sizes_offset as usize + table_len;
+    let mut required = offsets_end.max(sizes_end).max(header.file_names_offset as usize + 4);
+    if header.file_extensions_offset != 0 {
+        required = required.max(header.file_extensions_offset as usize + extensions_len);
+    }
+    if bytes.data.len() < required {
+        return Ok(required);
+    }
+
+    if options.name_table_format == NameTableFormat::FixedWidth {
+        bytes.set_position(header.file_names_offset as usize);
+        let name_length = bytes.read_u32()? as usize;
+        required = required.max(bytes.position + name_length.saturating_mul(header.file_number as usize));
+    }
+    Ok(required)
+}
+
+
+/// Re-reads a DAT that is still being written until it has grown to `required_dat_len`, giving up after
+/// `options.tail_timeout_ms`.
+fn wait_for_complete_dat(dat_path: &str, mut bytes: ByteDataWrapper, options: &ExtractOptions) -> io::Result<ByteDataWrapper> {
+    let start = std::time::Instant::now();
+    loop {
+        let required = required_dat_len(&mut bytes, options)?;
+        bytes.set_position(0);
+        if bytes.data.len() >= required {
+            return Ok(bytes);
+        }
+        if start.elapsed() >= Duration::from_millis(options.tail_timeout_ms) {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "{} is {} bytes long but needs {} bytes, it didn't grow within {} ms",
+                    dat_path, bytes.data.len(), required, options.tail_timeout_ms
+                ),
+            ));
+        }
+        thread::sleep(Duration::from_millis(50));
+        bytes = with_retries(options.max_retries, options.backoff_ms, || ByteDataWrapper::from_file(dat_path))?;
+    }
+}
+
+
+fn for_each_dat_entry<F>(listing: &DatListing, mut handler: F) -> io::Result<()>
+where
+    F: FnMut(&str, &[u8]) -> io::Result<()>,
+{
+    for entry in &listing.entries {
+        handler(&entry.name, listing.bytes.slice(entry.offset as usize, entry.size as usize)?)?;
+    }
+    Ok(())
+}
+
+
+#[no_mangle]
+pub extern "C" fn extract_dat_files_ffi(dat_path: *const c_char, extract_dir: *const c_char, should_extract_pak_files: c_uint) -> *mut c_char {
+    let should_extract_pak_files = should_extract_pak_files != 0; 
+    let result = ffi_path(dat_path, "dat_path").and_then(|dat_path| {
+        let extract_dir = ffi_path(extract_dir, "extract_dir")?;
+        extract_dat_files_sync(dat_path, extract_dir, should_extract_pak_files)
+    });
+
+    // Null on any failure, the `_timeout`, `_status` and `_callbacks` variants report the error
+    match result {  
+        Ok(files) => {
+            let json_files = json!(files).to_string();
+            CString::new(json_files).unwrap().into_raw() 
+        }
+        Err(_) => std::ptr::null_mut(), 
+    }
+}
+
+
+/// Same as `extract_dat_files_ffi`, but takes null-terminated UTF-16 paths, so paths with characters outside
+/// the active code page (e.g. a non-ASCII user name) survive the trip from the host.
+#[cfg(windows)]
+#[no_mangle]
+pub extern "C" fn extract_dat_files_ffi_w(dat_path: *const u16, extract_dir: *const u16, should_extract_pak_files: c_uint) -> *mut c_char {
+    let result = ffi_wide_path(dat_path, "dat_path").and_then(|dat_path| {
+        let extract_dir = ffi_wide_path(extract_dir, "extract_dir")?;
+        extract_dat_files_sync(&dat_path, &extract_dir, should_extract_pak_files != 0)
+    });
+
+    match result {
+        Ok(files) => CString::new(json!(files).to_string()).unwrap().into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+
+/// Same as `extract_dat_files_ffi`, but gives up after `timeout_ms` milliseconds (`0` disables the timeout).
+/// On failure this returns a JSON envelope `{"error": {"kind": ..., "message": ...}}` instead of null.
+#[no_mangle]
+pub extern "C" fn extract_dat_files_ffi_timeout(dat_path: *const c_char, extract_dir: *const c_char, should_extract_pak_files: c_uint, timeout_ms: c_uint) -> *mut c_char {
+    let (dat_path, extract_dir) = match (ffi_path(dat_path, "dat_path"), ffi_path(extract_dir, "extract_dir")) {
+        (Ok(dat_path), Ok(extract_dir)) => (dat_path, extract_dir),
+        (Err(e), _) | (_, Err(e)) => return ffi_error_envelope(&e),
+    };
+    let (dat_path, extract_dir) = (dat_path.to_string(), extract_dir.to_string());
+    let should_extract_pak_files = should_extract_pak_files != 0;
+
+    run_ffi_with_timeout(move |cancel| {
+        let options = ExtractOptions {
+            extract_pak_files: should_extract_pak_files,
+            cancel: Some(cancel),
+            ..Default::default()
+        };
+        extract_dat_files_with_options(&dat_path, &extract_dir, &options)
+    }, timeout_ms)
+}
+
+
+/// Same as `extract_dat_files_ffi`, but returns `FFI_STATUS_OK` or `FFI_STATUS_ERROR` and hands out its results
+/// through the out-parameters, for hosts that would rather check a status code than parse JSON. On success
+/// `*files_out` is set to the JSON file list, on failure `*error_out` to the error message. Either pointer may be
+/// null, the strings it receives are freed with `free_ffi_string`.
+#[no_mangle]
+pub extern "C" fn extract_dat_files_ffi_status(
+    dat_path: *const c_char,
+    extract_dir: *const c_char,
+    should_extract_pak_files: c_uint,
+    files_out: *mut *mut c_char,
+    error_out: *mut *mut c_char,
+) -> c_int {
+    let result = ffi_path(dat_path, "dat_path").and_then(|dat_path| {
+        let extract_dir = ffi_path(extract_dir, "extract_dir")?;
+        extract_dat_files_sync(dat_path, extract_dir, should_extract_pak_files != 0)
+    });
+    ffi_status(result, files_out, error_out)
+}
+
+
+/// Function pointers the host passes to `extract_dat_files_ffi_callbacks` to follow an extraction.
+/// Every pointer may be null. String arguments are only valid for the duration of the call.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct ExtractionCallbacks {
+    /// Called once before any entry is written, with the number of entries in the DAT.
+    pub on_start: Option<extern "C" fn(total_entries: c_uint)>,
+    /// Called after each entry is written, with its name, index in write order and size in bytes.
+    pub on_entry: Option<extern "C" fn(name: *const c_char, index: c_uint, size: u64)>,
+    /// Called when the extraction succeeded, with the number of extracted entries.
+    pub on_complete: Option<extern "C" fn(extracted_count: c_uint)>,
+    /// Called instead of `on_complete` when the extraction failed, with the error message.
+    pub on_error: Option<extern "C" fn(message: *const c_char)>,
+}
+
+
+/// An `FsSink` that reports the entry count of the DAT to `ExtractionCallbacks::on_start` and every written DAT entry
+/// to `ExtractionCallbacks::on_entry`.
+struct CallbackSink {
+    inner: FsSink,
+    callbacks: ExtractionCallbacks,
+    entry_index: c_uint,
+}
+
+impl OutputSink for CallbackSink {
+    fn start(&mut self, entry_count: usize) -> io::Result<()> {
+        if let Some(on_start) = self.callbacks.on_start {
+            on_start(entry_count as c_uint);
+        }
+        Ok(())
+    }
+
+    fn write_entry(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        self.inner.write_entry(name, data)?;
+        if let Some(on_entry) = self.callbacks.on_entry {
+            let name = CString::new(name).unwrap_or_default();
+            on_entry(name.as_ptr(), self.entry_index, data.len() as u64);
+        }
+        self.entry_index += 1;
+        Ok(())
+    }
+
+    fn write_manifest(&mut self, json: &str) -> io::Result<()> {
+        self.inner.write_manifest(json)
+    }
+
+    fn write_filelist(&mut self, filelist: &str) -> io::Result<()> {
+        self.inner.write_filelist(filelist)
+    }
+
+    fn write_dump(&mut self, name: &str, dump: &str) -> io::Result<()> {
+        self.inner.write_dump(name, dump)
+    }
+}
+
+
+/// Same as `extract_dat_files_ffi`, but streams progress to `callbacks` (which may be null) while extracting.
+/// On failure this returns the JSON error envelope instead of null, after calling `on_error`.
+#[no_mangle]
+pub extern "C" fn extract_dat_files_ffi_callbacks(
+    dat_path: *const c_char,
+    extract_dir: *const c_char,
+    should_extract_pak_files: c_uint,
+    callbacks: *const ExtractionCallbacks,
+) -> *mut c_char {
+    let (dat_path, extract_dir) = match (ffi_path(dat_path, "dat_path"), ffi_path(extract_dir, "extract_dir")) {
+        (Ok(dat_path), Ok(extract_dir)) => (dat_path, extract_dir),
+        (Err(e), _) | (_, Err(e)) => return ffi_error_envelope(&e),
+    };
+    let callbacks = unsafe { callbacks.as_ref() }.copied().unwrap_or_default();
+    let options = ExtractOptions {
+        extract_pak_files: should_extract_pak_files != 0,
+        ..Default::default()
+    };
+
+    let mut sink = CallbackSink {
+        inner: FsSink::new(extract_dir, options.resume)
+            .with_buffer_size(options.buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE))
+            .with_retries(options.max_retries, options.backoff_ms),
+        callbacks,
+        entry_index: 0,
+    };
+    let result = extract_dat_files_into_dir(dat_path, extract_dir, &mut sink, &options);
+
+    match result {
+        Ok(result) => {
+            if let Some(on_complete) = callbacks.on_complete {
+                on_complete(result.files.len() as c_uint);
+            }
+            let files: Vec<String> = result.files.into_iter().map(|file| file.path).collect();
+            CString::new(json!(files).to_string()).unwrap().into_raw()
+        }
+        Err(e) => {
+            if let Some(on_error) = callbacks.on_error {
+                let message = CString::new(e.to_string()).unwrap_or_default();
+                on_error(message.as_ptr());
+            }
+            ffi_error_envelope(&e)
+        }
+    }
+}
+
+
+/// Runs an extraction on its own thread and returns the file list as JSON, or the error envelope.
+/// On timeout the flag the extraction gets is set and the extraction is waited for, which stops before its next
+/// entry, so nothing writes into the extract dir anymore once the timeout is reported and the caller can retry
+/// or clean up.
+pub(crate) fn run_ffi_with_timeout<F>(extraction: F, timeout_ms: c_uint) -> *mut c_char
+where
+    F: FnOnce(Arc<AtomicBool>) -> io::Result<Vec<String>> + Send + 'static,
+{
+    let cancel = Arc::new(AtomicBool::new(false));
+    let (sender, receiver) = mpsc::channel();
+    let extraction_cancel = Arc::clone(&cancel);
+    let extraction_thread = thread::spawn(move || {
+        let _ = sender.send(extraction(extraction_cancel));
+    });
+
+    let received = if timeout_ms == 0 {
+        receiver.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected)
+    } else {
+        receiver.recv_timeout(Duration::from_millis(timeout_ms as u64))
+    };
+    let result = match received {
+        Ok(result) => result,
+        Err(mpsc::RecvTimeoutError::Timeout) => {
+            cancel.store(true, Ordering::Relaxed);
+            let _ = extraction_thread.join();
+            Err(io::Error::new(io::ErrorKind::TimedOut, format!("extraction timed out after {} ms", timeout_ms)))
+        }
+        Err(mpsc::RecvTimeoutError::Disconnected) => Err(io::Error::other("extraction thread panicked")),
+    };
+
+    match result {
+        Ok(files) => CString::new(json!(files).to_string()).unwrap().into_raw(),
+        Err(e) => ffi_error_envelope(&e),
+    }
+}
+
+
+/// The JSON object reported for a failed extraction, `{"error": {"kind": ..., "message": ...}}`.
+pub fn error_envelope(error: &io::Error) -> serde_json::Value {
+    json!({
+        "error": {
+            "kind": format!("{:?}", error.kind()),
+            "message": error.to_string(),
+        }
+    })
+}
+
+
+/// Reads a path argument of an FFI function. A null or non-UTF-8 path is reported as `InvalidInput`
+/// instead of panicking, which would abort the host.
+pub(crate) fn ffi_path<'a>(path: *const c_char, parameter: &str) -> io::Result<&'a str> {
+    if path.is_null() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is null", parameter)));
+    }
+    unsafe { CStr::from_ptr(path) }.to_str().map_err(|e| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not valid UTF-8: {}", parameter, e))
+    })
+}
+
+
+/// Reads a null-terminated UTF-16 path argument of a `_w` FFI function, reported like in `ffi_path` if it's
+/// null or contains unpaired surrogates.
+#[cfg(windows)]
+pub(crate) fn ffi_wide_path(path: *const u16, parameter: &str) -> io::Result<String> {
+    use std::ffi::OsString;
+    use std::os::windows::ffi::OsStringExt;
+
+    if path.is_null() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is null", parameter)));
+    }
+    let length = (0..).take_while(|&index| unsafe { *path.add(index) } != 0).count();
+    let wide = unsafe { std::slice::from_raw_parts(path, length) };
+    OsString::from_wide(wide).into_string().map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not valid UTF-16", parameter))
+    })
+}
+
+
+pub(crate) fn ffi_error_envelope(error: &io::Error) -> *mut c_char {
+    CString::new(error_envelope(error).to_string()).unwrap().into_raw()
+}
+
+
+/// Reads the entry `name` of a DAT into memory with `read_dat_entry`, without writing anything. Returns null if
+/// the DAT has no such entry or can't be read, otherwise the entry's bytes, whose length is stored in `*out_len`
+/// (if `out_len` isn't null). The buffer belongs to the library: pass it and its length to `free_buffer_ffi` once
+/// done, not to the host's `free`.
+#[no_mangle]
+pub extern "C" fn read_dat_entry_ffi(dat_path: *const c_char, name: *const c_char, out_len: *mut usize) -> *mut u8 {
+    let data = ffi_path(dat_path, "dat_path").and_then(|dat_path| read_dat_entry(dat_path, ffi_path(name, "name")?));
+    let Ok(Some(data)) = data else {
+        return std::ptr::null_mut();
+    };
+    if let Some(out_len) = unsafe { out_len.as_mut() } {
+        *out_len = data.len();
+    }
+    Box::into_raw(data.into_boxed_slice()) as *mut u8
+}
+
+
+/// Frees a buffer returned by `read_dat_entry_ffi`, given the length it reported. Null is ignored.
+#[no_mangle]
+pub extern "C" fn free_buffer_ffi(buffer: *mut u8, len: usize) {
+    if !buffer.is_null() {
+        drop(unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(buffer, len)) });
+    }
+}
+
+
+/// Status the `_status` FFI functions return on success.
+pub const FFI_STATUS_OK: c_int = 0;
+/// Status the `_status` FFI functions return on failure, with the message in their `error_out` parameter.
+pub const FFI_STATUS_ERROR: c_int = 1;
+
+
+/// Stores the file list or the error message of an extraction in the matching out-parameter, if it isn't null,
+/// and returns the status code.
+pub(crate) fn ffi_status(result: io::Result<Vec<String>>, files_out: *mut *mut c_char, error_out: *mut *mut c_char) -> c_int {
+    let (status, out, message) = match result {
+        Ok(files) => (FFI_STATUS_OK, files_out, json!(files).to_string()),
+        Err(e) => (FFI_STATUS_ERROR, error_out, e.to_string()),
+    };
+    if let Some(out) = unsafe { out.as_mut() } {
+        // Interior nulls can only come from paths or messages the C string couldn't hold anyway
+        *out = CString::new(message.replace('\0', "")).unwrap().into_raw();
+    }
+    status
+}
+
+
+/// Frees a string handed out through an out-parameter of the `_status` FFI functions. Null is ignored.
+#[no_mangle]
+pub extern "C" fn free_ffi_string(string: *mut c_char) {
+    if !string.is_null() {
+        drop(unsafe { CString::from_raw(string) });
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    fn envelope(string: *mut c_char) -> serde_json::Value {
+        serde_json::from_str(&take_ffi_string(string).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn slow_extraction_times_out_with_an_envelope() {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let extraction_stopped = Arc::clone(&stopped);
+        let result = envelope(run_ffi_with_timeout(
+            move |cancel| {
+                while !cancel.load(Ordering::Relaxed) {
+                    thread::sleep(Duration::from_millis(5));
+                }
+                extraction_stopped.store(true, Ordering::Relaxed);
+                Ok(vec![])
+            },
+            20,
+        ));
+        assert_eq!(result["error"]["kind"], "TimedOut");
+        // The timeout is only reported once the extraction stopped
+        assert!(stopped.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn cancelled_extraction_stops_before_the_next_entry() {
+        let dir = temp_dir();
+        let dat_path = write_dat(dir.path(), "test.dat", &[("a.bin", b"a"), ("b.bin", b"b")]);
+        let extract_dir = dir.path().join("out");
+        let options = ExtractOptions { cancel: Some(Arc::new(AtomicBool::new(true))), ..Default::default() };
+        let e = extract_dat_files_with_options(&dat_path, &path_str(&extract_dir), &options).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::Interrupted);
+        assert!(!extract_dir.join("a.bin").exists());
+    }
+
+    #[test]
+    fn extraction_within_the_timeout_returns_the_files() {
+        let result = envelope(run_ffi_with_timeout(|_| Ok(vec!["a.bin".to_string()]), 5_000));
+        assert_eq!(result, json!(["a.bin"]));
+    }
+
+    #[test]
+    fn zero_timeout_waits_for_the_extraction() {
+        let result = envelope(run_ffi_with_timeout(
+            |_| {
+                thread::sleep(Duration::from_millis(50));
+                Ok(vec![])
+            },
+            0,
+        ));
+        assert_eq!(result, json!([]));
+    }
+
+    #[test]
+    fn failed_extraction_reports_its_error_kind() {
+        let result = envelope(run_ffi_with_timeout(|_| Err(io::Error::new(io::ErrorKind::NotFound, "gone")), 0));
+        assert_eq!(result["error"]["kind"], "NotFound");
+        assert_eq!(result["error"]["message"], "gone");
+    }
+
+    #[test]
+    fn ffi_timeout_extracts_a_dat() {
+        let dir = temp_dir();
+        let dat_path = c_string(&write_dat(dir.path(), "test.dat", &[("a.bin", b"abc")]));
+        let extract_dir = c_string(&path_str(&dir.path().join("out")));
+        let result = envelope(extract_dat_files_ffi_timeout(dat_path.as_ptr(), extract_dir.as_ptr(), 0, 5_000));
+        assert_eq!(result.as_array().unwrap().len(), 1);
+        assert_eq!(fs::read(dir.path().join("out/a.bin")).unwrap(), b"abc");
+    }
+
+    #[test]
+    fn sync_extraction_writes_the_entries_and_the_manifest() {
+        let dir = temp_dir();
+        let dat_path = write_dat(dir.path(), "test.dat", &[("b.bin", b"bbb"), ("a.txt", b"aa")]);
+        let extract_dir = dir.path().join("out");
+        let mut files = extract_dat_files_sync(&dat_path, &path_str(&extract_dir), false).unwrap();
+        files.sort();
+        assert_eq!(files, [path_str(&extract_dir.join("a.txt")), path_str(&extract_dir.join("b.bin"))]);
+        assert_eq!(fs::read(extract_dir.join("b.bin")).unwrap(), b"bbb");
+        assert!(extract_dir.join("dat_info.json").exists());
+    }
+
+    #[test]
+    fn sync_extraction_extracts_pak_entries_when_asked() {
+        let dir = temp_dir();
+        let yax = yax_bytes("<root><text>a</text></root>");
+        let pak = pak_bytes(&[TestPakEntry::raw(&yax)]);
+        let dat_path = write_dat(dir.path(), "test.dat", &[("test.pak", &pak)]);
+        let extract_dir = dir.path().join("out");
+        let files = extract_dat_files_sync(&dat_path, &path_str(&extract_dir), true).unwrap();
+        assert_eq!(files, [path_str(&extract_dir.join("test.pak"))]);
+        let pak_extract_dir = extract_dir.join(PAK_EXTRACT_SUBDIR).join("test.pak");
+        assert!(pak_extract_dir.join("pakInfo.json").exists());
+        assert!(fs::read_to_string(pak_extract_dir.join("0.xml")).unwrap().contains("<text>a</text>"));
+    }
+
+    #[tokio::test]
+    async fn async_extraction_matches_the_sync_one() {
+        let dir = temp_dir();
+        let dat_path = write_dat(dir.path(), "test.dat", &[("a.bin", b"abc")]);
+        let sync_files = extract_dat_files_sync(&dat_path, &path_str(&dir.path().join("sync")), false).unwrap();
+        let async_files = extract_dat_files(&dat_path, &path_str(&dir.path().join("async")), false).await.unwrap();
+        assert_eq!(fs::read(&sync_files[0]).unwrap(), fs::read(&async_files[0]).unwrap());
+    }
+
+    #[test]
+    fn sync_extraction_of_a_missing_dat_fails() {
+        let dir = temp_dir();
+        let e = extract_dat_files_sync(&path_str(&dir.path().join("missing.dat")), &path_str(&dir.path().join("out")), false).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+    }
+
+    /// Overwrites the extensions table entry of entry `index` of `dat`.
+    fn set_table_extension(dat: &mut [u8], index: usize, extension: &[u8]) {
+        let position = u32::from_le_bytes(dat[12..16].try_into().unwrap()) as usize + index * 4;
+        dat[position..position + 4].fill(0);
+        dat[position..position + extension.len()].copy_from_slice(extension);
+    }
+
+    #[test]
+    fn extension_table_wins_over_the_name_and_the_mismatch_is_recorded() {
+        let dir = temp_dir();
+        let mut dat = dat_bytes(&[("a.wtp", b"texture"), ("b.bin", b"bin")]);
+        set_table_extension(&mut dat, 0, b"wtb");
+        let dat_path = write_file(dir.path(), "test.dat", &dat);
+        let extract_dir = dir.path().join("out");
+        let options = ExtractOptions { quiet: true, ..Default::default() };
+        extract_dat_files_with_options(&dat_path, &path_str(&extract_dir), &options).unwrap();
+
+        assert_eq!(fs::read(extract_dir.join("a.wtb")).unwrap(), b"texture");
+        assert!(!extract_dir.join("a.wtp").exists());
+        let dat_info: serde_json::Value = serde_json::from_slice(&fs::read(extract_dir.join("dat_info.json")).unwrap()).unwrap();
+        assert_eq!(
+            dat_info["extension_mismatches"],
+            json!([{ "name": "a.wtp", "name_ext": "wtp", "table_ext": "wtb", "extracted_as": "a.wtb" }])
+        );
+    }
+
+    #[test]
+    fn matching_or_missing_table_extensions_keep_the_name() {
+        assert_eq!(reconcile_extension("a.wtp".to_string(), "wtp"), ("a.wtp".to_string(), None));
+        assert_eq!(reconcile_extension("a.wtp".to_string(), ""), ("a.wtp".to_string(), None));
+        assert_eq!(reconcile_extension("noext".to_string(), "bin"), ("noext".to_string(), None));
+    }
+
+    #[test]
+    fn dat_without_mismatches_has_no_mismatch_list() {
+        let dir = temp_dir();
+        let dat_path = write_dat(dir.path(), "test.dat", &[("a.bin", b"abc")]);
+        let extract_dir = dir.path().join("out");
+        extract_dat_files_sync(&dat_path, &path_str(&extract_dir), false).unwrap();
+        let dat_info: serde_json::Value = serde_json::from_slice(&fs::read(extract_dir.join("dat_info.json")).unwrap()).unwrap();
+        assert!(dat_info.get("extension_mismatches").is_none());
+    }
+
+    #[test]
+    fn read_cstring_reads_names_in_sequence() {
+        let mut bytes = ByteDataWrapper { data: b"a.bin\0long_name.wtp\0\0c\0".to_vec(), position: 0 };
+        let names: Vec<String> = (0..4).map(|_| bytes.read_cstring(true).unwrap()).collect();
+        assert_eq!(names, ["a.bin", "long_name.wtp", "", "c"]);
+        assert_eq!(bytes.position, bytes.data.len());
+    }
+
+    #[test]
+    fn unterminated_cstring_is_unexpected_eof() {
+        let mut bytes = ByteDataWrapper { data: b"abc".to_vec(), position: 0 };
+        assert_eq!(bytes.read_cstring(false).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn null_terminated_name_table_extracts_variable_length_names() {
+        let dir = temp_dir();
+        let mut dat = dat_bytes(&[("a.bin", b"aaa"), ("long_name.bin", b"bbb")]);
+        // Replace the fixed width table (u32 length, 2 names of 14 bytes) by names back to back
+        let names_offset = u32::from_le_bytes(dat[16..20].try_into().unwrap()) as usize;
+        let names = b"a.bin\0long_name.bin\0";
+        dat[names_offset..names_offset + 4 + 2 * 14].fill(0);
+        dat[names_offset..names_offset + names.len()].copy_from_slice(names);
+        let dat_path = write_file(dir.path(), "test.dat", &dat);
+        let extract_dir = dir.path().join("out");
+        let options = ExtractOptions { name_table_format: NameTableFormat::NullTerminated, ..Default::default() };
+        extract_dat_files_with_options(&dat_path, &path_str(&extract_dir), &options).unwrap();
+
+        assert_eq!(fs::read(extract_dir.join("a.bin")).unwrap(), b"aaa");
+        assert_eq!(fs::read(extract_dir.join("long_name.bin")).unwrap(), b"bbb");
+    }
+
+    #[test]
+    fn resume_rewrites_truncated_entries_and_skips_complete_ones() {
+        let dir = temp_dir();
+        let dat_path = write_dat(dir.path(), "test.dat", &[("a.bin", b"aaaa"), ("b.bin", b"bbbb")]);
+        let extract_dir = dir.path().join("out");
+        extract_dat_files_sync(&dat_path, &path_str(&extract_dir), false).unwrap();
+        // An interrupted extraction: a.bin cut short, b.bin complete (marked to see whether it is rewritten)
+        fs::write(extract_dir.join("a.bin"), b"aa").unwrap();
+        fs::write(extract_dir.join("b.bin"), b"BBBB").unwrap();
+
+        let options = ExtractOptions { resume: true, ..Default::default() };
+        extract_dat_files_with_options(&dat_path, &path_str(&extract_dir), &options).unwrap();
+        assert_eq!(fs::read(extract_dir.join("a.bin")).unwrap(), b"aaaa");
+        assert_eq!(fs::read(extract_dir.join("b.bin")).unwrap(), b"BBBB");
+    }
+
+    #[test]
+    fn without_resume_complete_entries_are_rewritten() {
+        let dir = temp_dir();
+        let dat_path = write_dat(dir.path(), "test.dat", &[("b.bin", b"bbbb")]);
+        let extract_dir = dir.path().join("out");
+        extract_dat_files_sync(&dat_path, &path_str(&extract_dir), false).unwrap();
+        fs::write(extract_dir.join("b.bin"), b"BBBB").unwrap();
+        extract_dat_files_sync(&dat_path, &path_str(&extract_dir), false).unwrap();
+        assert_eq!(fs::read(extract_dir.join("b.bin")).unwrap(), b"bbbb");
+    }
+
+    #[test]
+    fn atomic_write_leaves_no_temporary_file() {
+        let dir = temp_dir();
+        let path = dir.path().join("a.bin");
+        write_file_atomic(&path, &[7; 100], 16).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), [7; 100]);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn failed_atomic_write_keeps_the_previous_file() {
+        let dir = temp_dir();
+        let path = dir.path().join("a.bin");
+        fs::write(&path, b"complete").unwrap();
+        // The temporary file can't be created where a directory is in the way
+        fs::create_dir(dir.path().join(".a.bin.tmp")).unwrap();
+        write_file_atomic(&path, b"new data", 4).unwrap_err();
+        assert_eq!(fs::read(&path).unwrap(), b"complete");
+    }
+
+    #[test]
+    fn failed_rename_removes_the_partial_temporary_file() {
+        let dir = temp_dir();
+        // Renaming onto a non-empty directory fails after the data was written
+        let path = dir.path().join("a.bin");
+        fs::create_dir(&path).unwrap();
+        fs::write(path.join("inner"), b"x").unwrap();
+        let e = write_file_atomic(&path, b"data", 4).unwrap_err();
+        assert!(e.to_string().contains("a.bin"), "{}", e);
+        assert!(!dir.path().join(".a.bin.tmp").exists());
+    }
+
+    #[test]
+    fn empty_dat_warning_is_printed_unless_quiet() {
+        if in_child_test() {
+            let dir = temp_dir();
+            let dat_path = write_file(dir.path(), "empty.dat", b"");
+            let options = ExtractOptions { quiet: std::env::var_os("QUIET").is_some(), ..Default::default() };
+            let files = extract_dat_files_with_options(&dat_path, &path_str(&dir.path().join("out")), &options).unwrap();
+            assert!(files.is_empty());
+            return;
+        }
+        let test = "tests::empty_dat_warning_is_printed_unless_quiet";
+        assert!(child_test_stderr(test, &[]).contains("Warning: Empty DAT file"));
+        assert!(!child_test_stderr(test, &[("QUIET", "1")]).contains("Warning"));
+    }
+
+    #[test]
+    fn handler_sees_every_entry_in_table_order_without_writing_files() {
+        let dir = temp_dir();
+        let dat_path = write_dat(dir.path(), "test.dat", &[("b.bin", b"bb"), ("a.txt", b"aaaa"), ("c.wtp", b"")]);
+        let mut entries = Vec::new();
+        extract_dat_files_with_handler(&dat_path, |name, data| {
+            entries.push((name.to_string(), data.len()));
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(entries, [("b.bin".to_string(), 2), ("a.txt".to_string(), 4), ("c.wtp".to_string(), 0)]);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn handler_error_stops_the_extraction() {
+        let dir = temp_dir();
+        let dat_path = write_dat(dir.path(), "test.dat", &[("a.bin", b"a"), ("b.bin", b"b")]);
+        let mut calls = 0;
+        let e = extract_dat_files_with_handler(&dat_path, |_, _| {
+            calls += 1;
+            Err(io::Error::other("stop"))
+        })
+        .unwrap_err();
+        assert_eq!(e.to_string(), "stop");
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn handler_is_not_called_for_an_empty_dat() {
+        let dir = temp_dir();
+        let dat_path = write_file(dir.path(), "empty.dat", b"");
+        extract_dat_files_with_handler(&dat_path, |name, _| panic!("called for {}", name)).unwrap();
+    }
+
+    /// A DAT whose single entry is named `a<0xff>.bin`.
+    fn dat_with_invalid_utf8_name() -> Vec<u8> {
+        let mut dat = dat_bytes(&[("ab.bin", b"data")]);
+        let names_offset = u32::from_le_bytes(dat[16..20].try_into().unwrap()) as usize;
+        dat[names_offset + 5] = 0xff;
+        dat
+    }
+
+    #[test]
+    fn strict_utf8_rejects_invalid_name_bytes() {
+        let dir = temp_dir();
+        let dat_path = write_file(dir.path(), "test.dat", &dat_with_invalid_utf8_name());
+        let extract_dir = dir.path().join("out");
+        let options = ExtractOptions { strict_utf8: true, ..Default::default() };
+        let e = extract_dat_files_with_options(&dat_path, &path_str(&extract_dir), &options).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(e.to_string().contains("not valid UTF-8"), "{}", e);
+        assert!(!extract_dir.exists());
+    }
+
+    #[test]
+    fn invalid_name_bytes_are_replaced_by_default() {
+        let dir = temp_dir();
+        let dat_path = write_file(dir.path(), "test.dat", &dat_with_invalid_utf8_name());
+        let extract_dir = dir.path().join("out");
+        extract_dat_files_sync(&dat_path, &path_str(&extract_dir), false).unwrap();
+        assert_eq!(fs::read(extract_dir.join("a\u{fffd}.bin")).unwrap(), b"data");
+    }
+
+    #[test]
+    fn strict_utf8_accepts_non_ascii_names() {
+        assert_eq!(decode_name("宝箱.bin\0\0".as_bytes(), true).unwrap(), "宝箱.bin");
+    }
+
+    #[test]
+    fn slices_and_tables_near_usize_max_are_errors() {
+        let bytes = ByteDataWrapper { data: vec![0; 16], position: 0 };
+        assert_eq!(bytes.slice(usize::MAX - 1, 4).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(bytes.table(0, usize::MAX / 2, 4).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let mut bytes = ByteDataWrapper { data: vec![0; 16], position: usize::MAX - 2 };
+        assert!(bytes.read_u32().is_err());
+        assert!(bytes.read_bytes(8).is_err());
+    }
+
+    #[test]
+    fn dat_entry_offset_near_u32_max_is_reported() {
+        let dir = temp_dir();
+        let mut dat = dat_bytes(&[("a.bin", b"abcd")]);
+        let offsets_offset = u32::from_le_bytes(dat[8..12].try_into().unwrap()) as usize;
+        dat[offsets_offset..offsets_offset + 4].copy_from_slice(&(u32::MAX - 2).to_le_bytes());
+        let dat_path = write_file(dir.path(), "test.dat", &dat);
+        let e = extract_dat_files_sync(&dat_path, &path_str(&dir.path().join("out")), false).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn zlib_entries_are_written_decompressed_and_listed() {
+        let dir = temp_dir();
+        let text = b"compressed text ".repeat(20);
+        let dat_path = write_dat(dir.path(), "test.dat", &[("a.bin", &zlib(&text)), ("b.bin", b"raw")]);
+        let extract_dir = dir.path().join("out");
+        let options = ExtractOptions { decompress_entries: true, ..Default::default() };
+        extract_dat_files_with_options(&dat_path, &path_str(&extract_dir), &options).unwrap();
+
+        assert_eq!(fs::read(extract_dir.join("a.bin")).unwrap(), text);
+        assert_eq!(fs::read(extract_dir.join("b.bin")).unwrap(), b"raw");
+        let dat_info: serde_json::Value = serde_json::from_slice(&fs::read(extract_dir.join("dat_info.json")).unwrap()).unwrap();
+        assert_eq!(dat_info["decompressed_entries"], json!([{ "name": "a.bin", "level": 6 }]));
+    }
+
+    #[test]
+    fn zlib_entries_stay_compressed_by_default() {
+        let dir = temp_dir();
+        let compressed = zlib(b"text");
+        let dat_path = write_dat(dir.path(), "test.dat", &[("a.bin", &compressed)]);
+        let extract_dir = dir.path().join("out");
+        extract_dat_files_sync(&dat_path, &path_str(&extract_dir), false).unwrap();
+        assert_eq!(fs::read(extract_dir.join("a.bin")).unwrap(), compressed);
+    }
+
+    #[test]
+    fn data_that_only_looks_like_zlib_is_not_decompressed() {
+        // A valid zlib header followed by garbage
+        assert_eq!(decompress_zlib_entry(b"\x78\x9cgarbage"), None);
+        // A complete stream with trailing bytes
+        let mut trailing = zlib(b"text");
+        trailing.extend_from_slice(b"more");
+        assert_eq!(decompress_zlib_entry(&trailing), None);
+        assert_eq!(decompress_zlib_entry(b"plain"), None);
+        assert_eq!(decompress_zlib_entry(&zlib(b"text")).unwrap(), b"text");
+    }
+
+    #[tokio::test]
+    async fn file_count_reads_the_header() {
+        let dir = temp_dir();
+        let dat = dat_bytes(&[("a.bin", b"a"), ("b.bin", b"b"), ("c.bin", b"c")]);
+        assert_eq!(dat_file_count(&write_file(dir.path(), "test.dat", &dat)).await.unwrap(), 3);
+        // The tables aren't read, a DAT cut off after its header still has a count
+        assert_eq!(dat_file_count(&write_file(dir.path(), "cut.dat", &dat[..DAT_HEADER_SIZE as usize])).await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn file_count_of_empty_and_zlib_wrapped_dats() {
+        let dir = temp_dir();
+        assert_eq!(dat_file_count(&write_file(dir.path(), "empty.dat", b"")).await.unwrap(), 0);
+        let wrapped = zlib(&dat_bytes(&[("a.bin", b"a"), ("b.bin", b"b")]));
+        assert_eq!(dat_file_count(&write_file(dir.path(), "wrapped.dat", &wrapped)).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn file_count_of_a_missing_dat_fails() {
+        let dir = temp_dir();
+        let e = dat_file_count(&path_str(&dir.path().join("missing.dat"))).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn unknown_entry_type_gets_a_hex_side_car() {
+        let dir = temp_dir();
+        let data: Vec<u8> = (0..40).collect();
+        let dat_path = write_dat(dir.path(), "test.dat", &[("a.xyz", &data), ("b.wtp", b"texture")]);
+        let extract_dir = dir.path().join("out");
+        let options = ExtractOptions { dump_unknown: Some(20), ..Default::default() };
+        let files = extract_dat_files_with_options(&dat_path, &path_str(&extract_dir), &options).unwrap();
+
+        assert_eq!(fs::read(extract_dir.join("a.xyz")).unwrap(), data);
+        assert_eq!(
+            fs::read_to_string(extract_dir.join("a.xyz.hex")).unwrap(),
+            "00000000  00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f  |................|\n\
+             00000010  10 11 12 13                                      |....|\n"
+        );
+        assert!(!extract_dir.join("b.wtp.hex").exists());
+        // The dumps aren't entries
+        assert_eq!(files.len(), 2);
+    }
+
+    #[test]
+    fn hex_dump_shows_printable_ascii() {
+        assert_eq!(hex_dump(b"Hi !\x7f"), format!("00000000  {:<47}  |Hi !.|\n", "48 69 20 21 7f"));
+        assert_eq!(hex_dump(b""), "");
+    }
+
+    #[test]
+    fn no_hex_side_cars_by_default() {
+        let dir = temp_dir();
+        let dat_path = write_dat(dir.path(), "test.dat", &[("a.xyz", b"data")]);
+        let extract_dir = dir.path().join("out");
+        extract_dat_files_sync(&dat_path, &path_str(&extract_dir), false).unwrap();
+        assert!(!extract_dir.join("a.xyz.hex").exists());
+    }
+
+    /// An effect archive: the DAT layout with an `EFF\0` magic and no extensions table.
+    fn eff_bytes(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut eff = dat_bytes(entries);
+        eff[..4].copy_from_slice(b"EFF\0");
+        eff[12..16].fill(0);
+        eff
+    }
+
+    #[test]
+    fn eff_without_extensions_table_extracts_as_eff() {
+        let dir = temp_dir();
+        let eff_path = write_file(dir.path(), "effect.eff", &eff_bytes(&[("a.est", b"effect"), ("b.wtp", b"texture")]));
+        let extract_dir = dir.path().join("out");
+        extract_dat_files_sync(&eff_path, &path_str(&extract_dir), false).unwrap();
+
+        assert_eq!(fs::read(extract_dir.join("a.est")).unwrap(), b"effect");
+        assert_eq!(fs::read(extract_dir.join("b.wtp")).unwrap(), b"texture");
+        let dat_info: serde_json::Value = serde_json::from_slice(&fs::read(extract_dir.join("dat_info.json")).unwrap()).unwrap();
+        assert_eq!(dat_info["container_kind"], "eff");
+        assert_eq!(dat_info["ext"], "eff");
+    }
+
+    #[test]
+    fn container_kind_comes_from_the_magic() {
+        let dir = temp_dir();
+        let kind = |name: &str, data: &[u8]| read_dat_header_info(&write_file(dir.path(), name, data)).unwrap().unwrap().container_kind;
+        assert_eq!(kind("a.dat", &dat_bytes(&[("a.bin", b"a")])), ContainerKind::Dat);
+        assert_eq!(kind("a.eff", &eff_bytes(&[("a.bin", b"a")])), ContainerKind::Eff);
+        let mut other = dat_bytes(&[("a.bin", b"a")]);
+        other[..4].copy_from_slice(b"XYZ\0");
+        assert_eq!(kind("a.xyz", &other), ContainerKind::Unknown);
+    }
+
+    /// Collects what an extraction writes in memory.
+    #[derive(Default)]
+    struct MockSink {
+        entries: Vec<(String, Vec<u8>)>,
+        manifest: Option<String>,
+    }
+
+    impl OutputSink for MockSink {
+        fn write_entry(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+            self.entries.push((name.to_string(), data.to_vec()));
+            Ok(())
+        }
+
+        fn write_manifest(&mut self, json: &str) -> io::Result<()> {
+            self.manifest = Some(json.to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn sink_receives_the_entries_and_the_manifest() {
+        let dir = temp_dir();
+        let dat_path = write_dat(dir.path(), "test.dat", &[("b.bin", b"bb"), ("a.txt", b"a")]);
+        let mut sink = MockSink::default();
+        let names = extract_dat_files_to_sink(&dat_path, &mut sink, &ExtractOptions::default()).unwrap();
+
+        assert_eq!(sink.entries, [("b.bin".to_string(), b"bb".to_vec()), ("a.txt".to_string(), b"a".to_vec())]);
+        let manifest: serde_json::Value = serde_json::from_str(&sink.manifest.unwrap()).unwrap();
+        assert_eq!(manifest["files"], json!(names));
+        assert_eq!(manifest["basename"], "test");
+        // Nothing but the DAT itself was written to disk
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn detailed_result_has_sizes_extensions_and_pak_results() {
+        let dir = temp_dir();
+        let yax = yax_bytes("<root><text>a</text></root>");
+        let pak = pak_bytes(&[TestPakEntry::raw(&yax), TestPakEntry::raw(&yax)]);
+        let dat_path = write_dat(dir.path(), "test.dat", &[("b.pak", &pak), ("a.wtp", b"texture"), ("noext", b"")]);
+        let extract_dir = dir.path().join("out");
+        let options = ExtractOptions { extract_pak_files: true, ..Default::default() };
+        let result = extract_dat_files_detailed(&dat_path, &path_str(&extract_dir), &options).unwrap();
+
+        let files: Vec<(&str, u64, &str)> = result.files.iter().map(|file| (file.name.as_str(), file.size, file.ext.as_str())).collect();
+        assert_eq!(files, [("a.wtp", 7, "wtp"), ("b.pak", pak.len() as u64, "pak"), ("noext", 0, "")]);
+        assert_eq!(result.files[0].path, path_str(&extract_dir.join("a.wtp")));
+        assert_eq!(result.manifest_path, path_str(&extract_dir.join("dat_info.json")));
+
+        let [pak_result] = &result.pak_results[..] else { panic!("{:?}", result.pak_results) };
+        let pak_dir = extract_dir.join(PAK_EXTRACT_SUBDIR).join("b.pak");
+        assert_eq!(pak_result.manifest_path, path_str(&pak_dir.join("pakInfo.json")));
+        let pak_files: Vec<(&str, u64)> = pak_result.files.iter().map(|file| (file.name.as_str(), file.size)).collect();
+        assert_eq!(pak_files, [("0.yax", yax.len() as u64), ("1.yax", yax.len() as u64)]);
+    }
+
+    #[test]
+    fn detailed_result_without_pak_extraction_has_no_pak_results() {
+        let dir = temp_dir();
+        let pak = pak_bytes(&[TestPakEntry::raw(b"abcd")]);
+        let dat_path = write_dat(dir.path(), "test.dat", &[("b.pak", &pak)]);
+        let result = extract_dat_files_detailed(&dat_path, &path_str(&dir.path().join("out")), &ExtractOptions::default()).unwrap();
+        assert_eq!(result.files.len(), 1);
+        assert!(result.pak_results.is_empty());
+    }
+
+    #[test]
+    fn hash_map_of_a_built_dat_is_consistent() {
+        let dir = temp_dir();
+        let names: Vec<String> = (0..40).map(|i| format!("entry_{}.bin", i)).collect();
+        let entries: Vec<(&str, &[u8])> = names.iter().map(|name| (name.as_str(), &b"data"[..])).collect();
+        assert!(verify_dat_hashmap(&write_dat(dir.path(), "many.dat", &entries)).unwrap());
+        assert!(verify_dat_hashmap(&write_dat(dir.path(), "one.dat", &[("a.bin", b"a")])).unwrap());
+    }
+
+    #[test]
+    fn renamed_entry_breaks_the_hash_map() {
+        let dir = temp_dir();
+        let mut dat = dat_bytes(&[("a.bin", b"a"), ("b.bin", b"b")]);
+        // Rename a.bin to z.bin in the name table only
+        let names_offset = u32::from_le_bytes(dat[16..20].try_into().unwrap()) as usize;
+        dat[names_offset + 4] = b'z';
+        assert!(!verify_dat_hashmap(&write_file(dir.path(), "test.dat", &dat)).unwrap());
+    }
+
+    #[test]
+    fn dat_without_hash_map_fails_verification() {
+        let dir = temp_dir();
+        let mut dat = dat_bytes(&[("a.bin", b"a")]);
+        dat[24..28].fill(0);
+        assert!(!verify_dat_hashmap(&write_file(dir.path(), "test.dat", &dat)).unwrap());
+    }
+
+    thread_local! {
+        /// What the mock callbacks were called with. The callbacks run on the thread that called the FFI function.
+        static CALLBACK_EVENTS: std::cell::RefCell<Vec<String>> = const { std::cell::RefCell::new(Vec::new()) };
+    }
+
+    fn record(event: String) {
+        CALLBACK_EVENTS.with(|events| events.borrow_mut().push(event));
+    }
+
+    fn take_callback_events() -> Vec<String> {
+        CALLBACK_EVENTS.with(|events| events.take())
+    }
+
+    extern "C" fn mock_on_start(total_entries: c_uint) {
+        record(format!("start {}", total_entries));
+    }
+
+    extern "C" fn mock_on_entry(name: *const c_char, index: c_uint, size: u64) {
+        let name = unsafe { CStr::from_ptr(name) }.to_str().unwrap();
+        record(format!("entry {} {} {}", name, index, size));
+    }
+
+    extern "C" fn mock_on_complete(extracted_count: c_uint) {
+        record(format!("complete {}", extracted_count));
+    }
+
+    extern "C" fn mock_on_error(message: *const c_char) {
+        record(format!("error {}", unsafe { CStr::from_ptr(message) }.to_str().unwrap()));
+    }
+
+    const MOCK_CALLBACKS: ExtractionCallbacks = ExtractionCallbacks {
+        on_start: Some(mock_on_start),
+        on_entry: Some(mock_on_entry),
+        on_complete: Some(mock_on_complete),
+        on_error: Some(mock_on_error),
+    };
+
+    #[test]
+    fn callbacks_follow_the_extraction_of_the_dat_entries() {
+        let dir = temp_dir();
+        let yax = yax_bytes("<root><text>a</text></root>");
+        let pak = pak_bytes(&[TestPakEntry::raw(&yax)]);
+        let dat_path = c_string(&write_dat(dir.path(), "test.dat", &[("b.bin", b"bb"), ("a.pak", &pak)]));
+        let extract_dir = c_string(&path_str(&dir.path().join("out")));
+        let result = envelope(extract_dat_files_ffi_callbacks(dat_path.as_ptr(), extract_dir.as_ptr(), 1, &MOCK_CALLBACKS));
+
+        assert_eq!(result.as_array().unwrap().len(), 2);
+        // The YAX files of the PAK aren't DAT entries
+        assert_eq!(
+            take_callback_events(),
+            ["start 2".to_string(), "entry b.bin 0 2".to_string(), format!("entry a.pak 1 {}", pak.len()), "complete 2".to_string()]
+        );
+    }
+
+    #[test]
+    fn empty_dat_starts_and_completes_with_no_entries() {
+        let dir = temp_dir();
+        let dat_path = c_string(&write_file(dir.path(), "empty.dat", b""));
+        let extract_dir = c_string(&path_str(&dir.path().join("out")));
+        let result = envelope(extract_dat_files_ffi_callbacks(dat_path.as_ptr(), extract_dir.as_ptr(), 0, &MOCK_CALLBACKS));
+        assert!(result.as_array().unwrap().is_empty());
+        assert_eq!(take_callback_events(), ["start 0", "complete 0"]);
+    }
+
+    #[test]
+    fn failed_extraction_calls_on_error_and_returns_the_envelope() {
+        let dir = temp_dir();
+        let dat_path = c_string(&path_str(&dir.path().join("missing.dat")));
+        let extract_dir = c_string(&path_str(&dir.path().join("out")));
+        let result = envelope(extract_dat_files_ffi_callbacks(dat_path.as_ptr(), extract_dir.as_ptr(), 0, &MOCK_CALLBACKS));
+
+        assert_eq!(result["error"]["kind"], "NotFound");
+        let events = take_callback_events();
+        assert_eq!(events, [format!("error {}", result["error"]["message"].as_str().unwrap())]);
+    }
+
+    #[test]
+    fn null_callbacks_and_null_function_pointers_are_skipped() {
+        let dir = temp_dir();
+        let dat_path = c_string(&write_dat(dir.path(), "test.dat", &[("a.bin", b"a")]));
+        let extract_dir = c_string(&path_str(&dir.path().join("out")));
+        let result = envelope(extract_dat_files_ffi_callbacks(dat_path.as_ptr(), extract_dir.as_ptr(), 0, std::ptr::null()));
+        assert_eq!(result.as_array().unwrap().len(), 1);
+
+        let only_complete = ExtractionCallbacks { on_complete: Some(mock_on_complete), ..Default::default() };
+        envelope(extract_dat_files_ffi_callbacks(dat_path.as_ptr(), extract_dir.as_ptr(), 0, &only_complete));
+        assert_eq!(take_callback_events(), ["complete 1"]);
+    }
+
+    #[test]
+    fn null_path_is_an_envelope_without_callbacks() {
+        let extract_dir = c_string("out");
+        let result = envelope(extract_dat_files_ffi_callbacks(std::ptr::null(), extract_dir.as_ptr(), 0, &MOCK_CALLBACKS));
+        assert_eq!(result["error"]["kind"], "InvalidInput");
+        assert!(take_callback_events().is_empty());
+    }
+
+    #[test]
+    fn index_prefix_keeps_order_and_colliding_names() {
+        let dir = temp_dir();
+        let dat_path = write_dat(dir.path(), "test.dat", &[("z.wmb", b"first"), ("a.wmb", b"second"), ("z.wmb", b"third")]);
+        let extract_dir = dir.path().join("out");
+        let options = ExtractOptions { index_prefix: true, ..Default::default() };
+        let files = extract_dat_files_with_options(&dat_path, &path_str(&extract_dir), &options).unwrap();
+
+        let names = ["000_z.wmb", "001_a.wmb", "002_z.wmb"];
+        assert_eq!(files, names.map(|name| path_str(&extract_dir.join(name))));
+        assert_eq!(fs::read(extract_dir.join("002_z.wmb")).unwrap(), b"third");
+        let dat_info: serde_json::Value = serde_json::from_slice(&fs::read(extract_dir.join("dat_info.json")).unwrap()).unwrap();
+        assert_eq!(
+            dat_info["index_prefixed"],
+            json!([
+                { "name": "z.wmb", "extracted_as": "000_z.wmb" },
+                { "name": "a.wmb", "extracted_as": "001_a.wmb" },
+                { "name": "z.wmb", "extracted_as": "002_z.wmb" },
+            ])
+        );
+    }
+
+    #[test]
+    fn index_prefix_widens_for_a_thousand_entries() {
+        let dir = temp_dir();
+        let names: Vec<String> = (0..1001).map(|i| format!("{}.bin", i)).collect();
+        let entries: Vec<(&str, &[u8])> = names.iter().map(|name| (name.as_str(), &b""[..])).collect();
+        let dat_path = write_dat(dir.path(), "test.dat", &entries);
+        let mut sink = MockSink::default();
+        let options = ExtractOptions { index_prefix: true, ..Default::default() };
+        let written = extract_dat_files_to_sink(&dat_path, &mut sink, &options).unwrap();
+        assert_eq!(written[0], "0000_0.bin");
+        assert_eq!(written[1000], "1000_1000.bin");
+    }
+
+    fn invalid_utf8_path() -> CString {
+        CString::new(b"\xff\xfe.dat".to_vec()).unwrap()
+    }
+
+    #[test]
+    fn invalid_utf8_path_is_an_invalid_input_envelope() {
+        let extract_dir = c_string("out");
+        let result = envelope(extract_dat_files_ffi_timeout(invalid_utf8_path().as_ptr(), extract_dir.as_ptr(), 0, 0));
+        assert_eq!(result["error"]["kind"], "InvalidInput");
+        assert!(result["error"]["message"].as_str().unwrap().starts_with("dat_path is not valid UTF-8"));
+
+        let result = envelope(extract_dat_files_ffi_timeout(extract_dir.as_ptr(), std::ptr::null(), 0, 0));
+        assert_eq!(result["error"]["message"], "extract_dir is null");
+    }
+
+    #[test]
+    fn legacy_ffi_returns_null_for_invalid_paths() {
+        let extract_dir = c_string("out");
+        assert!(extract_dat_files_ffi(invalid_utf8_path().as_ptr(), extract_dir.as_ptr(), 0).is_null());
+        assert!(extract_dat_files_ffi(std::ptr::null(), extract_dir.as_ptr(), 0).is_null());
+    }
+
+    #[test]
+    fn status_ffi_reports_invalid_paths_through_error_out() {
+        let extract_dir = c_string("out");
+        let (mut files, mut error) = (std::ptr::null_mut(), std::ptr::null_mut());
+        let status = extract_dat_files_ffi_status(invalid_utf8_path().as_ptr(), extract_dir.as_ptr(), 0, &mut files, &mut error);
+        assert_eq!(status, FFI_STATUS_ERROR);
+        assert!(files.is_null());
+        assert!(take_ffi_string(error).unwrap().starts_with("dat_path is not valid UTF-8"));
+    }
+
+    #[test]
+    fn status_ffi_hands_out_the_file_list_through_files_out() {
+        let dir = temp_dir();
+        let dat_path = c_string(&write_dat(dir.path(), "test.dat", &[("a.bin", b"abc")]));
+        let extract_dir = dir.path().join("out");
+        let (mut files, mut error) = (std::ptr::null_mut(), std::ptr::null_mut());
+        let status = extract_dat_files_ffi_status(dat_path.as_ptr(), c_string(&path_str(&extract_dir)).as_ptr(), 0, &mut files, &mut error);
+        assert_eq!(status, FFI_STATUS_OK);
+        assert!(error.is_null());
+        let files: Vec<String> = serde_json::from_str(&take_ffi_string(files).unwrap()).unwrap();
+        assert_eq!(files, [path_str(&extract_dir.join("a.bin"))]);
+    }
+
+    #[test]
+    fn status_ffi_accepts_null_out_parameters() {
+        let extract_dir = c_string("out");
+        let status = extract_dat_files_ffi_status(std::ptr::null(), extract_dir.as_ptr(), 0, std::ptr::null_mut(), std::ptr::null_mut());
+        assert_eq!(status, FFI_STATUS_ERROR);
+    }
+
+    /// `value` as the null-terminated UTF-16 a `_w` FFI function takes.
+    #[cfg(windows)]
+    fn wide(value: &str) -> Vec<u16> {
+        value.encode_utf16().chain([0]).collect()
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn wide_path_ffi_extracts_into_a_non_ascii_directory() {
+        let dir = temp_dir();
+        let dat_path = wide(&write_dat(dir.path(), "テスト.dat", &[("a.bin", b"abc")]));
+        let extract_dir = dir.path().join("ユーザー").join("out");
+        let result = take_ffi_string(extract_dat_files_ffi_w(dat_path.as_ptr(), wide(&path_str(&extract_dir)).as_ptr(), 0)).unwrap();
+        let files: Vec<String> = serde_json::from_str(&result).unwrap();
+        assert_eq!(files, [path_str(&extract_dir.join("a.bin"))]);
+        assert_eq!(fs::read(extract_dir.join("a.bin")).unwrap(), b"abc");
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn unpaired_surrogate_in_a_wide_path_is_invalid_input() {
+        let path = [0x61, 0xD800, 0x62, 0];
+        let e = ffi_wide_path(path.as_ptr(), "dat_path").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(e.to_string(), "dat_path is not valid UTF-16");
+        assert!(extract_dat_files_ffi_w(path.as_ptr(), wide("out").as_ptr(), 0).is_null());
+        assert!(extract_dat_files_ffi_w(std::ptr::null(), wide("out").as_ptr(), 0).is_null());
+    }
+
+    #[test]
+    fn small_write_buffer_writes_the_same_files() {
+        let dir = temp_dir();
+        let data: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+        let dat_path = write_dat(dir.path(), "test.dat", &[("a.bin", &data)]);
+        let extract_dir = dir.path().join("out");
+        let options = ExtractOptions { buffer_size: Some(7), ..Default::default() };
+        extract_dat_files_with_options(&dat_path, &path_str(&extract_dir), &options).unwrap();
+        assert_eq!(fs::read(extract_dir.join("a.bin")).unwrap(), data);
+    }
+
+    #[test]
+    fn with_retries_retries_transient_errors() {
+        let mut calls = 0;
+        let result = with_retries(3, 0, || {
+            calls += 1;
+            if calls == 1 { Err(io::Error::from(io::ErrorKind::Interrupted)) } else { Ok(calls) }
+        });
+        assert_eq!(result.unwrap(), 2);
+
+        let mut calls = 0;
+        let result: io::Result<()> = with_retries(2, 0, || {
+            calls += 1;
+            Err(io::Error::from(io::ErrorKind::Interrupted))
+        });
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::Interrupted);
+        assert_eq!(calls, 3);
+    }
+
+    #[test]
+    fn with_retries_does_not_retry_permanent_errors() {
+        let mut calls = 0;
+        let result: io::Result<()> = with_retries(3, 0, || {
+            calls += 1;
+            Err(io::Error::from(io::ErrorKind::NotFound))
+        });
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn absurd_file_number_is_invalid_data() {
+        let dir = temp_dir();
+        let mut dat = dat_bytes(&[("a.bin", b"data")]);
+        dat[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        let dat_path = write_file(dir.path(), "a.dat", &dat);
+        let extract_dir = path_str(&dir.path().join("out"));
+
+        let error = extract_dat_files_sync(&dat_path, &extract_dir, false).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains(&u32::MAX.to_string()), "{}", error);
+        assert_eq!(verify_dat_hashmap(&dat_path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(!dir.path().join("out").join("a.bin").exists());
+    }
+
+    #[test]
+    fn tail_waits_for_a_growing_dat() {
+        let dir = temp_dir();
+        let dat = dat_bytes(&[("a.bin", b"abc"), ("b.bin", &[7; 1000])]);
+        let dat_path = write_file(dir.path(), "a.dat", &dat[..dat.len() - 500]);
+        let writer = {
+            let dat_path = dat_path.clone();
+            let dat = dat.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(150));
+                fs::write(&dat_path, &dat[..12]).unwrap();
+                thread::sleep(Duration::from_millis(150));
+                fs::write(&dat_path, &dat).unwrap();
+            })
+        };
+        let options = ExtractOptions { tail: true, tail_timeout_ms: 10_000, ..Default::default() };
+        let extract_dir = dir.path().join("out");
+        let result = extract_dat_files_with_options(&dat_path, &path_str(&extract_dir), &options);
+        writer.join().unwrap();
+
+        assert_eq!(result.unwrap().len(), 2);
+        assert_eq!(fs::read(extract_dir.join("b.bin")).unwrap(), [7; 1000]);
+    }
+
+    #[test]
+    fn tail_gives_up_after_the_timeout() {
+        let dir = temp_dir();
+        let dat = dat_bytes(&[("a.bin", &[7; 1000])]);
+        let dat_path = write_file(dir.path(), "a.dat", &dat[..dat.len() - 1]);
+        let options = ExtractOptions { tail: true, tail_timeout_ms: 100, ..Default::default() };
+
+        let start = std::time::Instant::now();
+        let error = extract_dat_files_with_options(&dat_path, &path_str(&dir.path().join("out")), &options).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(error.to_string().contains("didn't grow within 100 ms"), "{}", error);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn failed_write_names_the_entry_and_path() {
+        let dir = temp_dir();
+        let dat_path = write_dat(dir.path(), "a.dat", &[("a.bin", b"abc"), ("b.bin", b"def")]);
+        let extract_dir = dir.path().join("out");
+        // A directory in the way makes writing b.bin fail
+        fs::create_dir_all(extract_dir.join("b.bin").join("blocker")).unwrap();
+
+        let error = extract_dat_files_sync(&dat_path, &path_str(&extract_dir), false).unwrap_err();
+        let message = error.to_string();
+        assert!(message.contains("DAT entry 1 (b.bin)"), "{}", message);
+        assert!(message.contains(&path_str(&extract_dir.join("b.bin"))), "{}", message);
+    }
+
+    #[test]
+    fn filelist_lists_the_entries_in_dat_order() {
+        let dir = temp_dir();
+        let dat_path = write_dat(dir.path(), "test.dat", &[("c.bin", b"c"), ("a.bin", b"a"), ("b.txt", b"b")]);
+        let extract_dir = dir.path().join("out");
+        let options = ExtractOptions { write_filelist: true, ..Default::default() };
+        extract_dat_files_with_options(&dat_path, &path_str(&extract_dir), &options).unwrap();
+        assert_eq!(fs::read_to_string(extract_dir.join("dat_filelist.txt")).unwrap(), "c.bin\na.bin\nb.txt\n");
+
+        let extract_dir = dir.path().join("default");
+        extract_dat_files_with_options(&dat_path, &path_str(&extract_dir), &ExtractOptions::default()).unwrap();
+        assert!(!extract_dir.join("dat_filelist.txt").exists());
+    }
+
+    #[test]
+    fn sink_receives_the_filelist_as_an_entry_by_default() {
+        let dir = temp_dir();
+        let dat_path = write_dat(dir.path(), "test.dat", &[("b.bin", b"bb"), ("a.txt", b"a")]);
+        let mut sink = MockSink::default();
+        let options = ExtractOptions { write_filelist: true, ..Default::default() };
+        extract_dat_files_to_sink(&dat_path, &mut sink, &options).unwrap();
+        let (name, filelist) = sink.entries.last().unwrap();
+        assert_eq!((name.as_str(), filelist.as_slice()), ("dat_filelist.txt", b"b.bin\na.txt\n".as_slice()));
+    }
+
+    /// `dat` with `reserved` bytes inserted after the header fields and every offset moved past them.
+    fn dat_with_reserved_header_space(dat: &[u8], reserved: usize) -> Vec<u8> {
+        let header_size = DAT_HEADER_SIZE as usize;
+        let mut larger = dat[..header_size].to_vec();
+        larger.resize(header_size + reserved, 0xEE);
+        larger.extend_from_slice(&dat[header_size..]);
+        let shift = |bytes: &mut [u8], position: usize| {
+            let offset = u32::from_le_bytes(bytes[position..position + 4].try_into().unwrap());
+            bytes[position..position + 4].copy_from_slice(&(offset + reserved as u32).to_le_bytes());
+        };
+        for position in (8..header_size).step_by(4) {
+            shift(&mut larger, position);
+        }
+        let file_count = u32::from_le_bytes(dat[4..8].try_into().unwrap()) as usize;
+        let offsets_offset = u32::from_le_bytes(larger[8..12].try_into().unwrap()) as usize;
+        for index in 0..file_count {
+            shift(&mut larger, offsets_offset + index * 4);
+        }
+        larger
+    }
+
+    #[test]
+    fn reserved_space_after_the_header_is_skipped() {
+        let dir = temp_dir();
+        let dat = dat_bytes(&[("a.bin", b"abc"), ("b.txt", b"defg")]);
+        let dat_path = write_file(dir.path(), "a.dat", &dat_with_reserved_header_space(&dat, 16));
+        let extract_dir = dir.path().join("out");
+        extract_dat_files_sync(&dat_path, &path_str(&extract_dir), false).unwrap();
+        assert_eq!(fs::read(extract_dir.join("a.bin")).unwrap(), b"abc");
+        assert_eq!(fs::read(extract_dir.join("b.txt")).unwrap(), b"defg");
+        assert!(verify_dat_hashmap(&dat_path).unwrap());
+    }
+
+    #[test]
+    fn table_offset_inside_the_header_is_invalid_data() {
+        let dir = temp_dir();
+        let mut dat = dat_bytes(&[("a.bin", b"abc")]);
+        // The names offset
+        dat[16..20].copy_from_slice(&12u32.to_le_bytes());
+        let dat_path = write_file(dir.path(), "a.dat", &dat);
+        let error = extract_dat_files_sync(&dat_path, &path_str(&dir.path().join("out")), false).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("names table offset 12 lies inside"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn zlib_wrapped_dat_is_extracted_transparently() {
+        let dir = temp_dir();
+        let dat_path = write_file(dir.path(), "a.dat", &zlib(&dat_bytes(&[("a.bin", b"abc"), ("b.txt", b"defg")])));
+        let extract_dir = dir.path().join("out");
+        let files = extract_dat_files(&dat_path, &path_str(&extract_dir), false).await.unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(fs::read(extract_dir.join("a.bin")).unwrap(), b"abc");
+        assert_eq!(fs::read(extract_dir.join("b.txt")).unwrap(), b"defg");
+        let manifest: serde_json::Value = serde_json::from_slice(&fs::read(extract_dir.join("dat_info.json")).unwrap()).unwrap();
+        assert_eq!(manifest["zlib_wrapped"], true);
+    }
+
+    #[test]
+    fn truncated_zlib_wrapped_dat_is_invalid_data() {
+        let dir = temp_dir();
+        let wrapped = zlib(&dat_bytes(&[("a.bin", &[7; 1000])]));
+        let dat_path = write_file(dir.path(), "a.dat", &wrapped[..wrapped.len() / 2]);
+        let error = extract_dat_files_sync(&dat_path, &path_str(&dir.path().join("out")), false).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("Failed to inflate zlib-wrapped DAT"), "{}", error);
+    }
+
+    #[test]
+    fn byte_data_wrapper_reads_and_seeks_like_a_cursor() {
+        let data: Vec<u8> = (0..32).collect();
+        let mut wrapper = ByteDataWrapper { data: data.clone(), position: 0 };
+        let mut cursor = io::Cursor::new(data);
+        let seeks = [SeekFrom::Start(4), SeekFrom::Current(6), SeekFrom::End(-3), SeekFrom::Current(-20), SeekFrom::End(8)];
+        for seek in seeks {
+            assert_eq!(wrapper.seek(seek).unwrap(), cursor.seek(seek).unwrap());
+            let (mut wrapper_buffer, mut cursor_buffer) = ([0; 5], [0; 5]);
+            assert_eq!(wrapper.read(&mut wrapper_buffer).unwrap(), cursor.read(&mut cursor_buffer).unwrap());
+            assert_eq!(wrapper_buffer, cursor_buffer);
+        }
+        assert_eq!(wrapper.seek(SeekFrom::Current(-100)).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert!(cursor.seek(SeekFrom::Current(-100)).is_err());
+    }
+
+    #[test]
+    fn byte_data_wrapper_helpers_read_through_the_traits() {
+        let mut wrapper = ByteDataWrapper { data: vec![1, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, b'D'], position: 0 };
+        assert_eq!(wrapper.read_u32().unwrap(), 1);
+        assert_eq!(wrapper.read_offset(OffsetWidth::U64).unwrap(), 2);
+        assert_eq!(wrapper.read_string(1).unwrap(), "D");
+        assert_eq!(wrapper.read_u32().unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn reserved_header_field_is_reported() {
+        let dir = temp_dir();
+        let dat = dat_bytes(&[("a.bin", b"abc"), ("b.bin", b"defg")]);
+        // Like the game, `dat_bytes` leaves a zero u32 before its first table at 32
+        let info = read_dat_header_info(&write_file(dir.path(), "game.dat", &dat)).unwrap().unwrap();
+        assert_eq!((info.reserved, info.entry_alignment), (Some(0), Some(16)));
+
+        // 16 more bytes keep the entries aligned to 16
+        let mut dat = dat_with_reserved_header_space(&dat, 16);
+        let header_size = DAT_HEADER_SIZE as usize;
+        dat[header_size..header_size + 4].copy_from_slice(&0x1234_5678u32.to_le_bytes());
+        let dat_path = write_file(dir.path(), "reserved.dat", &dat);
+        let info = read_dat_header_info(&dat_path).unwrap().unwrap();
+        assert_eq!((info.reserved, info.file_count), (Some(0x1234_5678), 2));
+
+        let extract_dir = dir.path().join("out");
+        extract_dat_files_sync(&dat_path, &path_str(&extract_dir), false).unwrap();
+        let manifest: serde_json::Value = serde_json::from_slice(&fs::read(extract_dir.join("dat_info.json")).unwrap()).unwrap();
+        assert_eq!(manifest["header"], json!({ "reserved": 0x1234_5678, "entry_alignment": 16 }));
+    }
+
+    #[test]
+    fn names_end_at_the_first_null_byte() {
+        assert_eq!(decode_name(b"ab.bin\0\0", false).unwrap(), "ab.bin");
+        assert_eq!(decode_name(b"ab\0cd", false).unwrap(), "ab");
+        assert_eq!(decode_name(b"\0\0\0", false).unwrap(), "");
+        assert_eq!(decode_name(b"", true).unwrap(), "");
+    }
+
+    #[test]
+    fn all_null_names_get_distinct_synthesized_names() {
+        let dir = temp_dir();
+        let mut dat = dat_bytes(&[("a.bin", b"first"), ("b.txt", b"second"), ("c.bin", b"third")]);
+        let names_offset = u32::from_le_bytes(dat[16..20].try_into().unwrap()) as usize;
+        let name_length = u32::from_le_bytes(dat[names_offset..names_offset + 4].try_into().unwrap()) as usize;
+        dat[names_offset + 4..names_offset + 4 + 2 * name_length].fill(0);
+        let dat_path = write_file(dir.path(), "test.dat", &dat);
+        let extract_dir = dir.path().join("out");
+        let options = ExtractOptions { quiet: true, ..Default::default() };
+        extract_dat_files_with_options(&dat_path, &path_str(&extract_dir), &options).unwrap();
+
+        assert_eq!(fs::read(extract_dir.join("unnamed_0.bin")).unwrap(), b"first");
+        assert_eq!(fs::read(extract_dir.join("unnamed_1.txt")).unwrap(), b"second");
+        assert_eq!(fs::read(extract_dir.join("c.bin")).unwrap(), b"third");
+        let manifest: serde_json::Value = serde_json::from_slice(&fs::read(extract_dir.join("dat_info.json")).unwrap()).unwrap();
+        assert_eq!(
+            manifest["unnamed_entries"],
+            json!([{ "index": 0, "extracted_as": "unnamed_0.bin" }, { "index": 1, "extracted_as": "unnamed_1.txt" }])
+        );
+    }
+
+    #[test]
+    fn dat_path_without_extension_is_extracted() {
+        let dir = temp_dir();
+        let dat_path = write_dat(dir.path(), "data", &[("a.bin", b"abc")]);
+        let extract_dir = dir.path().join("out");
+        extract_dat_files_sync(&dat_path, &path_str(&extract_dir), false).unwrap();
+        assert_eq!(fs::read(extract_dir.join("a.bin")).unwrap(), b"abc");
+        let manifest: serde_json::Value = serde_json::from_slice(&fs::read(extract_dir.join("dat_info.json")).unwrap()).unwrap();
+        assert_eq!((&manifest["basename"], &manifest["ext"]), (&json!("data"), &json!("")));
+    }
+
+    #[tokio::test]
+    async fn size_estimate_matches_the_extracted_files() {
+        let dir = temp_dir();
+        let yax = yax_bytes("<root><text>a</text></root>");
+        let compressible = yax_bytes(&format!("<root><text>{}</text></root>", "a".repeat(256)));
+        let pak = pak_bytes(&[TestPakEntry::raw(&yax), TestPakEntry::compressed(&compressible)]);
+        let dat_path = write_dat(dir.path(), "test.dat", &[("a.bin", b"abc"), ("b.pak", &pak), ("c.txt", b"")]);
+        let options = ExtractOptions { extract_pak_files: true, quiet: true, ..Default::default() };
+        let result = extract_dat_files_detailed(&dat_path, &path_str(&dir.path().join("out")), &options).unwrap();
+
+        let dat_files: u64 = result.files.iter().map(|file| file.size).sum();
+        let pak_files: u64 = result.pak_results.iter().flat_map(|pak_result| &pak_result.files).map(|file| file.size).sum();
+        assert_eq!(pak_files, (yax.len() + compressible.len()) as u64);
+        assert_eq!(estimate_extracted_size(&dat_path, false).await.unwrap(), dat_files);
+        assert_eq!(estimate_extracted_size(&dat_path, true).await.unwrap(), dat_files + pak_files);
+
+        let empty_path = write_file(dir.path(), "empty.dat", b"");
+        assert_eq!(estimate_extracted_size(&empty_path, true).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn regenerated_manifest_repacks_the_dat() {
+        let dir = temp_dir();
+        let yax = yax_bytes("<root><text>a</text></root>");
+        let pak = pak_bytes(&[TestPakEntry::raw(&yax)]);
+        let dat = dat_bytes(&[("a.bin", b"abc"), ("b.pak", &pak), ("c.txt", b"defg")]);
+        let dat_path = write_file(dir.path(), "test.dat", &dat);
+        let extract_dir = dir.path().join("out");
+        let options = ExtractOptions { extract_pak_files: true, write_filelist: true, ..Default::default() };
+        extract_dat_files_with_options(&dat_path, &path_str(&extract_dir), &options).unwrap();
+        let manifest_path = extract_dir.join("dat_info.json");
+        let original: serde_json::Value = serde_json::from_slice(&fs::read(&manifest_path).unwrap()).unwrap();
+        fs::remove_file(&manifest_path).unwrap();
+
+        regenerate_dat_manifest(&path_str(&extract_dir), "test", "dat").unwrap();
+        let regenerated: serde_json::Value = serde_json::from_slice(&fs::read(&manifest_path).unwrap()).unwrap();
+        // The PAK directory and the filelist aren't entries
+        assert_eq!(regenerated["files"], original["files"]);
+        assert_eq!((&regenerated["basename"], &regenerated["ext"]), (&json!("test"), &json!("dat")));
+
+        let repacked_path = path_str(&dir.path().join("repacked.dat"));
+        dat_repack::repack_dat_files(&path_str(&extract_dir), &repacked_path, &dat_repack::DatRepackOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(fs::read(&repacked_path).unwrap(), dat);
+    }
+
+    /// A DAT holding `leaf.bin` below `levels` DATs nested in one another, each as `nested.dat` of the one above.
+    fn nested_dat(levels: usize) -> Vec<u8> {
+        let mut dat = dat_bytes(&[("leaf.bin", b"leaf")]);
+        for _ in 0..levels {
+            dat = dat_bytes(&[("nested.dat", &dat)]);
+        }
+        dat
+    }
+
+    #[test]
+    fn recursive_extraction_extracts_nested_dats_within_the_limit() {
+        let dir = temp_dir();
+        let dat_path = write_file(dir.path(), "test.dat", &nested_dat(3));
+        let options = ExtractOptions { recursive: true, quiet: true, ..Default::default() };
+        let mut result = extract_dat_files_detailed(&dat_path, &path_str(&dir.path().join("out")), &options).unwrap();
+        for _ in 0..3 {
+            assert_eq!(result.nested_results.len(), 1);
+            result = result.nested_results.remove(0);
+        }
+        assert!(result.nested_results.is_empty());
+        assert_eq!(result.files.len(), 1);
+        assert_eq!(fs::read(&result.files[0].path).unwrap(), b"leaf");
+    }
+
+    #[test]
+    fn recursive_extraction_stops_at_an_artificially_deep_nesting() {
+        let dir = temp_dir();
+        let dat_path = write_file(dir.path(), "test.dat", &nested_dat(DEFAULT_MAX_NESTING_DEPTH * 4));
+        let options = ExtractOptions { recursive: true, quiet: true, ..Default::default() };
+        let e = extract_dat_files_detailed(&dat_path, &path_str(&dir.path().join("out")), &options).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(e.to_string().contains(&format!("nested deeper than the limit of {} levels", DEFAULT_MAX_NESTING_DEPTH)), "{}", e);
+        // Nothing is extracted past the limit
+        let deepest = (0..=DEFAULT_MAX_NESTING_DEPTH).fold(dir.path().join("out"), |dir, _| dir.join(DAT_EXTRACT_SUBDIR).join("nested.dat"));
+        assert!(!deepest.exists());
+    }
+
+    #[test]
+    fn max_nesting_depth_overrides_the_default_limit() {
+        let dir = temp_dir();
+        let dat_path = write_file(dir.path(), "test.dat", &nested_dat(3));
+        let options = ExtractOptions { recursive: true, max_nesting_depth: Some(2), quiet: true, ..Default::default() };
+        let e = extract_dat_files_detailed(&dat_path, &path_str(&dir.path().join("out")), &options).unwrap_err();
+        assert!(e.to_string().contains("limit of 2 levels"), "{}", e);
+        let options = ExtractOptions { recursive: true, max_nesting_depth: Some(3), quiet: true, ..Default::default() };
+        extract_dat_files_detailed(&dat_path, &path_str(&dir.path().join("out3")), &options).unwrap();
+    }
+
+    #[test]
+    fn non_recursive_extraction_leaves_nested_dats_alone() {
+        let dir = temp_dir();
+        let dat_path = write_file(dir.path(), "test.dat", &nested_dat(DEFAULT_MAX_NESTING_DEPTH * 4));
+        let options = ExtractOptions { quiet: true, ..Default::default() };
+        let result = extract_dat_files_detailed(&dat_path, &path_str(&dir.path().join("out")), &options).unwrap();
+        assert!(result.nested_results.is_empty());
+        assert!(!dir.path().join("out").join(DAT_EXTRACT_SUBDIR).exists());
+    }
+
+    #[test]
+    fn nested_dat_matching_an_ancestor_is_rejected_as_circular() {
+        let dir = temp_dir();
+        let inner = dat_bytes(&[("leaf.bin", b"leaf")]);
+        write_file(dir.path(), "nested.dat", &inner);
+        let options = ExtractOptions { recursive: true, quiet: true, ..Default::default() };
+        let mut ancestors = vec![(content_hash(&inner), "outer.dat".to_string())];
+        let e = extract_dir_result_nested(vec!["nested.dat".to_string()], &path_str(dir.path()), &options, 0, &mut ancestors).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(e.to_string().contains("is the same DAT as outer.dat"), "{}", e);
+        assert_eq!(ancestors.len(), 1);
+    }
+
+    #[test]
+    fn read_dat_entry_reads_one_entry_of_plain_and_zlib_wrapped_dats() {
+        let dir = temp_dir();
+        let entries: &[(&str, &[u8])] = &[("a.bin", b"first"), ("b.bin", b"second entry")];
+        let plain = write_dat(dir.path(), "plain.dat", entries);
+        let wrapped = write_file(dir.path(), "wrapped.dat", &zlib(&dat_bytes(entries)));
+        for dat_path in [plain, wrapped] {
+            assert_eq!(read_dat_entry(&dat_path, "b.bin").unwrap().unwrap(), b"second entry");
+            assert_eq!(read_dat_entry(&dat_path, "a.bin").unwrap().unwrap(), b"first");
+            assert_eq!(read_dat_entry(&dat_path, "c.bin").unwrap(), None);
+        }
+        assert_eq!(read_dat_entry(&write_file(dir.path(), "empty.dat", b""), "a.bin").unwrap(), None);
+    }
+
+    #[test]
+    fn read_dat_entry_rejects_entries_past_the_end_of_the_file() {
+        let dir = temp_dir();
+        let dat = dat_bytes(&[("a.bin", b"first"), ("b.bin", b"second entry")]);
+        let dat_path = write_file(dir.path(), "truncated.dat", &dat[..dat.len() - 4]);
+        let e = read_dat_entry(&dat_path, "b.bin").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(e.to_string().contains("DAT entry b.bin lies outside of"), "{}", e);
+        assert_eq!(read_dat_entry(&dat_path, "a.bin").unwrap().unwrap(), b"first");
+    }
+
+    #[test]
+    fn read_dat_entry_ffi_hands_out_the_entry_bytes() {
+        let dir = temp_dir();
+        let dat_path = c_string(&write_dat(dir.path(), "test.dat", &[("a.bin", b"first"), ("b.bin", b"second entry")]));
+        let mut len = 0;
+        let buffer = read_dat_entry_ffi(dat_path.as_ptr(), c_string("b.bin").as_ptr(), &mut len);
+        assert!(!buffer.is_null());
+        assert_eq!(unsafe { std::slice::from_raw_parts(buffer, len) }, b"second entry");
+        free_buffer_ffi(buffer, len);
+        free_buffer_ffi(std::ptr::null_mut(), 0);
+    }
+
+    #[test]
+    fn read_dat_entry_ffi_returns_null_for_missing_entries_and_dats() {
+        let dir = temp_dir();
+        let dat_path = c_string(&write_dat(dir.path(), "test.dat", &[("a.bin", b"first")]));
+        let mut len = 7;
+        assert!(read_dat_entry_ffi(dat_path.as_ptr(), c_string("missing.bin").as_ptr(), &mut len).is_null());
+        assert_eq!(len, 7);
+        let missing_dat = c_string(&path_str(&dir.path().join("missing.dat")));
+        assert!(read_dat_entry_ffi(missing_dat.as_ptr(), c_string("a.bin").as_ptr(), &mut len).is_null());
+        assert!(read_dat_entry_ffi(dat_path.as_ptr(), std::ptr::null(), &mut len).is_null());
+    }
+
+    #[test]
+    fn lowercase_names_get_a_suffix_when_they_collide() {
+        let mut used = HashSet::new();
+        assert_eq!(unique_lowercase_name("File.wmb", &mut used), "file.wmb");
+        assert_eq!(unique_lowercase_name("file.wmb", &mut used), "file_1.wmb");
+        assert_eq!(unique_lowercase_name("FILE.WMB", &mut used), "file_2.wmb");
+        assert_eq!(unique_lowercase_name("README", &mut used), "readme");
+        assert_eq!(unique_lowercase_name("readme", &mut used), "readme_1");
+    }
+
+    #[tokio::test]
+    async fn normalize_case_keeps_names_differing_in_case_and_repacks_them() {
+        let dir = temp_dir();
+        let dat = dat_bytes(&[("File.wmb", b"upper"), ("file.wmb", b"lower"), ("Other.BIN", b"other")]);
+        let dat_path = write_file(dir.path(), "test.dat", &dat);
+        let extract_dir = dir.path().join("out");
+        let options = ExtractOptions { normalize_case: true, quiet: true, ..Default::default() };
+        let files = extract_dat_files_with_options(&dat_path, &path_str(&extract_dir), &options).unwrap();
+
+        assert_eq!(files.len(), 3);
+        assert_eq!(fs::read(extract_dir.join("file.wmb")).unwrap(), b"upper");
+        assert_eq!(fs::read(extract_dir.join("file_1.wmb")).unwrap(), b"lower");
+        assert_eq!(fs::read(extract_dir.join("other.bin")).unwrap(), b"other");
+        let manifest: serde_json::Value = serde_json::from_slice(&fs::read(extract_dir.join("dat_info.json")).unwrap()).unwrap();
+        assert_eq!(
+            manifest["normalized_case"],
+            json!([
+                { "name": "File.wmb", "extracted_as": "file.wmb" },
+                { "name": "file.wmb", "extracted_as": "file_1.wmb" },
+                { "name": "Other.BIN", "extracted_as": "other.bin" },
+            ])
+        );
+
+        let repacked_path = path_str(&dir.path().join("repacked.dat"));
+        dat_repack::repack_dat_files(&path_str(&extract_dir), &repacked_path, &dat_repack::DatRepackOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(read_dat_entry(&repacked_path, "File.wmb").unwrap().unwrap(), b"upper");
+        assert_eq!(read_dat_entry(&repacked_path, "file.wmb").unwrap().unwrap(), b"lower");
+        assert_eq!(fs::read(&repacked_path).unwrap(), dat);
+    }
+
+    #[tokio::test]
+    async fn gzipped_entries_decompress_to_the_original_bytes_and_repack() {
+        let dir = temp_dir();
+        let yax = yax_bytes("<root><text>a</text></root>");
+        let pak = pak_bytes(&[TestPakEntry::raw(&yax)]);
+        let entries: &[(&str, &[u8])] = &[("a.wmb", b"model data"), ("b.pak", &pak), ("c.bin", b"")];
+        let dat = dat_bytes(entries);
+        let dat_path = write_file(dir.path(), "test.dat", &dat);
+        let extract_dir = dir.path().join("out");
+        let options = ExtractOptions { gzip_entries: true, extract_pak_files: true, quiet: true, ..Default::default() };
+        let files = extract_dat_files_with_options(&dat_path, &path_str(&extract_dir), &options).unwrap();
+
+        assert_eq!(files, ["a.wmb.gz", "b.pak.gz", "c.bin.gz"].map(|file| path_str(&extract_dir.join(file))));
+        for ((_, data), file) in entries.iter().zip(&files) {
+            let mut decompressed = Vec::new();
+            flate2::read::GzDecoder::new(File::open(file).unwrap()).read_to_end(&mut decompressed).unwrap();
+            assert_eq!(&decompressed, data);
+        }
+        let manifest: serde_json::Value = serde_json::from_slice(&fs::read(extract_dir.join("dat_info.json")).unwrap()).unwrap();
+        assert_eq!(manifest["gzipped"], json!(true));
+        // PAKs aren't extracted from gzipped entries
+        assert!(!extract_dir.join(PAK_EXTRACT_SUBDIR).exists());
+
+        let repacked_path = path_str(&dir.path().join("repacked.dat"));
+        dat_repack::repack_dat_files(&path_str(&extract_dir), &repacked_path, &dat_repack::DatRepackOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(fs::read(&repacked_path).unwrap(), dat);
+    }
+
+    #[test]
+    fn entries_are_not_gzipped_by_default() {
+        let dir = temp_dir();
+        let dat_path = write_dat(dir.path(), "test.dat", &[("a.wmb", b"model data")]);
+        let extract_dir = dir.path().join("out");
+        let files = extract_dat_files_with_options(&dat_path, &path_str(&extract_dir), &ExtractOptions::default()).unwrap();
+        assert_eq!(files, [path_str(&extract_dir.join("a.wmb"))]);
+        let manifest: serde_json::Value = serde_json::from_slice(&fs::read(extract_dir.join("dat_info.json")).unwrap()).unwrap();
+        assert!(manifest.get("gzipped").is_none());
+    }
+
+    /// A DAT in the `OffsetWidth::U64` layout: u64 table offsets in the header, u64 offsets and sizes tables,
+    /// 16 byte aligned entries and no hash map. The first entry's offset is returned alongside, to corrupt it.
+    fn dat64_bytes(entries: &[(&str, &[u8])]) -> (Vec<u8>, usize) {
+        let count = entries.len();
+        let name_length = entries.iter().map(|(name, _)| name.len() + 1).max().unwrap_or(1);
+        let offsets_offset = 48;
+        let extensions_offset = offsets_offset + count * 8;
+        let names_offset = extensions_offset + count * 4;
+        let sizes_offset = names_offset + 4 + count * name_length;
+        let mut data_offset = (sizes_offset + count * 8).next_multiple_of(16);
+
+        let mut dat = b"DAT\0".to_vec();
+        dat.extend_from_slice(&(count as u32).to_le_bytes());
+        for offset in [offsets_offset, extensions_offset, names_offset, sizes_offset, 0] {
+            dat.extend_from_slice(&(offset as u64).to_le_bytes());
+        }
+        let mut data = Vec::new();
+        for (_, entry) in entries {
+            dat.extend_from_slice(&(data_offset as u64).to_le_bytes());
+            data.resize(data_offset - (sizes_offset + count * 8).next_multiple_of(16), 0);
+            data.extend_from_slice(entry);
+            data_offset += entry.len().next_multiple_of(16);
+        }
+        for (name, _) in entries {
+            let mut extension = name.rsplit_once('.').map_or("", |(_, extension)| extension).as_bytes().to_vec();
+            extension.resize(4, 0);
+            dat.extend_from_slice(&extension);
+        }
+        dat.extend_from_slice(&(name_length as u32).to_le_bytes());
+        for (name, _) in entries {
+            let mut name = name.as_bytes().to_vec();
+            name.resize(name_length, 0);
+            dat.extend_from_slice(&name);
+        }
+        for (_, entry) in entries {
+            dat.extend_from_slice(&(entry.len() as u64).to_le_bytes());
+        }
+        dat.resize(dat.len().next_multiple_of(16), 0);
+        dat.extend_from_slice(&data);
+        (dat, offsets_offset)
+    }
+
+    #[test]
+    fn dat_with_64_bit_offsets_extracts_with_offset_width_u64() {
+        let dir = temp_dir();
+        let (dat, _) = dat64_bytes(&[("a.bin", b"first"), ("long_name.wmb", b"second entry")]);
+        let dat_path = write_file(dir.path(), "test.dat", &dat);
+        let extract_dir = dir.path().join("out");
+        let options = ExtractOptions { offset_width: OffsetWidth::U64, quiet: true, ..Default::default() };
+        let files = extract_dat_files_with_options(&dat_path, &path_str(&extract_dir), &options).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(fs::read(extract_dir.join("a.bin")).unwrap(), b"first");
+        assert_eq!(fs::read(extract_dir.join("long_name.wmb")).unwrap(), b"second entry");
+
+        // Read as the default 32 bit layout, the offsets point into the header
+        let e = extract_dat_files_with_options(&dat_path, &path_str(&dir.path().join("out32")), &ExtractOptions::default()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn offsets_past_4_gb_are_not_truncated() {
+        let dir = temp_dir();
+        let (mut dat, offsets_offset) = dat64_bytes(&[("a.bin", b"first")]);
+        let first_offset = u64::from_le_bytes(dat[offsets_offset..offsets_offset + 8].try_into().unwrap());
+        dat[offsets_offset..offsets_offset + 8].copy_from_slice(&(first_offset + (1 << 32)).to_le_bytes());
+        let dat_path = write_file(dir.path(), "test.dat", &dat);
+        let options = ExtractOptions { offset_width: OffsetWidth::U64, quiet: true, ..Default::default() };
+        // Truncated to 32 bits, the offset would read the entry
+        assert!(extract_dat_files_with_options(&dat_path, &path_str(&dir.path().join("out")), &options).is_err());
+        assert!(!dir.path().join("out/a.bin").exists());
+    }
+
+    #[test]
+    fn extract_dir_that_is_a_file_is_not_a_directory() {
+        let dir = temp_dir();
+        let dat = dat_bytes(&[("a.bin", b"abc")]);
+        let dat_path = write_file(dir.path(), "test.dat", &dat);
+        // The DAT itself given as the extract directory
+        let e = extract_dat_files_with_options(&dat_path, &dat_path, &ExtractOptions::default()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotADirectory);
+        assert_eq!(e.to_string(), format!("Can't extract to {}: it is a file, not a directory", dat_path));
+        assert_eq!(fs::read(&dat_path).unwrap(), dat);
+
+        let e = extract_dat_files_detailed(&dat_path, &dat_path, &ExtractOptions::default()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotADirectory);
+    }
+
+    #[test]
+    fn only_entries_matching_the_pattern_are_extracted() {
+        let dir = temp_dir();
+        let dat_path = write_dat(dir.path(), "test.dat", &[("a.wtp", b"tex a"), ("b.wmb", b"model"), ("C.WTP", b"tex c"), ("d.bin", b"bin")]);
+        let extract_dir = dir.path().join("out");
+        let files = extract_dat_files_matching(&dat_path, &path_str(&extract_dir), "*.wtp").unwrap();
+        assert_eq!(files, [path_str(&extract_dir.join("a.wtp")), path_str(&extract_dir.join("C.WTP"))]);
+        assert_eq!(fs::read(extract_dir.join("C.WTP")).unwrap(), b"tex c");
+        assert!(!extract_dir.join("b.wmb").exists() && !extract_dir.join("d.bin").exists());
+
+        let manifest: serde_json::Value = serde_json::from_slice(&fs::read(extract_dir.join("dat_info.json")).unwrap()).unwrap();
+        assert_eq!(manifest["files"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn pattern_without_matches_writes_nothing() {
+        let dir = temp_dir();
+        let dat_path = write_dat(dir.path(), "test.dat", &[("a.wtp", b"tex a")]);
+        let extract_dir = dir.path().join("out");
+        assert!(extract_dat_files_matching(&dat_path, &path_str(&extract_dir), "*.wmb").unwrap().is_empty());
+        assert!(!extract_dir.exists());
+    }
+
+    #[test]
+    fn invalid_pattern_is_invalid_input() {
+        let dir = temp_dir();
+        let dat_path = write_dat(dir.path(), "test.dat", &[("a.wtp", b"tex a")]);
+        let e = extract_dat_files_matching(&dat_path, &path_str(&dir.path().join("out")), "[a-").unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn provenance_records_the_source_dat() {
+        let dir = temp_dir();
+        let dat = dat_bytes(&[("a.bin", b"abc")]);
+        let dat_path = write_file(dir.path(), "test.dat", &dat);
+        let wrapped_path = write_file(dir.path(), "wrapped.dat", &zlib(&dat));
+        for (path, out) in [(&dat_path, "out"), (&wrapped_path, "wrapped_out")] {
+            let extract_dir = dir.path().join(out);
+            let options = ExtractOptions { provenance: true, quiet: true, ..Default::default() };
+            extract_dat_files_with_options(path, &path_str(&extract_dir), &options).unwrap();
+            let manifest: serde_json::Value = serde_json::from_slice(&fs::read(extract_dir.join("dat_info.json")).unwrap()).unwrap();
+            let provenance = &manifest["provenance"];
+            assert_eq!(provenance["version"], json!(1));
+            assert_eq!(provenance["tool"], json!(env!("CARGO_PKG_NAME")));
+            assert_eq!(provenance["tool_version"], json!(env!("CARGO_PKG_VERSION")));
+            assert!(provenance["extracted_at_unix"].as_u64().unwrap() > 0);
+            // A zlib-wrapped DAT is described by the DAT inside
+            assert_eq!(provenance["source"], json!({ "path": path, "size": dat.len(), "crc32": format!("{:08x}", crc32fast::hash(&dat)) }));
+        }
+
+        let extract_dir = dir.path().join("plain_out");
+        extract_dat_files_with_options(&dat_path, &path_str(&extract_dir), &ExtractOptions::default()).unwrap();
+        let manifest: serde_json::Value = serde_json::from_slice(&fs::read(extract_dir.join("dat_info.json")).unwrap()).unwrap();
+        assert!(manifest.get("provenance").is_none());
     }
 }
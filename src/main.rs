@@ -0,0 +1,144 @@
+use extract_dat_files::pak_extract::{extract_pak_files_with_options, PakExtractOptions};
+use extract_dat_files::tar_writer::extract_dat_to_tar_writer;
+use extract_dat_files::verify::{verify_dat, verify_pak, VerifyReport};
+use extract_dat_files::{error_envelope, extract_dat_files_with_options, ExtractOptions};
+use serde_json::json;
+use std::path::Path;
+use std::process::ExitCode;
+
+const USAGE: &str = "Usage: extract_dat <file.dat|file.pak> <extract_dir> [--pak] [--json]
+       extract_dat <file.dat|file.pak> --verify-only [--json]
+
+  --pak          also extract the PAK files inside a DAT (and convert their YAX files to XML)
+  --json         print the result as JSON instead of text
+  --verify-only  check every entry (offsets, PAK decompression, YAX conversion) in memory and write nothing
+
+An <extract_dir> of - writes the DAT entries to stdout as a tar archive instead, e.g. | tar -x";
+
+struct Args {
+    input: String,
+    /// Empty with `verify_only`.
+    extract_dir: String,
+    extract_pak_files: bool,
+    json: bool,
+    verify_only: bool,
+}
+
+fn parse_args() -> Option<Args> {
+    let mut positional = Vec::new();
+    let mut extract_pak_files = false;
+    let mut json = false;
+    let mut verify_only = false;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--pak" => extract_pak_files = true,
+            "--json" => json = true,
+            "--verify-only" => verify_only = true,
+            _ if arg.starts_with("--") => return None,
+            _ => positional.push(arg),
+        }
+    }
+
+    if verify_only {
+        let [input]: [String; 1] = positional.try_into().ok()?;
+        return Some(Args { input, extract_dir: String::new(), extract_pak_files, json, verify_only });
+    }
+    let [input, extract_dir]: [String; 2] = positional.try_into().ok()?;
+    Some(Args { input, extract_dir, extract_pak_files, json, verify_only })
+}
+
+fn print_verify_report(report: &VerifyReport, json: bool) {
+    if json {
+        let entries: Vec<_> = report
+            .entries
+            .iter()
+            .map(|entry| json!({ "name": entry.name, "ok": entry.error.is_none(), "error": entry.error }))
+            .collect();
+        println!("{}", json!({ "entries": entries, "hash_map_valid": report.hash_map_valid, "passed": report.passed() }));
+        return;
+    }
+    for entry in &report.entries {
+        match &entry.error {
+            None => println!("OK    {}", entry.name),
+            Some(error) => println!("FAIL  {}: {}", entry.name, error),
+        }
+    }
+    if report.hash_map_valid == Some(false) {
+        println!("Note: the hash map table is missing or doesn't match the names");
+    }
+    let failures = report.failures().count();
+    println!("{} of {} entries passed", report.entries.len() - failures, report.entries.len());
+}
+
+fn main() -> ExitCode {
+    let Some(args) = parse_args() else {
+        eprintln!("{}", USAGE);
+        return ExitCode::from(2);
+    };
+
+    let is_pak = Path::new(&args.input).extension().is_some_and(|ext| ext.eq_ignore_ascii_case("pak"));
+    if args.verify_only {
+        let report = if is_pak { verify_pak(&args.input) } else { verify_dat(&args.input) };
+        return match report {
+            Ok(report) => {
+                print_verify_report(&report, args.json);
+                if report.passed() { ExitCode::SUCCESS } else { ExitCode::FAILURE }
+            }
+            Err(e) => {
+                if args.json {
+                    println!("{}", error_envelope(&e));
+                } else {
+                    eprintln!("Error: {}", e);
+                }
+                ExitCode::FAILURE
+            }
+        };
+    }
+    if args.extract_dir == "-" && !is_pak {
+        return match extract_dat_to_tar_writer(&args.input, std::io::stdout().lock()) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    let result = if is_pak {
+        let options = PakExtractOptions {
+            yax_to_xml: args.extract_pak_files,
+            quiet: args.json,
+            ..Default::default()
+        };
+        extract_pak_files_with_options(&args.input, &args.extract_dir, &options)
+    } else {
+        let options = ExtractOptions {
+            extract_pak_files: args.extract_pak_files,
+            quiet: args.json,
+            ..Default::default()
+        };
+        extract_dat_files_with_options(&args.input, &args.extract_dir, &options)
+    };
+
+    match result {
+        Ok(files) => {
+            if args.json {
+                println!("{}", json!({ "files": files, "count": files.len() }));
+            } else {
+                for file in &files {
+                    println!("{}", file);
+                }
+                println!("Extracted {} files to {}", files.len(), args.extract_dir);
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            if args.json {
+                println!("{}", error_envelope(&e));
+            } else {
+                eprintln!("Error: {}", e);
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -1,13 +1,20 @@
 use flate2::read::ZlibDecoder;
 use serde_json::json;
-use std::ffi::{CStr, CString};
-use std::fs::{create_dir_all, File};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs::{self, File};
 use std::io::{self, Read, Write};
-use std::os::raw::c_char;
-use std::path::Path;
+use std::os::raw::{c_char, c_int, c_uint};
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
 use std::ptr;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use rayon::prelude::*;
 
-use crate::yax_to_xml_convert::convert_yax_to_xml;
+use crate::{check_cancelled, content_hash, create_extract_dir, ffi_error_envelope, ffi_path, ffi_status, path_error, run_ffi_with_timeout, to_hex, ByteDataWrapper};
+use crate::yax_to_xml_convert::{convert_yax_to_xml_with_options, yax_is_convertible, YaxConvertOptions};
 
 
 #[derive(Debug)]
@@ -15,139 +22,573 @@ struct HeaderEntry {
     r#type: u32,           
     uncompressed_size: u32, 
     offset: u32,           
+    /// The bytes after `offset` in the longer header entries of `ENTRY_STRIDES`, empty for 12 byte entries.
+    extra: Vec<u8>,
 }
 
 impl HeaderEntry {
 
-    fn new(bytes: &mut ByteDataWrapper) -> Self {
-        let r#type = bytes.read_u32();           
-        let uncompressed_size = bytes.read_u32();
-        let offset = bytes.read_u32();           
-        HeaderEntry {
+    fn new(bytes: &mut ByteDataWrapper) -> io::Result<Self> {
+        let r#type = bytes.read_u32()?;           
+        let uncompressed_size = bytes.read_u32()?;
+        let offset = bytes.read_u32()?;           
+        Ok(HeaderEntry {
             r#type,
             uncompressed_size,
             offset,
+            extra: Vec::new(),
+        })
+    }
+}
+
+
+/// Entry sizes of the PAK variants `read_header_entries` detects, the standard one first.
+const ENTRY_STRIDES: [u32; 4] = [12, 16, 20, 24];
+
+/// Reads the header entry table. It consists of `stride` byte entries starting with `type`, `uncompressed_size`
+/// and `offset` (the standard 12 byte entries have nothing else), followed by 4 bytes. So the first entry's offset
+/// must be `4 + stride * n`, anything else isn't a PAK we understand.
+/// Without a given `stride` the first one in `ENTRY_STRIDES` is used whose entries have ascending offsets
+/// inside the file. Returns the entries and the stride.
+/// A PAK without entries is just the 4 terminating bytes, which leaves no first offset to read.
+fn read_header_entries(bytes: &mut ByteDataWrapper, stride: Option<u32>) -> io::Result<(Vec<HeaderEntry>, u32)> {
+    if (4..12).contains(&bytes.data.len()) && bytes.data.iter().all(|&byte| byte == 0) {
+        return Ok((Vec::new(), stride.unwrap_or(ENTRY_STRIDES[0])));
+    }
+    if bytes.data.len() < 12 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "PAK file is too small to contain a header"));
+    }
+
+    bytes.position = 8;
+    let first_offset = bytes.read_u32()?;
+    let fits_stride = |stride: u32| first_offset >= 4 && stride >= 12 && (first_offset - 4).is_multiple_of(stride);
+    if first_offset as usize > bytes.data.len() || !stride.map_or(ENTRY_STRIDES.iter().any(|&s| fits_stride(s)), fits_stride) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid PAK header: first offset {} is not 4 + {} * n", first_offset, stride.unwrap_or(12)),
+        ));
+    }
+
+    let candidates: Vec<u32> = match stride {
+        Some(stride) => vec![stride],
+        None => ENTRY_STRIDES.iter().copied().filter(|&stride| fits_stride(stride)).collect(),
+    };
+    // If no stride gives plausible offsets, the first one is used and the bad offsets are reported later
+    let mut fallback = None;
+    for stride in candidates {
+        let header_entries = read_entries_with_stride(bytes, first_offset, stride)?;
+        let ascending = header_entries.windows(2).all(|pair| pair[0].offset <= pair[1].offset);
+        let inside = header_entries.iter().all(|entry| entry.offset >= first_offset && entry.offset as usize <= bytes.data.len());
+        if ascending && inside {
+            return Ok((header_entries, stride));
         }
+        fallback.get_or_insert((header_entries, stride));
     }
+    Ok(fallback.expect("at least one stride fits the first offset"))
 }
 
+fn read_entries_with_stride(bytes: &mut ByteDataWrapper, first_offset: u32, stride: u32) -> io::Result<Vec<HeaderEntry>> {
+    let file_count = (first_offset - 4) / stride;
+    let mut header_entries = Vec::with_capacity(file_count as usize);
+    for index in 0..file_count {
+        bytes.position = (index * stride) as usize;
+        let mut entry = HeaderEntry::new(bytes)?;
+        entry.extra = bytes.slice(bytes.position, stride as usize - 12)?.to_vec();
+        header_entries.push(entry);
+    }
+    Ok(header_entries)
+}
 
-struct ByteDataWrapper {
-    data: Vec<u8>,  
-    position: usize, 
+/// Total size of the YAX files extracting the PAK in `data` writes, the sum of its entries' uncompressed sizes.
+pub(crate) fn pak_extracted_size(data: Vec<u8>) -> io::Result<u64> {
+    let mut bytes = ByteDataWrapper { data, position: 0 };
+    let (header_entries, _) = read_header_entries(&mut bytes, None)?;
+    Ok(header_entries.iter().map(|entry| u64::from(entry.uncompressed_size)).sum())
 }
 
-impl ByteDataWrapper {
+/// Reads every entry of the PAK in `data` like extraction in strict mode does and converts the non-empty ones
+/// to XML in memory. Returns each entry's name and what went wrong with it, if anything. Fails only if the
+/// header can't be read.
+pub(crate) fn check_pak_entries(data: Vec<u8>) -> io::Result<Vec<(String, Option<String>)>> {
+    let mut bytes = ByteDataWrapper { data, position: 0 };
+    let (header_entries, _) = read_header_entries(&mut bytes, None)?;
+    let file_sizes = entry_sizes(&header_entries, bytes.data.len())?;
+    let strict_options = PakExtractOptions { strict: true, ..Default::default() };
+    Ok(header_entries
+        .iter()
+        .enumerate()
+        .map(|(i, meta)| {
+            let checked = read_pak_yax(meta, file_sizes[i] as usize, &mut bytes, i, &strict_options)
+                .map_err(|e| e.to_string())
+                .and_then(|yax| if yax.is_empty() { Ok(()) } else { yax_is_convertible(&yax).map_err(|e| e.to_string()) });
+            (format!("{}.yax", i), checked.err())
+        })
+        .collect())
+}
 
-    fn from_file(file_path: &str) -> io::Result<Self> {
-        let mut file = File::open(file_path)?; 
-        let mut data = Vec::new();             
-        file.read_to_end(&mut data)?;          
-        Ok(ByteDataWrapper { data, position: 0 })
+/// Each entry's stored size is the distance to the next entry's offset (or to the end of the file for the last one).
+/// PAK offsets are u32, so a file that doesn't fit or offsets that go backwards are reported instead of wrapping.
+fn entry_sizes(header_entries: &[HeaderEntry], file_len: usize) -> io::Result<Vec<u32>> {
+    let file_len = u32::try_from(file_len).map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidData, "PAK file is larger than its 32 bit offsets can address")
+    })?;
+
+    let mut file_sizes = Vec::with_capacity(header_entries.len());
+    for (i, entry) in header_entries.iter().enumerate() {
+        let end = header_entries.get(i + 1).map_or(file_len, |next| next.offset);
+        let size = end.checked_sub(entry.offset).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("PAK entry {} has offset {} past the end of the entry at {}", i, entry.offset, end),
+            )
+        })?;
+        file_sizes.push(size);
     }
+    Ok(file_sizes)
+}
+
+/// Number of data bytes in an uncompressed entry that takes `raw_size` bytes in the PAK. Entries are padded to
+/// a multiple of 4, so `raw_size` is the data plus the 0 to 3 padding bytes needed to align
+/// `uncompressed_size`: for a size with remainder 1, 2 or 3 modulo 4 that is 3, 2 or 1 bytes, for remainder 0
+/// none. `None` if `raw_size` is smaller than that padding.
+fn aligned_read_size(meta: &HeaderEntry, raw_size: usize) -> Option<usize> {
+    let padding = (4 - meta.uncompressed_size as usize % 4) % 4;
+    raw_size.checked_sub(padding)
+}
 
+/// How an entry is stored in the PAK.
+#[derive(Debug, Clone, Copy, Default)]
+struct StoredEntry {
+    /// The entry is a u32 compressed size and a zlib stream, told by a stored size smaller than `uncompressed_size`.
+    compressed: bool,
+    /// Bytes of entry data: the zlib stream or the uncompressed data without padding.
+    read_size: u32,
+}
 
-    fn read_u32(&mut self) -> u32 {
-        let result = u32::from_le_bytes(self.data[self.position..self.position + 4].try_into().unwrap());
-        self.position += 4; 
-        result
+/// Tells how entry `index`, taking `size` bytes in the PAK, is stored, leaving `bytes` at the start of its data.
+fn stored_entry(meta: &HeaderEntry, size: usize, bytes: &mut ByteDataWrapper, index: usize) -> io::Result<StoredEntry> {
+    if meta.uncompressed_size == 0 {
+        return Ok(StoredEntry::default());
     }
+    bytes.position = meta.offset as usize;
+    let compressed = meta.uncompressed_size > size as u32;  
+    let read_size = if compressed {
+        bytes.read_u32()?
+    } else {
+        aligned_read_size(meta, size).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("PAK entry {} is smaller than its alignment padding", index))
+        })? as u32
+    };
+    Ok(StoredEntry { compressed, read_size })
+}
 
-
-    fn read_u8_list(&mut self, size: usize) -> Vec<u8> {
-        let result = self.data[self.position..self.position + size].to_vec(); 
-        self.position += size; 
-        result
+/// The data of an entry that looked compressed but isn't a zlib stream, from the `stored` bytes it takes in the
+/// PAK: its first `uncompressed_size` bytes if they fit, otherwise the stored bytes without the zeros padding them
+/// to a multiple of 4. The header size is wrong then, so the padding can't be told from it, and data ending in
+/// zero bytes loses up to 3 of them.
+fn raw_fallback_bytes(stored: &[u8], uncompressed_size: u32) -> &[u8] {
+    if let Some(data) = stored.get(..uncompressed_size as usize) {
+        return data;
     }
+    let padding = stored.iter().rev().take(3).take_while(|&&byte| byte == 0).count();
+    &stored[..stored.len() - padding]
 }
 
-pub async fn extract_pak_yax(
+fn read_pak_yax(
     meta: &HeaderEntry,
     size: usize,
     bytes: &mut ByteDataWrapper,
-    extract_dir: &Path,
     index: usize,
-) -> io::Result<()> {
-    bytes.position = meta.offset as usize; 
-    let is_compressed = meta.uncompressed_size > size as u32;  
-    let read_size = if is_compressed {
-        bytes.read_u32() as usize 
-    } else {
-        size - ((4 - (meta.uncompressed_size % 4)) % 4) as usize 
+    options: &PakExtractOptions,
+) -> io::Result<Vec<u8>> {
+    // An empty entry may still own padding bytes, none of which are data
+    if meta.uncompressed_size == 0 {
+        return Ok(Vec::new());
+    }
+
+    let stored = stored_entry(meta, size, bytes, index)?;
+    if !stored.compressed {
+        return Ok(bytes.read_bytes(stored.read_size as usize)?.to_vec());
+    }
+
+    let decompressed = bytes.read_bytes(stored.read_size as usize).and_then(|zlib_bytes| {
+        let mut decompressed_bytes = Vec::new();
+        ZlibDecoder::new(zlib_bytes).read_to_end(&mut decompressed_bytes)?;
+        Ok(decompressed_bytes)
+    });
+    let file_bytes = match decompressed {
+        Ok(file_bytes) => file_bytes,
+        Err(e) if options.strict => {
+            return Err(io::Error::new(e.kind(), format!("PAK entry {} looks compressed but isn't a zlib stream: {}", index, e)));
+        }
+        Err(e) => {
+            // Compression is only told by the sizes, so this may be raw data behind a wrong `uncompressed_size`
+            if !options.quiet {
+                eprintln!("Warning: PAK entry {} looks compressed but isn't a zlib stream ({}), extracting it uncompressed", index, e);
+            }
+            return Ok(raw_fallback_bytes(bytes.slice(meta.offset as usize, size)?, meta.uncompressed_size).to_vec());
+        }
     };
 
-    let mut extracted_file = File::create(extract_dir.join(format!("{}.yax", index)))?;  
-    let mut file_bytes = bytes.read_u8_list(read_size); 
-    if is_compressed {
-        let mut decoder = ZlibDecoder::new(&file_bytes[..]); 
-        let mut decompressed_bytes = Vec::new(); 
-        decoder.read_to_end(&mut decompressed_bytes)?; 
-        file_bytes = decompressed_bytes; 
+    // A different size means a corrupt entry or one that was wrongly taken for compressed
+    if file_bytes.len() != meta.uncompressed_size as usize {
+        let message = format!(
+            "PAK entry {} decompresses to {} bytes but its header says {}",
+            index, file_bytes.len(), meta.uncompressed_size
+        );
+        if options.strict {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        }
+        if !options.quiet {
+            eprintln!("Warning: {}", message);
+        }
+    }
+    Ok(file_bytes)
+}
+
+
+/// Points `link` at the identical entry `original` in the same directory. Returns false where symlinks aren't available.
+#[cfg(unix)]
+fn link_duplicate(original: &str, link: &Path) -> bool {
+    let _ = std::fs::remove_file(link);
+    std::os::unix::fs::symlink(original, link).is_ok()
+}
+
+#[cfg(not(unix))]
+fn link_duplicate(_original: &str, _link: &Path) -> bool {
+    false
+}
+
+
+/// Returns the (decompressed) YAX bytes of entry `index` without writing anything, e.g. to preview it
+/// with `yax_to_xml_convert::yax_bytes_to_xml`.
+pub fn read_pak_yax_bytes(pak_path: &str, index: usize) -> io::Result<Vec<u8>> {
+    let mut bytes = ByteDataWrapper::from_file(pak_path)?;
+    let (header_entries, _) = read_header_entries(&mut bytes, None)?;
+    let meta = header_entries.get(index).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Entry {} is out of bounds for a PAK with {} entries", index, header_entries.len()),
+        )
+    })?;
+    let file_sizes = entry_sizes(&header_entries, bytes.data.len())?;
+    read_pak_yax(meta, file_sizes[index] as usize, &mut bytes, index, &PakExtractOptions::default())
+}
+
+
+/// Calls `handler` with the index, header `type` and (decompressed) YAX bytes of every PAK entry, in header
+/// order, instead of writing any files. E.g. to convert the entries with `yax_to_xml_convert::yax_bytes_to_xml`.
+pub fn extract_pak_with_handler<F>(pak_path: &str, mut handler: F) -> io::Result<()>
+where
+    F: FnMut(usize, u32, &[u8]) -> io::Result<()>,
+{
+    let mut bytes = ByteDataWrapper::from_file(pak_path)?;
+    let (header_entries, _) = read_header_entries(&mut bytes, None)?;
+    let file_sizes = entry_sizes(&header_entries, bytes.data.len())?;
+    for (i, meta) in header_entries.iter().enumerate() {
+        let file_bytes = read_pak_yax(meta, file_sizes[i] as usize, &mut bytes, i, &PakExtractOptions::default())?;
+        handler(i, meta.r#type, &file_bytes)?;
     }
-    extracted_file.write_all(&file_bytes)?; 
     Ok(())
 }
 
 
+/// The entry among `candidates`, the unique entries already written to `extract_dir_path` with the content hash
+/// of `file_bytes`, whose content is `file_bytes`.
+fn find_duplicate(candidates: Option<&Vec<usize>>, file_bytes: &[u8], extract_dir_path: &Path) -> io::Result<Option<usize>> {
+    for &candidate in candidates.into_iter().flatten() {
+        let candidate_path = extract_dir_path.join(format!("{}.yax", candidate));
+        if fs::read(&candidate_path).map_err(|e| path_error(e, "read", &candidate_path))? == file_bytes {
+            return Ok(Some(candidate));
+        }
+    }
+    Ok(None)
+}
+
+
+/// Runs the conversion `convert`, with a panic in it (e.g. from quick-xml) returned as an error so one bad entry
+/// can't unwind through the whole extraction. With `panic = "abort"` a panic still aborts.
+fn catch_conversion_panic(convert: impl FnOnce() -> io::Result<()>) -> io::Result<()> {
+    panic::catch_unwind(AssertUnwindSafe(convert)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        Err(io::Error::other(format!("conversion panicked: {}", message)))
+    })
+}
+
+
+/// Converts the extracted entries `indices` of `extract_dir_path` from `<i>.yax` to `<i>.xml` on the rayon pool,
+/// `convert` getting the YAX and the XML path. With `error_sidecars` a failed or panicking conversion is written to
+/// `<i>.yax.error.txt` and returned with its index, otherwise the first one is the error. Once `cancel` is set
+/// the remaining conversions are skipped and the extraction fails.
+fn convert_entries<F>(
+    extract_dir_path: &Path,
+    indices: Vec<usize>,
+    error_sidecars: bool,
+    cancel: &Option<Arc<AtomicBool>>,
+    convert: F,
+) -> io::Result<Vec<(usize, String)>>
+where
+    F: Fn(&Path, &Path) -> io::Result<()> + Sync,
+{
+    indices.into_par_iter().filter_map(|i| {
+        if let Err(e) = check_cancelled(cancel) {
+            return Some(Err(e));
+        }
+        let yax_path = extract_dir_path.join(format!("{}.yax", i));
+        let xml_path = yax_path.with_extension("xml");
+        let e = catch_conversion_panic(|| convert(&yax_path, &xml_path)).err()?;
+        if !error_sidecars {
+            return Some(Err(io::Error::new(e.kind(), format!("Failed to convert {} to XML: {}", yax_path.display(), e))));
+        }
+        let error_path = extract_dir_path.join(format!("{}.yax.error.txt", i));
+        let message = format!("Failed to convert {}.yax to XML: {}\n", i, e);
+        Some(fs::write(&error_path, &message).map(|_| (i, e.to_string())).map_err(|e| path_error(e, "write", &error_path)))
+    }).collect()
+}
+
+
+/// The `pakInfo.json` record of entry `index`. The extra header bytes of a stride over 12 are kept as
+/// `header_extra` in hex, which `repack_pak_files` writes back.
+fn pak_info_entry(index: usize, meta: &HeaderEntry) -> serde_json::Value {
+    let mut file = json!({
+        "name": format!("{}.yax", index),
+        "type": meta.r#type,
+    });
+    if !meta.extra.is_empty() {
+        file["header_extra"] = json!(to_hex(&meta.extra));
+    }
+    file
+}
+
+
+/// Returns the `pakInfo.json` extracting the PAK would write, with each entry's `offset` and
+/// `uncompressed_size` added, without extracting any entry. For a quick overview of a PAK.
+pub fn read_pak_info(pak_path: &str) -> io::Result<String> {
+    let mut bytes = ByteDataWrapper::from_file(pak_path)?;
+    let (header_entries, entry_stride) = read_header_entries(&mut bytes, None)?;
+    let files: Vec<serde_json::Value> = header_entries
+        .iter()
+        .enumerate()
+        .map(|(i, meta)| {
+            let mut file = pak_info_entry(i, meta);
+            file["offset"] = json!(meta.offset);
+            file["uncompressed_size"] = json!(meta.uncompressed_size);
+            file
+        })
+        .collect();
+    Ok(serde_json::to_string_pretty(&json!({ "entry_stride": entry_stride, "files": files }))?)
+}
+
+
+/// Returns the entries of the PAK as a tree for reading at the command line: per entry its index, type,
+/// offset, stored and uncompressed size and whether it is compressed, followed by the totals.
+/// The type is the raw header value, the PAK format doesn't name its types.
+pub fn pak_tree_string(pak_path: &str) -> io::Result<String> {
+    let mut bytes = ByteDataWrapper::from_file(pak_path)?;
+    let (header_entries, entry_stride) = read_header_entries(&mut bytes, None)?;
+    let file_sizes = entry_sizes(&header_entries, bytes.data.len())?;
+
+    let file_name = Path::new(pak_path).file_name().map_or_else(|| pak_path.into(), |name| name.to_string_lossy());
+    let mut tree = format!("{} ({} entries, {} byte header entries)\n", file_name, header_entries.len(), entry_stride);
+    let mut total_stored = 0u64;
+    let mut total_uncompressed = 0u64;
+    for (i, meta) in header_entries.iter().enumerate() {
+        let stored = stored_entry(meta, file_sizes[i] as usize, &mut bytes, i)?;
+        let branch = if i + 1 == header_entries.len() { "└──" } else { "├──" };
+        tree.push_str(&format!(
+            "{} {}.yax  type {}  offset {}  stored {}  uncompressed {}{}\n",
+            branch,
+            i,
+            meta.r#type,
+            meta.offset,
+            file_sizes[i],
+            meta.uncompressed_size,
+            if stored.compressed { "  compressed" } else { "" },
+        ));
+        total_stored += u64::from(file_sizes[i]);
+        total_uncompressed += u64::from(meta.uncompressed_size);
+    }
+    tree.push_str(&format!(
+        "Total: {} entries, {} bytes stored, {} bytes uncompressed\n",
+        header_entries.len(), total_stored, total_uncompressed
+    ));
+    Ok(tree)
+}
+
+
+/// Options for `extract_pak_files_with_options`.
+#[derive(Debug, Clone, Default)]
+pub struct PakExtractOptions {
+    /// Convert the extracted YAX files to XML.
+    pub yax_to_xml: bool,
+    /// Write byte-identical entries only once. A duplicate is recorded as `duplicate_of` in `pakInfo.json`
+    /// and, where the platform supports it, symlinked to the first copy instead of being written again.
+    /// Where it doesn't, or the symlink can't be created, the duplicate is written as a copy.
+    pub dedup: bool,
+    /// Only extract the entries with these indices. `pakInfo.json` then lists just those entries.
+    pub entries: Option<Range<usize>>,
+    /// Cache the YAX to XML conversions in this directory, see `YaxConvertOptions::cache_dir`.
+    pub xml_cache_dir: Option<PathBuf>,
+    /// Size of the header entries, detected if `None` (see `read_header_entries`). The standard PAK uses 12.
+    pub entry_stride: Option<u32>,
+    /// With `yax_to_xml`, only convert entries whose header `type` is in this list and leave the others as
+    /// plain `.yax` files, for PAKs that also hold non-YAX entries. `None` converts every entry.
+    pub convert_types: Option<Vec<u32>>,
+    /// With `yax_to_xml`, write a failing conversion's error to `<index>.yax.error.txt` and go on with the other
+    /// entries instead of failing the extraction. The failures are also listed as `conversion_errors` in `pakInfo.json`.
+    pub error_sidecars: bool,
+    /// Fail when a compressed entry doesn't decompress to the size its header states, instead of warning.
+    pub strict: bool,
+    /// Don't print the warnings about entries that don't decompress as their header says to stderr.
+    pub quiet: bool,
+    /// Checked before every entry and every conversion, see `ExtractOptions::cancel`.
+    pub cancel: Option<Arc<AtomicBool>>,
+}
+
+
+/// Extracts only the PAK entries `start..end`, e.g. to look at one part of a large PAK.
+pub fn extract_pak_range(
+    pak_path: &str,
+    extract_dir: &str,
+    start: usize,
+    end: usize,
+    yax_to_xml: bool,
+) -> io::Result<Vec<String>> {
+    let options = PakExtractOptions {
+        yax_to_xml,
+        entries: Some(start..end),
+        ..Default::default()
+    };
+    extract_pak_files_with_options(pak_path, extract_dir, &options)
+}
+
+
 pub async fn extract_pak_files(
     pak_path: &str,
     extract_dir: &str,
     yax_to_xml: bool,
+) -> io::Result<Vec<String>> {
+    let pak_path = pak_path.to_string();
+    let extract_dir = extract_dir.to_string();
+    tokio::task::spawn_blocking(move || extract_pak_files_sync(&pak_path, &extract_dir, yax_to_xml))
+        .await
+        .map_err(io::Error::other)?
+}
+
+
+/// Blocking version of `extract_pak_files` for callers that don't run a tokio runtime.
+/// YAX to XML conversion still runs in parallel on the rayon thread pool.
+pub fn extract_pak_files_sync(
+    pak_path: &str,
+    extract_dir: &str,
+    yax_to_xml: bool,
+) -> io::Result<Vec<String>> {
+    let options = PakExtractOptions {
+        yax_to_xml,
+        ..Default::default()
+    };
+    extract_pak_files_with_options(pak_path, extract_dir, &options)
+}
+
+
+pub fn extract_pak_files_with_options(
+    pak_path: &str,
+    extract_dir: &str,
+    options: &PakExtractOptions,
 ) -> io::Result<Vec<String>> {
     let mut bytes = ByteDataWrapper::from_file(pak_path)?;  
 
-    bytes.position = 8; 
-    let first_offset = bytes.read_u32();
-    let file_count = (first_offset - 4) / 12; 
+    let (header_entries, entry_stride) = read_header_entries(&mut bytes, options.entry_stride)?;
+    let range = match &options.entries {
+        Some(range) if range.start > range.end || range.end > header_entries.len() => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Entry range {:?} is out of bounds for a PAK with {} entries", range, header_entries.len()),
+            ));
+        }
+        Some(range) => range.clone(),
+        None => 0..header_entries.len(),
+    };
 
-    bytes.position = 0; 
-    let mut header_entries = Vec::with_capacity(file_count as usize); 
-    for _ in 0..file_count { 
-        header_entries.push(HeaderEntry::new(&mut bytes));
-    }
+    let file_sizes = entry_sizes(&header_entries, bytes.data.len())?;
 
-    let mut file_sizes = Vec::with_capacity(file_count as usize); 
-    for i in 0..file_count as usize {
-        let size = if i == file_count as usize - 1 {
-            bytes.data.len() as u32 - header_entries[i].offset 
-        } else {
-            header_entries[i + 1].offset - header_entries[i].offset 
-        };
-        file_sizes.push(size);
+    create_extract_dir(Path::new(extract_dir))?;
+    let extract_dir_path = Path::new(extract_dir);
+    // Unique entries by content hash, only the entries with an equal hash are compared byte for byte
+    let mut unique_entries: HashMap<u128, Vec<usize>> = HashMap::new();
+    let mut duplicate_of = vec![None; header_entries.len()];
+    let mut stored_entries = vec![StoredEntry::default(); header_entries.len()];
+    for (i, meta) in header_entries.iter().enumerate().take(range.end).skip(range.start) {  
+        check_cancelled(&options.cancel)?;
+        stored_entries[i] = stored_entry(meta, file_sizes[i] as usize, &mut bytes, i)?;
+        let file_bytes = read_pak_yax(meta, file_sizes[i] as usize, &mut bytes, i, options)?;
+        let yax_path = extract_dir_path.join(format!("{}.yax", i));
+        let hash = if options.dedup { content_hash(&file_bytes) } else { 0 };
+        if options.dedup {
+            if let Some(original) = find_duplicate(unique_entries.get(&hash), &file_bytes, extract_dir_path)? {
+                let original_name = format!("{}.yax", original);
+                let linked = link_duplicate(&original_name, &yax_path);
+                duplicate_of[i] = Some(original_name);
+                if linked {
+                    continue;
+                }
+                // Without a symlink the duplicate is written as a copy, so every returned path exists
+            }
+        }
+
+        File::create(&yax_path)
+            .and_then(|mut extracted_file| extracted_file.write_all(&file_bytes))
+            .map_err(|e| path_error(e, &format!("write PAK entry {} to", i), &yax_path))?;
+        if options.dedup {
+            unique_entries.entry(hash).or_default().push(i);
+        }
     }
 
-    create_dir_all(extract_dir)?;
-    let extract_dir_path = Path::new(extract_dir);
-    for (i, meta) in header_entries.iter().enumerate() {  
-        extract_pak_yax(meta, file_sizes[i] as usize, &mut bytes, extract_dir_path, i).await?;
+    let mut conversion_errors = Vec::new();
+    if options.yax_to_xml { 
+        let convert_options = YaxConvertOptions {
+            cache_dir: options.xml_cache_dir.clone(),
+            ..Default::default()
+        };
+        let convertible = range.clone().filter(|&i| {
+            let is_convertible_type = options.convert_types.as_ref().is_none_or(|types| types.contains(&header_entries[i].r#type));
+            // Empty entries have no YAX to convert
+            is_convertible_type && header_entries[i].uncompressed_size != 0
+        }).collect();
+        conversion_errors = convert_entries(extract_dir_path, convertible, options.error_sidecars, &options.cancel, |yax_path, xml_path| {
+            convert_yax_to_xml_with_options(&yax_path.to_string_lossy(), &xml_path.to_string_lossy(), &convert_options)
+        })?;
     }
 
-    let meta = json!({ 
-        "files": header_entries.iter().enumerate().map(|(i, meta)| json!({
-            "name": format!("{}.yax", i),
-            "type": meta.r#type,
-        })).collect::<Vec<_>>()
+    let mut meta = json!({ 
+        "entry_stride": entry_stride,
+        "files": header_entries.iter().enumerate().take(range.end).skip(range.start).map(|(i, meta)| {
+            let mut file = pak_info_entry(i, meta);
+            file["compressed"] = json!(stored_entries[i].compressed);
+            file["stored_read_size"] = json!(stored_entries[i].read_size);
+            if let Some(original_name) = &duplicate_of[i] {
+                file["duplicate_of"] = json!(original_name);
+            }
+            file
+        }).collect::<Vec<_>>()
     });
 
+    if !conversion_errors.is_empty() {
+        meta["conversion_errors"] = conversion_errors
+            .iter()
+            .map(|(i, error)| json!({ "name": format!("{}.yax", i), "error": error }))
+            .collect();
+    }
+
     let pak_info_path = Path::new(extract_dir).join("pakInfo.json");  
     let mut pak_info_file = File::create(pak_info_path)?; 
     pak_info_file.write_all(serde_json::to_string_pretty(&meta)?.as_bytes())?; 
 
-    if yax_to_xml { 
-        let tasks: Vec<_> = (0..file_count as usize).map(|i| { 
-            let extract_dir_path = extract_dir_path.to_path_buf();
-            tokio::task::spawn(async move { 
-                let yax_path = extract_dir_path.join(format!("{}.yax", i)); 
-                let xml_path = yax_path.with_extension("xml"); 
-                convert_yax_to_xml(yax_path.to_str().unwrap(), xml_path.to_str().unwrap()); 
-            })
-        }).collect();
-        for task in tasks { 
-            task.await.unwrap();
-        }
-    }
-
-    Ok((0..file_count as usize).map(|i| extract_dir_path.join(format!("{}.yax", i)).to_str().unwrap().to_string()).collect()) 
+    Ok(range.map(|i| extract_dir_path.join(format!("{}.yax", i)).to_str().unwrap().to_string()).collect()) 
 }
 
 
@@ -157,11 +598,10 @@ pub extern "C" fn extract_pak_files_ffi(
     extract_dir: *const c_char,
     yax_to_xml: bool,
 ) -> *mut c_char {
-    let pak_path = unsafe { CStr::from_ptr(pak_path) }.to_str().unwrap(); 
-    let extract_dir = unsafe { CStr::from_ptr(extract_dir) }.to_str().unwrap();  
-
-    let rt = tokio::runtime::Runtime::new().unwrap(); 
-    let result = rt.block_on(internal_extract_pak_files(pak_path, extract_dir, yax_to_xml)); 
+    let result = ffi_path(pak_path, "pak_path").and_then(|pak_path| {
+        let extract_dir = ffi_path(extract_dir, "extract_dir")?;
+        extract_pak_files_sync(pak_path, extract_dir, yax_to_xml)
+    });
 
     match result {
         Ok(files) => {
@@ -174,64 +614,770 @@ pub extern "C" fn extract_pak_files_ffi(
 }
 
 
-async fn internal_extract_pak_files(
-    pak_path: &str,
-    extract_dir: &str,
+/// Same as `extract_pak_files_ffi`, but reports through a status code and out-parameters like
+/// `extract_dat_files_ffi_status`.
+#[no_mangle]
+pub extern "C" fn extract_pak_files_ffi_status(
+    pak_path: *const c_char,
+    extract_dir: *const c_char,
     yax_to_xml: bool,
-) -> io::Result<Vec<String>> {
-    let mut bytes = ByteDataWrapper::from_file(pak_path)?; 
+    files_out: *mut *mut c_char,
+    error_out: *mut *mut c_char,
+) -> c_int {
+    let result = ffi_path(pak_path, "pak_path").and_then(|pak_path| {
+        let extract_dir = ffi_path(extract_dir, "extract_dir")?;
+        extract_pak_files_sync(pak_path, extract_dir, yax_to_xml)
+    });
+    ffi_status(result, files_out, error_out)
+}
 
-    bytes.position = 8; 
-    let first_offset = bytes.read_u32(); 
-    let file_count = (first_offset - 4) / 12;  
 
-    bytes.position = 0;
-    let mut header_entries = Vec::with_capacity(file_count as usize);  
-    for _ in 0..file_count { 
-        header_entries.push(HeaderEntry::new(&mut bytes));
+/// Same as `extract_pak_files_ffi`, but takes null-terminated UTF-16 paths like `extract_dat_files_ffi_w`.
+#[cfg(windows)]
+#[no_mangle]
+pub extern "C" fn extract_pak_files_ffi_w(
+    pak_path: *const u16,
+    extract_dir: *const u16,
+    yax_to_xml: bool,
+) -> *mut c_char {
+    use crate::ffi_wide_path;
+
+    let result = ffi_wide_path(pak_path, "pak_path").and_then(|pak_path| {
+        let extract_dir = ffi_wide_path(extract_dir, "extract_dir")?;
+        extract_pak_files_sync(&pak_path, &extract_dir, yax_to_xml)
+    });
+
+    match result {
+        Ok(files) => CString::new(json!(files).to_string()).unwrap().into_raw(),
+        Err(_) => ptr::null_mut(),
     }
+}
+
 
-    let mut file_sizes = Vec::with_capacity(file_count as usize); 
-    for i in 0..file_count as usize { 
-        let size = if i == file_count as usize - 1 {
-            bytes.data.len() as u32 - header_entries[i].offset 
-        } else {
-            header_entries[i + 1].offset - header_entries[i].offset 
+/// Same as `extract_pak_files_ffi`, but gives up after `timeout_ms` milliseconds (`0` disables the timeout).
+/// On failure this returns a JSON envelope `{"error": {"kind": ..., "message": ...}}` instead of null.
+#[no_mangle]
+pub extern "C" fn extract_pak_files_ffi_timeout(
+    pak_path: *const c_char,
+    extract_dir: *const c_char,
+    yax_to_xml: bool,
+    timeout_ms: c_uint,
+) -> *mut c_char {
+    let (pak_path, extract_dir) = match (ffi_path(pak_path, "pak_path"), ffi_path(extract_dir, "extract_dir")) {
+        (Ok(pak_path), Ok(extract_dir)) => (pak_path, extract_dir),
+        (Err(e), _) | (_, Err(e)) => return ffi_error_envelope(&e),
+    };
+    let (pak_path, extract_dir) = (pak_path.to_string(), extract_dir.to_string());
+
+    run_ffi_with_timeout(move |cancel| {
+        let options = PakExtractOptions {
+            yax_to_xml,
+            cancel: Some(cancel),
+            ..Default::default()
         };
-        file_sizes.push(size);
+        extract_pak_files_with_options(&pak_path, &extract_dir, &options)
+    }, timeout_ms)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    fn header_entries(pak: Vec<u8>) -> io::Result<(Vec<HeaderEntry>, u32)> {
+        read_header_entries(&mut ByteDataWrapper { data: pak, position: 0 }, None)
     }
 
-    create_dir_all(extract_dir)?; 
+    #[test]
+    fn first_offset_not_4_plus_a_multiple_of_the_stride_is_invalid() {
+        let mut pak = pak_bytes(&[TestPakEntry::raw(b"abcdefgh")]);
+        // 17 fits none of 12, 16, 20 and 24 byte entries
+        pak[8..12].copy_from_slice(&17u32.to_le_bytes());
+        let e = header_entries(pak).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(e.to_string().contains("first offset 17"), "{}", e);
+    }
 
-    let extract_dir_path = Path::new(extract_dir);
-    for (i, meta) in header_entries.iter().enumerate() { 
-        extract_pak_yax(meta, file_sizes[i] as usize, &mut bytes, extract_dir_path, i).await?;
+    #[test]
+    fn first_offset_past_the_end_of_the_file_is_invalid() {
+        let mut pak = pak_bytes(&[TestPakEntry::raw(b"abcd")]);
+        pak[8..12].copy_from_slice(&(4 + 12 * 100u32).to_le_bytes());
+        assert_eq!(header_entries(pak).unwrap_err().kind(), io::ErrorKind::InvalidData);
     }
 
-    let meta = json!({ 
-        "files": header_entries.iter().enumerate().map(|(i, meta)| json!({
-            "name": format!("{}.yax", i),
-            "type": meta.r#type,
-        })).collect::<Vec<_>>()
-    });
+    #[test]
+    fn consistent_header_gives_one_entry_per_12_bytes() {
+        let pak = pak_bytes(&[TestPakEntry::raw(b"abcd"), TestPakEntry::raw(b"efgh"), TestPakEntry::raw(b"ijkl")]);
+        let (entries, stride) = header_entries(pak).unwrap();
+        assert_eq!(stride, 12);
+        assert_eq!(entries.iter().map(|entry| entry.offset).collect::<Vec<_>>(), [40, 44, 48]);
+    }
+    #[test]
+    fn pak_passing_the_check_extracts_raw_and_compressed_entries() {
+        let dir = temp_dir();
+        let compressible = b"yax ".repeat(64);
+        let pak_path = write_file(dir.path(), "test.pak", &pak_bytes(&[TestPakEntry::compressed(&compressible), TestPakEntry::raw(b"raw")]));
+        let files = extract_pak_files_sync(&pak_path, &path_str(&dir.path().join("out")), false).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(fs::read(&files[0]).unwrap(), compressible);
+        assert_eq!(fs::read(&files[1]).unwrap(), b"raw");
+    }
+
+    #[tokio::test]
+    async fn async_extraction_matches_the_sync_one() {
+        let dir = temp_dir();
+        let pak_path = write_file(dir.path(), "test.pak", &pak_bytes(&[TestPakEntry::raw(b"abcd"), TestPakEntry::raw(b"efgh")]));
+        let sync_files = extract_pak_files_sync(&pak_path, &path_str(&dir.path().join("sync")), false).unwrap();
+        let async_files = extract_pak_files(&pak_path, &path_str(&dir.path().join("async")), false).await.unwrap();
+        assert_eq!(sync_files.len(), async_files.len());
+        for (sync_file, async_file) in sync_files.iter().zip(&async_files) {
+            assert_eq!(fs::read(sync_file).unwrap(), fs::read(async_file).unwrap());
+        }
+    }
+
+    fn header_entry(uncompressed_size: u32, offset: u32) -> HeaderEntry {
+        HeaderEntry { r#type: 1, uncompressed_size, offset, extra: Vec::new() }
+    }
+
+    #[test]
+    fn entry_sizes_near_u32_max_dont_wrap() {
+        let entries = [header_entry(4, u32::MAX - 8), header_entry(4, u32::MAX - 4)];
+        assert_eq!(entry_sizes(&entries, u32::MAX as usize).unwrap(), [4, 4]);
+        assert_eq!(entry_sizes(&[header_entry(0, u32::MAX)], u32::MAX as usize).unwrap(), [0]);
+    }
+
+    #[test]
+    fn offsets_going_backwards_are_invalid() {
+        let entries = [header_entry(4, u32::MAX - 4), header_entry(4, 16)];
+        let e = entry_sizes(&entries, u32::MAX as usize).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        // The last entry ends at the end of the file
+        assert!(entry_sizes(&[header_entry(4, 100)], 50).is_err());
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn pak_longer_than_u32_offsets_can_address_is_invalid() {
+        let e = entry_sizes(&[header_entry(4, 16)], u32::MAX as usize + 1).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn padding_larger_than_the_stored_entry_is_no_read_size() {
+        // u32::MAX has remainder 3 modulo 4, so one padding byte
+        assert_eq!(aligned_read_size(&header_entry(u32::MAX, 0), 1), Some(0));
+        assert_eq!(aligned_read_size(&header_entry(1, 0), 2), None);
+        assert_eq!(aligned_read_size(&header_entry(8, 0), usize::MAX), Some(usize::MAX));
+    }
+
+    #[test]
+    fn first_offset_of_u32_max_is_invalid() {
+        let mut pak = pak_bytes(&[TestPakEntry::raw(b"abcd")]);
+        pak[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(header_entries(pak).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    fn pak_info(extract_dir: &Path) -> serde_json::Value {
+        serde_json::from_slice(&fs::read(extract_dir.join("pakInfo.json")).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn dedup_links_identical_entries_to_the_first_copy() {
+        let dir = temp_dir();
+        let pak_path = write_file(dir.path(), "test.pak", &pak_bytes(&[TestPakEntry::raw(b"same"), TestPakEntry::raw(b"diff"), TestPakEntry::raw(b"same")]));
+        let extract_dir = dir.path().join("out");
+        let options = PakExtractOptions { dedup: true, ..Default::default() };
+        let files = extract_pak_files_with_options(&pak_path, &path_str(&extract_dir), &options).unwrap();
+
+        assert_eq!(files.len(), 3);
+        assert_eq!(fs::read(&files[2]).unwrap(), b"same");
+        #[cfg(unix)]
+        assert_eq!(fs::read_link(extract_dir.join("2.yax")).unwrap(), Path::new("0.yax"));
+        let pak_info = pak_info(&extract_dir);
+        assert_eq!(pak_info["files"][2]["duplicate_of"], "0.yax");
+        assert!(pak_info["files"][0].get("duplicate_of").is_none());
+        assert!(pak_info["files"][1].get("duplicate_of").is_none());
+    }
+
+    #[test]
+    fn dedup_compares_the_bytes_of_entries_with_equal_hashes() {
+        let dir = temp_dir();
+        write_file(dir.path(), "0.yax", b"same");
+        write_file(dir.path(), "1.yax", b"diff");
+        // Entries 0 and 1 as if their contents had collided on the hash
+        let candidates = vec![1, 0];
+        assert_eq!(find_duplicate(Some(&candidates), b"same", dir.path()).unwrap(), Some(0));
+        assert_eq!(find_duplicate(Some(&vec![1]), b"same", dir.path()).unwrap(), None);
+        assert_eq!(find_duplicate(None, b"same", dir.path()).unwrap(), None);
+    }
+
+    #[test]
+    fn without_dedup_identical_entries_are_separate_files() {
+        let dir = temp_dir();
+        let pak_path = write_file(dir.path(), "test.pak", &pak_bytes(&[TestPakEntry::raw(b"same"), TestPakEntry::raw(b"same")]));
+        let extract_dir = dir.path().join("out");
+        extract_pak_files_sync(&pak_path, &path_str(&extract_dir), false).unwrap();
+        assert!(!fs::symlink_metadata(extract_dir.join("1.yax")).unwrap().file_type().is_symlink());
+        assert!(pak_info(&extract_dir)["files"][1].get("duplicate_of").is_none());
+    }
+
+    #[test]
+    fn range_extracts_only_the_middle_entries() {
+        let dir = temp_dir();
+        let entries: Vec<Vec<u8>> = (0..5u8).map(|i| vec![i; 4]).collect();
+        let pak = pak_bytes(&entries.iter().map(|data| TestPakEntry::raw(data)).collect::<Vec<_>>());
+        let pak_path = write_file(dir.path(), "test.pak", &pak);
+        let extract_dir = dir.path().join("out");
+        let files = extract_pak_range(&pak_path, &path_str(&extract_dir), 1, 3, false).unwrap();
+
+        assert_eq!(files, [path_str(&extract_dir.join("1.yax")), path_str(&extract_dir.join("2.yax"))]);
+        assert_eq!(fs::read(&files[1]).unwrap(), [2; 4]);
+        let mut written: Vec<String> = fs::read_dir(&extract_dir).unwrap().map(|entry| entry.unwrap().file_name().into_string().unwrap()).collect();
+        written.sort();
+        assert_eq!(written, ["1.yax", "2.yax", "pakInfo.json"]);
+        let names: Vec<_> = pak_info(&extract_dir)["files"].as_array().unwrap().iter().map(|file| file["name"].clone()).collect();
+        assert_eq!(names, ["1.yax", "2.yax"]);
+    }
+
+    #[test]
+    fn range_past_the_entry_count_is_invalid_input() {
+        let dir = temp_dir();
+        let pak_path = write_file(dir.path(), "test.pak", &pak_bytes(&[TestPakEntry::raw(b"abcd")]));
+        let extract_dir = dir.path().join("out");
+        for (start, end) in [(0, 2), (1, 0)] {
+            let e = extract_pak_range(&pak_path, &path_str(&extract_dir), start, end, false).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        }
+        assert!(!extract_dir.exists());
+    }
+
+    #[test]
+    fn empty_range_writes_only_the_manifest() {
+        let dir = temp_dir();
+        let pak_path = write_file(dir.path(), "test.pak", &pak_bytes(&[TestPakEntry::raw(b"abcd")]));
+        let extract_dir = dir.path().join("out");
+        assert!(extract_pak_range(&pak_path, &path_str(&extract_dir), 1, 1, false).unwrap().is_empty());
+        assert!(pak_info(&extract_dir)["files"].as_array().unwrap().is_empty());
+    }
+
+    #[test]
+    fn zero_size_entry_is_an_empty_file_without_xml() {
+        let dir = temp_dir();
+        let yax = yax_bytes("<root><text>a</text></root>");
+        let pak = pak_bytes(&[TestPakEntry::raw(&yax), TestPakEntry::raw(b""), TestPakEntry::raw(&yax)]);
+        let pak_path = write_file(dir.path(), "test.pak", &pak);
+        let extract_dir = dir.path().join("out");
+        let files = extract_pak_files_sync(&pak_path, &path_str(&extract_dir), true).unwrap();
+
+        assert_eq!(files.len(), 3);
+        assert_eq!(fs::read(extract_dir.join("1.yax")).unwrap(), b"");
+        assert!(!extract_dir.join("1.xml").exists());
+        assert!(extract_dir.join("0.xml").exists());
+        assert!(extract_dir.join("2.xml").exists());
+    }
+
+    #[test]
+    fn zero_size_entry_owning_padding_bytes_is_empty() {
+        let dir = temp_dir();
+        let mut pak = pak_bytes(&[TestPakEntry::raw(b"abcd"), TestPakEntry::raw(b"")]);
+        pak.extend_from_slice(&[0xff; 4]);
+        let pak_path = write_file(dir.path(), "test.pak", &pak);
+        let files = extract_pak_files_sync(&pak_path, &path_str(&dir.path().join("out")), false).unwrap();
+        assert_eq!(fs::read(&files[1]).unwrap(), b"");
+    }
+
+    #[test]
+    fn invalid_utf8_pak_path_is_reported_without_panicking() {
+        let pak_path = CString::new(b"\xff.pak".to_vec()).unwrap();
+        let extract_dir = c_string("out");
+        let result = take_ffi_string(extract_pak_files_ffi_timeout(pak_path.as_ptr(), extract_dir.as_ptr(), false, 0)).unwrap();
+        let result: serde_json::Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(result["error"]["kind"], "InvalidInput");
+        assert!(extract_pak_files_ffi(pak_path.as_ptr(), extract_dir.as_ptr(), false).is_null());
+    }
+
+    #[test]
+    fn stride_16_header_is_detected() {
+        let pak = pak_bytes_with_stride(&[TestPakEntry::raw(b"abcd"), TestPakEntry::raw(b"efgh")], 16);
+        let (entries, stride) = header_entries(pak).unwrap();
+        assert_eq!(stride, 16);
+        assert_eq!(entries.iter().map(|entry| entry.offset).collect::<Vec<_>>(), [36, 40]);
+        assert_eq!(entries[1].extra, [1; 4]);
+    }
+
+    #[test]
+    fn stride_fitting_several_candidates_is_picked_by_plausible_offsets() {
+        // 48 header bytes fit 12, 16 and 24 byte entries, but only 16 gives offsets inside the file
+        let entries = [TestPakEntry::raw(b"abcd"), TestPakEntry::raw(b"efgh"), TestPakEntry::raw(b"ijkl")];
+        let (header_entries, stride) = header_entries(pak_bytes_with_stride(&entries, 16)).unwrap();
+        assert_eq!(stride, 16);
+        assert_eq!(header_entries.len(), 3);
+    }
+
+    #[test]
+    fn extraction_records_the_stride_and_extra_header_bytes() {
+        let dir = temp_dir();
+        let pak = pak_bytes_with_stride(&[TestPakEntry::raw(b"abcd"), TestPakEntry::raw(b"efgh")], 20);
+        let pak_path = write_file(dir.path(), "test.pak", &pak);
+        let extract_dir = dir.path().join("out");
+        let files = extract_pak_files_sync(&pak_path, &path_str(&extract_dir), false).unwrap();
+        assert_eq!(fs::read(&files[1]).unwrap(), b"efgh");
+
+        let info = pak_info(&extract_dir);
+        assert_eq!(info["entry_stride"], 20);
+        assert_eq!(info["files"][1]["header_extra"], "0101010101010101");
+    }
+
+    #[test]
+    fn given_stride_overrides_detection() {
+        let dir = temp_dir();
+        let pak = pak_bytes_with_stride(&[TestPakEntry::raw(b"abcd"), TestPakEntry::raw(b"efgh")], 16);
+        let pak_path = write_file(dir.path(), "test.pak", &pak);
+        let options = PakExtractOptions { entry_stride: Some(12), ..Default::default() };
+        let error = extract_pak_files_with_options(&pak_path, &path_str(&dir.path().join("out")), &options).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let options = PakExtractOptions { entry_stride: Some(16), ..Default::default() };
+        let files = extract_pak_files_with_options(&pak_path, &path_str(&dir.path().join("out")), &options).unwrap();
+        assert_eq!(files.len(), 2);
+    }
+
+    #[test]
+    fn only_allowed_types_are_converted() {
+        let dir = temp_dir();
+        let yax = yax_bytes("<root><text>a</text></root>");
+        let entries = [TestPakEntry::raw(&yax), TestPakEntry { r#type: 5, data: b"not yax data", compressed: false }];
+        let pak_path = write_file(dir.path(), "test.pak", &pak_bytes(&entries));
+
+        let options = PakExtractOptions { yax_to_xml: true, ..Default::default() };
+        assert!(extract_pak_files_with_options(&pak_path, &path_str(&dir.path().join("all")), &options).is_err());
+
+        let extract_dir = dir.path().join("typed");
+        let options = PakExtractOptions { yax_to_xml: true, convert_types: Some(vec![1]), ..Default::default() };
+        extract_pak_files_with_options(&pak_path, &path_str(&extract_dir), &options).unwrap();
+        assert!(fs::read_to_string(extract_dir.join("0.xml")).unwrap().contains("<text>a</text>"));
+        assert!(!extract_dir.join("1.xml").exists());
+        assert_eq!(fs::read(extract_dir.join("1.yax")).unwrap(), b"not yax data");
+    }
+
+    #[test]
+    fn failed_write_names_the_entry_and_path() {
+        let dir = temp_dir();
+        let pak_path = write_file(dir.path(), "test.pak", &pak_bytes(&[TestPakEntry::raw(b"abcd"), TestPakEntry::raw(b"efgh")]));
+        let extract_dir = dir.path().join("out");
+        fs::create_dir_all(extract_dir.join("1.yax").join("blocker")).unwrap();
+
+        let error = extract_pak_files_sync(&pak_path, &path_str(&extract_dir), false).unwrap_err();
+        let message = error.to_string();
+        assert!(message.contains("write PAK entry 1 to"), "{}", message);
+        assert!(message.contains(&path_str(&extract_dir.join("1.yax"))), "{}", message);
+    }
+
+    #[test]
+    fn reads_one_entry_into_memory() {
+        let dir = temp_dir();
+        let compressible = b"yax ".repeat(64);
+        let pak = pak_bytes(&[TestPakEntry::raw(b"abcd"), TestPakEntry::compressed(&compressible), TestPakEntry::raw(b"efgh")]);
+        let pak_path = write_file(dir.path(), "test.pak", &pak);
+
+        assert_eq!(read_pak_yax_bytes(&pak_path, 0).unwrap(), b"abcd");
+        assert_eq!(read_pak_yax_bytes(&pak_path, 1).unwrap(), compressible);
+        assert_eq!(read_pak_yax_bytes(&pak_path, 2).unwrap(), b"efgh");
+        // Nothing but the PAK was written
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
 
-    let pak_info_path = Path::new(extract_dir).join("pakInfo.json"); 
-    let mut pak_info_file = File::create(pak_info_path)?;
-    pak_info_file.write_all(serde_json::to_string_pretty(&meta)?.as_bytes())?;  
-
-    if yax_to_xml { 
-        let tasks: Vec<_> = (0..file_count as usize).map(|i| { 
-            let extract_dir_path = extract_dir_path.to_path_buf();
-            tokio::task::spawn(async move { 
-                let yax_path = extract_dir_path.join(format!("{}.yax", i)); 
-                let xml_path = yax_path.with_extension("xml"); 
-                convert_yax_to_xml(yax_path.to_str().unwrap(), xml_path.to_str().unwrap());  
+    #[test]
+    fn reading_an_entry_past_the_end_is_invalid_input() {
+        let dir = temp_dir();
+        let pak_path = write_file(dir.path(), "test.pak", &pak_bytes(&[TestPakEntry::raw(b"abcd")]));
+        let error = read_pak_yax_bytes(&pak_path, 1).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("with 1 entries"), "{}", error);
+    }
+
+    #[test]
+    fn broken_yax_gets_an_error_sidecar() {
+        let dir = temp_dir();
+        let yax = yax_bytes("<root><text>a</text></root>");
+        let entries = [TestPakEntry::raw(&yax), TestPakEntry::raw(b"not yax data"), TestPakEntry::raw(&yax)];
+        let pak_path = write_file(dir.path(), "test.pak", &pak_bytes(&entries));
+
+        let options = PakExtractOptions { yax_to_xml: true, ..Default::default() };
+        let error = extract_pak_files_with_options(&pak_path, &path_str(&dir.path().join("failing")), &options).unwrap_err();
+        assert!(error.to_string().contains("1.yax"), "{}", error);
+
+        let extract_dir = dir.path().join("out");
+        let options = PakExtractOptions { yax_to_xml: true, error_sidecars: true, ..Default::default() };
+        extract_pak_files_with_options(&pak_path, &path_str(&extract_dir), &options).unwrap();
+        assert!(extract_dir.join("0.xml").exists() && extract_dir.join("2.xml").exists());
+        assert!(!extract_dir.join("1.xml").exists());
+        let sidecar = fs::read_to_string(extract_dir.join("1.yax.error.txt")).unwrap();
+        assert!(sidecar.starts_with("Failed to convert 1.yax to XML: Not a YAX file"), "{}", sidecar);
+
+        let conversion_errors = pak_info(&extract_dir)["conversion_errors"].clone();
+        assert_eq!(conversion_errors.as_array().unwrap().len(), 1);
+        assert_eq!(conversion_errors[0]["name"], "1.yax");
+        assert!(conversion_errors[0]["error"].as_str().unwrap().contains("Not a YAX file"));
+    }
+
+    /// A PAK with one compressed entry whose header claims 300 bytes instead of the 256 it decompresses to.
+    fn pak_with_wrong_uncompressed_size() -> Vec<u8> {
+        let mut pak = pak_bytes(&[TestPakEntry::compressed(&b"yax ".repeat(64))]);
+        pak[4..8].copy_from_slice(&300u32.to_le_bytes());
+        pak
+    }
+
+    #[test]
+    fn wrong_uncompressed_size_is_a_warning() {
+        if in_child_test() {
+            let dir = temp_dir();
+            let pak_path = write_file(dir.path(), "test.pak", &pak_with_wrong_uncompressed_size());
+            let quiet = std::env::var_os("QUIET").is_some();
+            let options = PakExtractOptions { quiet, ..Default::default() };
+            let files = extract_pak_files_with_options(&pak_path, &path_str(&dir.path().join("out")), &options).unwrap();
+            assert_eq!(fs::read(&files[0]).unwrap(), b"yax ".repeat(64));
+            return;
+        }
+        let test = "pak_extract::tests::wrong_uncompressed_size_is_a_warning";
+        let stderr = child_test_stderr(test, &[]);
+        assert!(stderr.contains("Warning: PAK entry 0 decompresses to 256 bytes but its header says 300"), "{}", stderr);
+        assert!(!child_test_stderr(test, &[("QUIET", "1")]).contains("Warning"));
+    }
+
+    #[test]
+    fn wrong_uncompressed_size_fails_in_strict_mode() {
+        let dir = temp_dir();
+        let pak_path = write_file(dir.path(), "test.pak", &pak_with_wrong_uncompressed_size());
+        let options = PakExtractOptions { strict: true, ..Default::default() };
+        let error = extract_pak_files_with_options(&pak_path, &path_str(&dir.path().join("out")), &options).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("decompresses to 256 bytes but its header says 300"), "{}", error);
+    }
+
+    #[test]
+    fn aligned_read_size_strips_the_padding_of_every_residue() {
+        // (uncompressed_size, padded size in the PAK)
+        for (uncompressed_size, raw_size) in [(8, 8), (5, 8), (6, 8), (7, 8)] {
+            let meta = header_entry(uncompressed_size, 0);
+            assert_eq!(aligned_read_size(&meta, raw_size), Some(uncompressed_size as usize), "{}", uncompressed_size);
+        }
+        // Only the alignment padding is stripped, not any bytes after it
+        assert_eq!(aligned_read_size(&header_entry(5, 0), 12), Some(9));
+        assert_eq!(aligned_read_size(&header_entry(4, 0), 0), Some(0));
+        assert_eq!(aligned_read_size(&header_entry(7, 0), 0), None);
+    }
+
+    #[test]
+    fn entries_of_every_residue_extract_without_padding() {
+        let dir = temp_dir();
+        let data: Vec<Vec<u8>> = (8..12).map(|length| vec![length as u8; length]).collect();
+        let entries: Vec<TestPakEntry> = data.iter().map(|data| TestPakEntry::raw(data)).collect();
+        let pak_path = write_file(dir.path(), "test.pak", &pak_bytes(&entries));
+        let files = extract_pak_files_sync(&pak_path, &path_str(&dir.path().join("out")), false).unwrap();
+        for (file, data) in files.iter().zip(&data) {
+            assert_eq!(&fs::read(file).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn pak_info_lists_every_entry_without_extracting() {
+        let dir = temp_dir();
+        let entries = [TestPakEntry::raw(b"abcd"), TestPakEntry { r#type: 7, data: b"efg", compressed: false }, TestPakEntry::raw(b"")];
+        let pak_path = write_file(dir.path(), "test.pak", &pak_bytes(&entries));
+        let info: serde_json::Value = serde_json::from_str(&read_pak_info(&pak_path).unwrap()).unwrap();
+
+        assert_eq!(info["entry_stride"], 12);
+        let files = info["files"].as_array().unwrap();
+        assert_eq!(files.len(), 3);
+        assert_eq!(files[1], json!({ "name": "1.yax", "type": 7, "offset": 44, "uncompressed_size": 3 }));
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        // The same records as the extraction's pakInfo.json, plus offset and size
+        extract_pak_files_sync(&pak_path, &path_str(&dir.path().join("out")), false).unwrap();
+        let extracted = pak_info(&dir.path().join("out"));
+        for (file, extracted_file) in files.iter().zip(extracted["files"].as_array().unwrap()) {
+            assert_eq!((&file["name"], &file["type"]), (&extracted_file["name"], &extracted_file["type"]));
+        }
+    }
+
+    #[test]
+    fn corrupt_yax_entries_fail_the_extraction_without_panicking() {
+        let dir = temp_dir();
+        let yax = yax_bytes("<root><text>a</text><value><text>b</text></value></root>");
+        // Every single byte corruption of the YAX, each converted on a rayon thread
+        let corrupted: Vec<Vec<u8>> = (0..yax.len())
+            .map(|index| {
+                let mut corrupted = yax.clone();
+                corrupted[index] = 0xFF;
+                corrupted
             })
-        }).collect();
-        for task in tasks { 
-            task.await.unwrap();
+            .collect();
+        let entries: Vec<TestPakEntry> = corrupted.iter().map(|data| TestPakEntry::raw(data)).collect();
+        let pak_path = write_file(dir.path(), "test.pak", &pak_bytes(&entries));
+
+        let options = PakExtractOptions { yax_to_xml: true, error_sidecars: true, ..Default::default() };
+        let extract_dir = dir.path().join("sidecars");
+        let result = std::panic::catch_unwind(|| extract_pak_files_with_options(&pak_path, &path_str(&extract_dir), &options));
+        assert!(result.expect("conversion panicked").is_ok());
+        // Corrupting the node count or an indentation breaks the conversion
+        assert!(extract_dir.join("0.yax.error.txt").exists());
+        assert!(!extract_dir.join("0.xml").exists());
+
+        let options = PakExtractOptions { yax_to_xml: true, ..Default::default() };
+        let extract_dir = dir.path().join("failing");
+        let result = std::panic::catch_unwind(|| extract_pak_files_with_options(&pak_path, &path_str(&extract_dir), &options));
+        assert!(result.expect("conversion panicked").unwrap_err().to_string().contains("Failed to convert"));
+    }
+
+    #[test]
+    fn panicking_conversions_become_errors_of_their_entries() {
+        let dir = temp_dir();
+        let convert = |yax_path: &Path, xml_path: &Path| {
+            if yax_path.ends_with("1.yax") {
+                panic!("injected panic");
+            }
+            fs::write(xml_path, b"<root/>")
+        };
+
+        let errors = convert_entries(dir.path(), vec![0, 1, 2], true, &None, convert).unwrap();
+        assert_eq!(errors, [(1, "conversion panicked: injected panic".to_string())]);
+        assert!(dir.path().join("0.xml").exists() && dir.path().join("2.xml").exists());
+        let sidecar = fs::read_to_string(dir.path().join("1.yax.error.txt")).unwrap();
+        assert_eq!(sidecar, "Failed to convert 1.yax to XML: conversion panicked: injected panic\n");
+
+        let e = convert_entries(dir.path(), vec![0, 1, 2], false, &None, convert).unwrap_err();
+        assert!(e.to_string().contains("1.yax to XML: conversion panicked: injected panic"), "{}", e);
+    }
+
+    #[test]
+    fn cancelled_extraction_writes_no_entries_and_converts_nothing() {
+        let dir = temp_dir();
+        let yax = yax_bytes("<root><text>a</text></root>");
+        let pak_path = write_file(dir.path(), "test.pak", &pak_bytes(&[TestPakEntry::raw(&yax)]));
+        let cancel = Some(Arc::new(AtomicBool::new(true)));
+        let options = PakExtractOptions { yax_to_xml: true, cancel: cancel.clone(), ..Default::default() };
+        let extract_dir = dir.path().join("out");
+        let e = extract_pak_files_with_options(&pak_path, &path_str(&extract_dir), &options).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::Interrupted);
+        assert!(!extract_dir.join("0.yax").exists());
+
+        let e = convert_entries(dir.path(), vec![0], true, &cancel, |_, _| unreachable!()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::Interrupted);
+    }
+
+    #[test]
+    fn pak_info_flags_compressed_and_raw_entries() {
+        let dir = temp_dir();
+        let compressible = b"yax ".repeat(64);
+        let entries = [TestPakEntry::compressed(&compressible), TestPakEntry::raw(&compressible), TestPakEntry::raw(b"")];
+        let pak_path = write_file(dir.path(), "test.pak", &pak_bytes(&entries));
+        let extract_dir = dir.path().join("out");
+        extract_pak_files_sync(&pak_path, &path_str(&extract_dir), false).unwrap();
+        let stored: Vec<(serde_json::Value, serde_json::Value)> = pak_info(&extract_dir)["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|file| (file["compressed"].clone(), file["stored_read_size"].clone()))
+            .collect();
+        // The zlib stream without its size prefix, the raw data without padding
+        let zlib_size = zlib(&compressible).len();
+        assert_eq!(stored, [(json!(true), json!(zlib_size)), (json!(false), json!(256)), (json!(false), json!(0))]);
+    }
+
+    #[test]
+    fn pak_without_entries_writes_an_empty_pak_info() {
+        let dir = temp_dir();
+        let empty = pak_bytes(&[]);
+        assert_eq!(empty, [0; 4]);
+        let pak_path = write_file(dir.path(), "empty.pak", &empty);
+        let extract_dir = dir.path().join("out");
+        let options = PakExtractOptions { yax_to_xml: true, ..Default::default() };
+        let files = extract_pak_files_with_options(&pak_path, &path_str(&extract_dir), &options).unwrap();
+        assert!(files.is_empty());
+        assert_eq!(pak_info(&extract_dir)["files"], json!([]));
+        assert_eq!(fs::read_dir(&extract_dir).unwrap().count(), 1);
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&read_pak_info(&pak_path).unwrap()).unwrap()["files"], json!([]));
+    }
+
+    #[test]
+    fn first_offset_of_4_is_a_pak_without_entries() {
+        let mut pak = vec![0; 16];
+        pak[8..12].copy_from_slice(&4u32.to_le_bytes());
+        let (entries, stride) = header_entries(pak).unwrap();
+        assert!(entries.is_empty());
+        assert_eq!(stride, 12);
+    }
+
+    #[test]
+    fn truncated_pak_header_is_invalid_data() {
+        let dir = temp_dir();
+        let pak_path = write_file(dir.path(), "short.pak", &[1, 0, 0, 0, 4, 0]);
+        let error = extract_pak_files_sync(&pak_path, &path_str(&dir.path().join("out")), false).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn status_ffi_reports_success_and_failure_through_the_out_parameters() {
+        let dir = temp_dir();
+        let pak_path = c_string(&write_file(dir.path(), "test.pak", &pak_bytes(&[TestPakEntry::raw(b"abcd")])));
+        let extract_dir = c_string(&path_str(&dir.path().join("out")));
+
+        let (mut files, mut error) = (std::ptr::null_mut(), std::ptr::null_mut());
+        let status = extract_pak_files_ffi_status(pak_path.as_ptr(), extract_dir.as_ptr(), false, &mut files, &mut error);
+        assert_eq!(status, crate::FFI_STATUS_OK);
+        assert!(error.is_null());
+        let files: Vec<String> = serde_json::from_str(&take_ffi_string(files).unwrap()).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(fs::read(&files[0]).unwrap(), b"abcd");
+
+        let missing = c_string(&path_str(&dir.path().join("missing.pak")));
+        let (mut files, mut error) = (std::ptr::null_mut(), std::ptr::null_mut());
+        let status = extract_pak_files_ffi_status(missing.as_ptr(), extract_dir.as_ptr(), false, &mut files, &mut error);
+        assert_eq!(status, crate::FFI_STATUS_ERROR);
+        assert!(files.is_null());
+        assert!(!take_ffi_string(error).unwrap().is_empty());
+    }
+
+    #[test]
+    fn tree_lists_every_entry_and_the_totals() {
+        let dir = temp_dir();
+        let compressible = b"yax ".repeat(64);
+        let pak = pak_bytes(&[
+            TestPakEntry::raw(b"abcd"),
+            TestPakEntry { r#type: 7, ..TestPakEntry::compressed(&compressible) },
+            TestPakEntry::raw(b"efghijkl"),
+        ]);
+        let compressed_len = zlib(&compressible).len();
+        let stored_compressed = (4 + compressed_len).next_multiple_of(4);
+        let pak_path = write_file(dir.path(), "test.pak", &pak);
+
+        let tree = pak_tree_string(&pak_path).unwrap();
+        let lines: Vec<&str> = tree.lines().collect();
+        assert_eq!(lines.len(), 5, "{}", tree);
+        assert_eq!(lines[0], "test.pak (3 entries, 12 byte header entries)");
+        assert_eq!(lines[1], "├── 0.yax  type 1  offset 40  stored 4  uncompressed 4");
+        assert_eq!(lines[2], format!("├── 1.yax  type 7  offset 44  stored {}  uncompressed 256  compressed", stored_compressed));
+        assert_eq!(lines[3], format!("└── 2.yax  type 1  offset {}  stored 8  uncompressed 8", 44 + stored_compressed));
+        assert_eq!(lines[4], format!("Total: 3 entries, {} bytes stored, 268 bytes uncompressed", 12 + stored_compressed));
+    }
+
+    #[test]
+    fn tree_of_an_empty_pak_has_just_the_totals() {
+        let dir = temp_dir();
+        let pak_path = write_file(dir.path(), "empty.pak", &pak_bytes(&[]));
+        assert_eq!(pak_tree_string(&pak_path).unwrap(), "empty.pak (0 entries, 12 byte header entries)\nTotal: 0 entries, 0 bytes stored, 0 bytes uncompressed\n");
+    }
+
+    #[test]
+    fn handler_gets_the_index_type_and_decompressed_bytes_of_every_entry() {
+        let dir = temp_dir();
+        let compressible = b"yax ".repeat(64);
+        let pak = pak_bytes(&[
+            TestPakEntry::raw(b"abcd"),
+            TestPakEntry { r#type: 7, ..TestPakEntry::compressed(&compressible) },
+            TestPakEntry { r#type: 3, ..TestPakEntry::raw(b"efghijkl") },
+        ]);
+        let pak_path = write_file(dir.path(), "test.pak", &pak);
+
+        let mut entries = Vec::new();
+        extract_pak_with_handler(&pak_path, |index, r#type, data| {
+            entries.push((index, r#type, data.len()));
+            if index == 1 {
+                assert_eq!(data, compressible.as_slice());
+            }
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(entries, [(0, 1, 4), (1, 7, 256), (2, 3, 8)]);
+        // Nothing but the PAK was written
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        let files = extract_pak_files_sync(&pak_path, &path_str(&dir.path().join("out")), false).unwrap();
+        let mut handled = Vec::new();
+        extract_pak_with_handler(&pak_path, |_, _, data| {
+            handled.push(data.to_vec());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(handled, files.iter().map(|file| fs::read(file).unwrap()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn handler_error_stops_the_extraction() {
+        let dir = temp_dir();
+        let pak_path = write_file(dir.path(), "test.pak", &pak_bytes(&[TestPakEntry::raw(b"abcd"), TestPakEntry::raw(b"efgh")]));
+        let mut calls = 0;
+        let e = extract_pak_with_handler(&pak_path, |_, _, _| {
+            calls += 1;
+            Err(io::Error::other("stop"))
+        })
+        .unwrap_err();
+        assert_eq!(e.to_string(), "stop");
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn extract_dir_that_is_a_file_is_not_a_directory() {
+        let dir = temp_dir();
+        let pak = pak_bytes(&[TestPakEntry::raw(b"abcd")]);
+        let pak_path = write_file(dir.path(), "test.pak", &pak);
+        let e = extract_pak_files_sync(&pak_path, &pak_path, false).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotADirectory);
+        assert!(e.to_string().contains(&pak_path), "{}", e);
+        assert_eq!(fs::read(&pak_path).unwrap(), pak);
+    }
+
+    /// Raw data of a PAK whose entries trip the compression heuristic: the first one's leading u32 reaches past the
+    /// entry, the second one's is in range but followed by no zlib stream.
+    const RAW_LOOKING_COMPRESSED: [&[u8]; 2] = [b"raw entry bytes!", b"\x08\0\0\0not zlib data"];
+
+    /// A PAK of `RAW_LOOKING_COMPRESSED` whose headers claim 64 uncompressed bytes, more than is stored.
+    fn pak_with_raw_entries_looking_compressed() -> Vec<u8> {
+        let mut pak = pak_bytes(&RAW_LOOKING_COMPRESSED.map(TestPakEntry::raw));
+        pak[4..8].copy_from_slice(&64u32.to_le_bytes());
+        pak[16..20].copy_from_slice(&64u32.to_le_bytes());
+        pak
+    }
+
+    #[test]
+    fn raw_entries_tripping_the_compression_heuristic_are_extracted_raw() {
+        if in_child_test() {
+            let dir = temp_dir();
+            let pak_path = write_file(dir.path(), "test.pak", &pak_with_raw_entries_looking_compressed());
+            let quiet = std::env::var_os("QUIET").is_some();
+            let options = PakExtractOptions { quiet, ..Default::default() };
+            let files = extract_pak_files_with_options(&pak_path, &path_str(&dir.path().join("out")), &options).unwrap();
+            assert_eq!(fs::read(&files[0]).unwrap(), RAW_LOOKING_COMPRESSED[0]);
+            // Without the 3 bytes of padding the PAK stores it with
+            assert_eq!(fs::read(&files[1]).unwrap(), RAW_LOOKING_COMPRESSED[1]);
+            return;
         }
+        let test = "pak_extract::tests::raw_entries_tripping_the_compression_heuristic_are_extracted_raw";
+        let stderr = child_test_stderr(test, &[]);
+        for index in 0..2 {
+            let warning = format!("Warning: PAK entry {} looks compressed but isn't a zlib stream", index);
+            assert!(stderr.contains(&warning), "{}", stderr);
+        }
+        assert!(!child_test_stderr(test, &[("QUIET", "1")]).contains("Warning"));
+    }
+
+    #[test]
+    fn raw_entries_tripping_the_compression_heuristic_fail_in_strict_mode() {
+        let dir = temp_dir();
+        let pak_path = write_file(dir.path(), "test.pak", &pak_with_raw_entries_looking_compressed());
+        let options = PakExtractOptions { strict: true, ..Default::default() };
+        let error = extract_pak_files_with_options(&pak_path, &path_str(&dir.path().join("out")), &options).unwrap_err();
+        assert!(error.to_string().contains("PAK entry 0 looks compressed but isn't a zlib stream"), "{}", error);
     }
 
-    Ok((0..file_count as usize).map(|i| extract_dir_path.join(format!("{}.yax", i)).to_str().unwrap().to_string()).collect()) 
+    #[test]
+    fn raw_fallback_keeps_the_header_size_or_strips_the_padding() {
+        assert_eq!(raw_fallback_bytes(b"abcdef\0\0", 6), b"abcdef");
+        assert_eq!(raw_fallback_bytes(b"abcde\0\0\0", 64), b"abcde");
+        assert_eq!(raw_fallback_bytes(b"abcd", 64), b"abcd");
+        // At most 3 bytes are padding
+        assert_eq!(raw_fallback_bytes(b"abcd\0\0\0\0", 64), b"abcd\0");
+    }
 }
@@ -0,0 +1,361 @@
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use crate::from_hex;
+use serde_json::Value;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+
+/// Options for `repack_pak_files`.
+#[derive(Debug, Clone, Default)]
+pub struct PakRepackOptions {
+    /// Zlib compress every entry that is at least this many bytes large. `None` stores all entries uncompressed.
+    /// Entries whose `pakInfo.json` record has a `compressed` flag keep the storage they were extracted with instead.
+    /// Either way an entry compression doesn't make smaller is stored uncompressed, since the PAK format tells
+    /// compressed entries only by their stored size being smaller than their uncompressed size.
+    pub compress_threshold: Option<usize>,
+    /// Zlib level from 0 (fastest) to 9 (smallest) for the compressed entries, `None` for zlib's default of 6.
+    pub compression_level: Option<u32>,
+}
+
+
+struct PakEntry {
+    r#type: u32,
+    uncompressed_size: u32,
+    /// Written after the offset in header entries longer than 12 bytes.
+    header_extra: Vec<u8>,
+    data: Vec<u8>,
+}
+
+
+fn too_large_error() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "PAK contents are larger than its 32 bit offsets can address")
+}
+
+
+fn pad_to_4(bytes: &mut Vec<u8>) {
+    while !bytes.len().is_multiple_of(4) {
+        bytes.push(0);
+    }
+}
+
+
+/// Builds the stored bytes of one entry and returns them with their read size (the `stored_read_size` of
+/// `pakInfo.json`): the size of the zlib stream or of the data without padding. Compressed entries are a u32
+/// compressed size followed by the zlib stream. The extractor only treats an entry as compressed when its stored
+/// size is smaller than `uncompressed_size`, so compression is dropped again for entries it doesn't shrink.
+fn pack_entry(data: &[u8], compression: Option<Compression>) -> io::Result<(Vec<u8>, u32)> {
+    if let Some(compression) = compression {
+        let mut encoder = ZlibEncoder::new(Vec::new(), compression);
+        encoder.write_all(data)?;
+        let compressed = encoder.finish()?;
+
+        let read_size = u32::try_from(compressed.len()).map_err(|_| too_large_error())?;
+        let mut packed = Vec::with_capacity(compressed.len() + 8);
+        packed.extend_from_slice(&read_size.to_le_bytes());
+        packed.extend_from_slice(&compressed);
+        pad_to_4(&mut packed);
+        if packed.len() < data.len() {
+            return Ok((packed, read_size));
+        }
+    }
+
+    let read_size = u32::try_from(data.len()).map_err(|_| too_large_error())?;
+    let mut packed = data.to_vec();
+    pad_to_4(&mut packed);
+    Ok((packed, read_size))
+}
+
+
+/// Compares the `stored_read_size` the extraction recorded for entry `name` with the `read_size` repacking
+/// produced. They differ when the entry was edited or compressed differently, and the PAK then doesn't match
+/// the original byte for byte.
+fn stored_size_mismatch(name: &str, file: &Value, read_size: u32) -> Option<String> {
+    let recorded = file["stored_read_size"].as_u64()?;
+    (recorded != u64::from(read_size)).then(|| {
+        format!(
+            "PAK entry {} was stored as {} bytes when extracted and is stored as {} now, so the PAK differs from the original",
+            name, recorded, read_size
+        )
+    })
+}
+
+
+/// Repacks a directory produced by `extract_pak_files` (its `pakInfo.json` and `.yax` files) into a PAK file.
+/// Entries that don't shrink when compressed are stored uncompressed, see `PakRepackOptions::compress_threshold`.
+/// The header entries get the recorded `entry_stride`, with the `header_extra` bytes of every entry after its offset.
+/// Entries whose stored size differs from the `stored_read_size` they were extracted with are warned about on stderr.
+pub async fn repack_pak_files(
+    extract_dir: &str,
+    pak_path: &str,
+    options: &PakRepackOptions,
+) -> io::Result<()> {
+    let extract_dir = extract_dir.to_string();
+    let pak_path = pak_path.to_string();
+    let options = options.clone();
+    tokio::task::spawn_blocking(move || repack_pak_files_sync(&extract_dir, &pak_path, &options))
+        .await
+        .map_err(io::Error::other)?
+}
+
+
+/// Blocking version of `repack_pak_files` for callers that don't run a tokio runtime.
+pub fn repack_pak_files_sync(
+    extract_dir: &str,
+    pak_path: &str,
+    options: &PakRepackOptions,
+) -> io::Result<()> {
+    let compression = match options.compression_level {
+        Some(level) if level > 9 => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("zlib compression level {} is not between 0 and 9", level),
+            ));
+        }
+        Some(level) => Compression::new(level),
+        None => Compression::default(),
+    };
+    let extract_dir_path = Path::new(extract_dir);
+    let pak_info: Value = serde_json::from_slice(&fs::read(extract_dir_path.join("pakInfo.json"))?)?;
+    let files = pak_info["files"].as_array().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "pakInfo.json has no files list")
+    })?;
+    let entry_stride = match pak_info["entry_stride"].as_u64() {
+        None => 12,
+        Some(stride) if stride >= 12 && stride.is_multiple_of(4) && stride <= 24 => stride as usize,
+        Some(stride) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("pakInfo.json has an unsupported entry_stride of {}", stride),
+            ));
+        }
+    };
+
+    let mut entries = Vec::with_capacity(files.len());
+    for file in files {
+        let name = file["name"].as_str().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "pakInfo.json entry is missing its name")
+        })?;
+        let r#type = file["type"].as_u64().unwrap_or(0) as u32;
+        let header_extra = match file["header_extra"].as_str() {
+            Some(hex) => from_hex(hex).filter(|extra| extra.len() == entry_stride - 12),
+            None => (entry_stride == 12).then(Vec::new),
+        }
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("pakInfo.json entry {} has no header_extra of {} bytes for its entry_stride", name, entry_stride - 12),
+            )
+        })?;
+        let mut path = extract_dir_path.join(name);
+        if let Some(original_name) = file["duplicate_of"].as_str().filter(|_| !path.exists()) {
+            // The duplicate was removed, the entry it duplicates has the same bytes
+            path = extract_dir_path.join(original_name);
+        }
+        let data = fs::read(path)?;
+
+        let uncompressed_size = u32::try_from(data.len()).map_err(|_| too_large_error())?;
+        let compress = match file["compressed"].as_bool() {
+            Some(compressed) => compressed,
+            None => options.compress_threshold.is_some_and(|threshold| data.len() >= threshold),
+        };
+        let (data, read_size) = pack_entry(&data, compress.then_some(compression))?;
+        if let Some(warning) = stored_size_mismatch(name, file, read_size) {
+            eprintln!("Warning: {}", warning);
+        }
+        entries.push(PakEntry {
+            r#type,
+            uncompressed_size,
+            header_extra,
+            data,
+        });
+    }
+
+    let mut offset = u32::try_from(entries.len() * entry_stride + 4).map_err(|_| too_large_error())?;
+    let mut header = Vec::with_capacity(offset as usize);
+    for entry in &entries {
+        header.extend_from_slice(&entry.r#type.to_le_bytes());
+        header.extend_from_slice(&entry.uncompressed_size.to_le_bytes());
+        header.extend_from_slice(&offset.to_le_bytes());
+        header.extend_from_slice(&entry.header_extra);
+        offset = u32::try_from(entry.data.len())
+            .ok()
+            .and_then(|size| offset.checked_add(size))
+            .ok_or_else(too_large_error)?;
+    }
+    header.extend_from_slice(&[0; 4]);
+
+    let mut pak_file = File::create(pak_path)?;
+    pak_file.write_all(&header)?;
+    for entry in &entries {
+        pak_file.write_all(&entry.data)?;
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pak_extract::{extract_pak_files_sync, extract_pak_files_with_options, PakExtractOptions};
+    use crate::test_util::*;
+    use serde_json::json;
+
+    /// Extracts `pak` into `dir/extracted` and returns the contents of its YAX files.
+    fn extract(dir: &Path, pak_path: &str) -> Vec<Vec<u8>> {
+        let extract_dir = dir.join(format!("{}_extracted", Path::new(pak_path).file_name().unwrap().to_string_lossy()));
+        let files = extract_pak_files_sync(pak_path, &path_str(&extract_dir), false).unwrap();
+        files.iter().map(|file| fs::read(file).unwrap()).collect()
+    }
+
+    /// Drops the `compressed` flags from the `pakInfo.json` in `extract_dir`, so the repack options decide.
+    fn remove_compressed_flags(extract_dir: &str) {
+        let pak_info_path = Path::new(extract_dir).join("pakInfo.json");
+        let mut pak_info: Value = serde_json::from_slice(&fs::read(&pak_info_path).unwrap()).unwrap();
+        for file in pak_info["files"].as_array_mut().unwrap() {
+            file.as_object_mut().unwrap().remove("compressed");
+        }
+        fs::write(&pak_info_path, pak_info.to_string()).unwrap();
+    }
+
+    #[test]
+    fn compress_threshold_compresses_raw_entries_and_round_trips() {
+        let dir = temp_dir();
+        let large = b"entry ".repeat(100);
+        let pak_path = write_file(dir.path(), "in.pak", &pak_bytes(&[TestPakEntry::raw(&large), TestPakEntry::raw(b"tiny")]));
+        let extract_dir = path_str(&dir.path().join("in.pak_extracted"));
+        extract(dir.path(), &pak_path);
+        remove_compressed_flags(&extract_dir);
+
+        let repacked_path = path_str(&dir.path().join("out.pak"));
+        let options = PakRepackOptions { compress_threshold: Some(64), ..Default::default() };
+        repack_pak_files_sync(&extract_dir, &repacked_path, &options).unwrap();
+
+        assert!(fs::metadata(&repacked_path).unwrap().len() < fs::metadata(&pak_path).unwrap().len());
+        assert_eq!(extract(dir.path(), &repacked_path), [large, b"tiny".to_vec()]);
+    }
+
+    #[test]
+    fn entry_that_does_not_shrink_is_stored_uncompressed() {
+        let incompressible: Vec<u8> = (0..64u32).map(|i| (i.wrapping_mul(2654435761) >> 24) as u8).collect();
+        let (packed, read_size) = pack_entry(&incompressible, Some(Compression::best())).unwrap();
+        assert_eq!(packed, incompressible);
+        assert_eq!(read_size, 64);
+    }
+
+    #[test]
+    fn unchanged_extraction_repacks_byte_identical() {
+        let dir = temp_dir();
+        let compressible = b"yax ".repeat(64);
+        let pak = pak_bytes(&[TestPakEntry::compressed(&compressible), TestPakEntry::raw(b"raw data")]);
+        let pak_path = write_file(dir.path(), "in.pak", &pak);
+        extract(dir.path(), &pak_path);
+
+        let repacked_path = path_str(&dir.path().join("out.pak"));
+        repack_pak_files_sync(&path_str(&dir.path().join("in.pak_extracted")), &repacked_path, &Default::default()).unwrap();
+        assert_eq!(fs::read(&repacked_path).unwrap(), pak);
+    }
+
+    #[test]
+    fn compression_level_above_9_is_rejected() {
+        let dir = temp_dir();
+        let options = PakRepackOptions { compression_level: Some(10), ..Default::default() };
+        let e = repack_pak_files_sync(&path_str(dir.path()), &path_str(&dir.path().join("out.pak")), &options).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[tokio::test]
+    async fn async_repack_writes_the_same_pak() {
+        let dir = temp_dir();
+        let pak = pak_bytes(&[TestPakEntry::raw(b"abcd")]);
+        let pak_path = write_file(dir.path(), "in.pak", &pak);
+        extract(dir.path(), &pak_path);
+        let repacked_path = path_str(&dir.path().join("out.pak"));
+        repack_pak_files(&path_str(&dir.path().join("in.pak_extracted")), &repacked_path, &Default::default()).await.unwrap();
+        assert_eq!(fs::read(&repacked_path).unwrap(), pak);
+    }
+
+    #[test]
+    fn removed_duplicate_is_repacked_from_the_entry_it_duplicates() {
+        let dir = temp_dir();
+        let pak = pak_bytes(&[TestPakEntry::raw(b"same"), TestPakEntry::raw(b"same")]);
+        let pak_path = write_file(dir.path(), "in.pak", &pak);
+        let extract_dir = dir.path().join("in.pak_extracted");
+        let options = PakExtractOptions { dedup: true, ..Default::default() };
+        extract_pak_files_with_options(&pak_path, &path_str(&extract_dir), &options).unwrap();
+        fs::remove_file(extract_dir.join("1.yax")).unwrap();
+
+        let repacked_path = path_str(&dir.path().join("out.pak"));
+        repack_pak_files_sync(&path_str(&extract_dir), &repacked_path, &Default::default()).unwrap();
+        assert_eq!(fs::read(&repacked_path).unwrap(), pak);
+    }
+
+    #[test]
+    fn compression_levels_round_trip_and_higher_ones_are_smaller() {
+        let dir = temp_dir();
+        // Words in a pseudo-random order, which the higher levels' longer match search packs better
+        let words = ["yax", "node", "text", "value", "root", "entry", "name", "hash"];
+        let mut state = 1u32;
+        let data: Vec<u8> = (0..20_000)
+            .flat_map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                format!("{} ", words[(state >> 16) as usize % words.len()]).into_bytes()
+            })
+            .collect();
+        let pak_path = write_file(dir.path(), "in.pak", &pak_bytes(&[TestPakEntry::raw(&data)]));
+        let extract_dir = path_str(&dir.path().join("in.pak_extracted"));
+        extract(dir.path(), &pak_path);
+        remove_compressed_flags(&extract_dir);
+
+        let mut sizes = Vec::new();
+        for level in [1, 9] {
+            let repacked_path = path_str(&dir.path().join(format!("level_{}.pak", level)));
+            let options = PakRepackOptions { compress_threshold: Some(0), compression_level: Some(level) };
+            repack_pak_files_sync(&extract_dir, &repacked_path, &options).unwrap();
+            assert_eq!(extract(dir.path(), &repacked_path), std::slice::from_ref(&data));
+            sizes.push(fs::metadata(&repacked_path).unwrap().len());
+        }
+        assert!(sizes[1] < sizes[0], "level 9: {} bytes, level 1: {} bytes", sizes[1], sizes[0]);
+        assert!(sizes[0] < data.len() as u64);
+    }
+
+    #[test]
+    fn recorded_compression_wins_over_the_threshold() {
+        let dir = temp_dir();
+        let compressible = b"yax ".repeat(64);
+        let pak = pak_bytes(&[TestPakEntry::raw(&compressible), TestPakEntry::compressed(&compressible)]);
+        let pak_path = write_file(dir.path(), "in.pak", &pak);
+        extract(dir.path(), &pak_path);
+
+        // The threshold would compress the raw entry and `None` would store the compressed one raw
+        let repacked_path = path_str(&dir.path().join("out.pak"));
+        for compress_threshold in [Some(0), None] {
+            let options = PakRepackOptions { compress_threshold, ..Default::default() };
+            repack_pak_files_sync(&path_str(&dir.path().join("in.pak_extracted")), &repacked_path, &options).unwrap();
+            assert_eq!(fs::read(&repacked_path).unwrap(), pak);
+        }
+    }
+
+    #[test]
+    fn recorded_stored_read_size_is_checked_against_the_repacked_one() {
+        let dir = temp_dir();
+        let compressible = b"yax ".repeat(64);
+        let pak_path = write_file(dir.path(), "in.pak", &pak_bytes(&[TestPakEntry::compressed(&compressible), TestPakEntry::raw(b"raw data")]));
+        extract(dir.path(), &pak_path);
+        let extract_dir = dir.path().join("in.pak_extracted");
+        let pak_info: Value = serde_json::from_slice(&fs::read(extract_dir.join("pakInfo.json")).unwrap()).unwrap();
+        let files = pak_info["files"].as_array().unwrap();
+
+        // Unchanged entries repack to the recorded sizes
+        let (_, compressed_size) = pack_entry(&compressible, Some(Compression::default())).unwrap();
+        assert_eq!(stored_size_mismatch("0.yax", &files[0], compressed_size), None);
+        let (_, raw_size) = pack_entry(b"raw data", None).unwrap();
+        assert_eq!(stored_size_mismatch("1.yax", &files[1], raw_size), None);
+
+        let (_, edited_size) = pack_entry(b"edited raw data", None).unwrap();
+        let warning = stored_size_mismatch("1.yax", &files[1], edited_size).unwrap();
+        assert!(warning.contains("1.yax was stored as 8 bytes when extracted and is stored as 15 now"), "{}", warning);
+        assert_eq!(stored_size_mismatch("1.yax", &json!({ "name": "1.yax" }), edited_size), None);
+    }
+}
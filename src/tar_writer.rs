@@ -0,0 +1,107 @@
+use crate::{extract_dat_files_to_sink, ExtractOptions, OutputSink};
+use std::io::{self, Write};
+
+
+/// An `OutputSink` writing every entry, and `dat_info.json` last, as a file of an uncompressed tar archive.
+/// Headers are GNU style, so names over 100 bytes and entries of 8 GiB and more are stored too.
+pub struct TarSink<W: Write> {
+    builder: tar::Builder<W>,
+}
+
+impl<W: Write> TarSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { builder: tar::Builder::new(writer) }
+    }
+
+    /// Writes the two zero blocks that end the archive and returns the writer.
+    pub fn finish(self) -> io::Result<W> {
+        let mut writer = self.builder.into_inner()?;
+        writer.flush()?;
+        Ok(writer)
+    }
+
+    fn write_file(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_mode(0o644);
+        header.set_mtime(0);
+        header.set_size(data.len() as u64);
+        self.builder.append_data(&mut header, name, data)
+    }
+}
+
+impl<W: Write> OutputSink for TarSink<W> {
+    fn write_entry(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        self.write_file(name, data)
+    }
+
+    fn write_manifest(&mut self, json: &str) -> io::Result<()> {
+        self.write_file("dat_info.json", json.as_bytes())
+    }
+}
+
+
+/// Streams the entries of a DAT and its `dat_info.json` into a tar archive written to `writer`, e.g. stdout,
+/// without creating any files.
+pub fn extract_dat_to_tar_writer<W: Write>(dat_path: &str, writer: W) -> io::Result<()> {
+    let options = ExtractOptions {
+        quiet: true,
+        ..Default::default()
+    };
+    let mut sink = TarSink::new(writer);
+    extract_dat_files_to_sink(dat_path, &mut sink, &options)?;
+    sink.finish()?;
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+    use std::io::Read;
+
+    /// The names and contents of the files in the tar archive `tar`, in archive order.
+    fn read_tar(tar: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let mut archive = tar::Archive::new(tar);
+        archive
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let mut entry = entry.unwrap();
+                let name = entry.path().unwrap().to_string_lossy().into_owned();
+                let mut data = Vec::new();
+                entry.read_to_end(&mut data).unwrap();
+                (name, data)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tar_holds_every_entry_and_the_manifest_last() {
+        let dir = temp_dir();
+        let dat_path = write_dat(dir.path(), "test.dat", &[("b.bin", b"bbb"), ("a.txt", b"")]);
+        let mut tar = Vec::new();
+        extract_dat_to_tar_writer(&dat_path, &mut tar).unwrap();
+
+        let files = read_tar(&tar);
+        let names: Vec<&str> = files.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["b.bin", "a.txt", "dat_info.json"]);
+        assert_eq!(files[0].1, b"bbb");
+        assert!(files[1].1.is_empty());
+        let dat_info: serde_json::Value = serde_json::from_slice(&files[2].1).unwrap();
+        assert_eq!(dat_info["basename"], "test");
+        // Nothing was extracted to disk
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn names_longer_than_100_bytes_are_kept() {
+        let dir = temp_dir();
+        let long_name = format!("{}.bin", "n".repeat(150));
+        let dat_path = write_dat(dir.path(), "test.dat", &[(&long_name, b"data")]);
+        let mut tar = Vec::new();
+        extract_dat_to_tar_writer(&dat_path, &mut tar).unwrap();
+        assert_eq!(read_tar(&tar)[0], (long_name, b"data".to_vec()));
+    }
+}
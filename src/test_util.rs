@@ -0,0 +1,159 @@
+//! Builders for the DATs, PAKs and YAX files the unit tests work on, written into temporary directories.
+
+use crate::dat_repack::build_dat;
+use crate::xml_to_yax_convert::xml_to_yax;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::ffi::{CStr, CString};
+use std::io::Write;
+use std::os::raw::c_char;
+use std::path::Path;
+use std::process::Command;
+
+
+pub(crate) fn temp_dir() -> tempfile::TempDir {
+    tempfile::tempdir().unwrap()
+}
+
+
+/// `path` as the `&str` the path based APIs take.
+pub(crate) fn path_str(path: &Path) -> String {
+    path.to_str().unwrap().to_string()
+}
+
+
+/// Writes `data` to `name` in `dir` and returns its path.
+pub(crate) fn write_file(dir: &Path, name: &str, data: &[u8]) -> String {
+    let path = dir.join(name);
+    std::fs::write(&path, data).unwrap();
+    path_str(&path)
+}
+
+
+/// A DAT in the game's layout holding `entries` in this order.
+pub(crate) fn dat_bytes(entries: &[(&str, &[u8])]) -> Vec<u8> {
+    let entries: Vec<(String, Vec<u8>)> = entries.iter().map(|(name, data)| (name.to_string(), data.to_vec())).collect();
+    build_dat(&entries).unwrap()
+}
+
+
+/// Writes `dat_bytes(entries)` to `name` in `dir` and returns its path.
+pub(crate) fn write_dat(dir: &Path, name: &str, entries: &[(&str, &[u8])]) -> String {
+    write_file(dir, name, &dat_bytes(entries))
+}
+
+
+pub(crate) fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+
+/// One entry of `pak_bytes`.
+pub(crate) struct TestPakEntry<'a> {
+    pub r#type: u32,
+    pub data: &'a [u8],
+    /// Store the data as a u32 size and a zlib stream.
+    pub compressed: bool,
+}
+
+impl<'a> TestPakEntry<'a> {
+    pub(crate) fn raw(data: &'a [u8]) -> Self {
+        Self { r#type: 1, data, compressed: false }
+    }
+
+    pub(crate) fn compressed(data: &'a [u8]) -> Self {
+        Self { r#type: 1, data, compressed: true }
+    }
+}
+
+
+/// A PAK with 12 byte header entries.
+pub(crate) fn pak_bytes(entries: &[TestPakEntry]) -> Vec<u8> {
+    pak_bytes_with_stride(entries, 12)
+}
+
+
+/// A PAK whose header entries are `stride` bytes, the bytes after the offset filled with the entry index.
+pub(crate) fn pak_bytes_with_stride(entries: &[TestPakEntry], stride: usize) -> Vec<u8> {
+    let mut stored = Vec::new();
+    for entry in entries {
+        let mut data = if entry.compressed {
+            let compressed = zlib(entry.data);
+            let mut data = (compressed.len() as u32).to_le_bytes().to_vec();
+            data.extend_from_slice(&compressed);
+            data
+        } else {
+            entry.data.to_vec()
+        };
+        while data.len() % 4 != 0 {
+            data.push(0);
+        }
+        stored.push(data);
+    }
+
+    let mut pak = Vec::new();
+    let mut offset = entries.len() * stride + 4;
+    for (index, (entry, data)) in entries.iter().zip(&stored).enumerate() {
+        pak.extend_from_slice(&entry.r#type.to_le_bytes());
+        pak.extend_from_slice(&(entry.data.len() as u32).to_le_bytes());
+        pak.extend_from_slice(&(offset as u32).to_le_bytes());
+        pak.resize(pak.len() + stride - 12, index as u8);
+        offset += data.len();
+    }
+    pak.extend_from_slice(&[0; 4]);
+    for data in stored {
+        pak.extend_from_slice(&data);
+    }
+    pak
+}
+
+
+/// The YAX `xml_to_yax` makes of `xml`, e.g. `<root><text>a</text></root>`. Use built-in tag names like
+/// `text`, `name`, `value` or `id` for a YAX that converts back to the same tags.
+pub(crate) fn yax_bytes(xml: &str) -> Vec<u8> {
+    xml_to_yax(xml.as_bytes()).unwrap()
+}
+
+
+pub(crate) fn c_string(value: &str) -> CString {
+    CString::new(value).unwrap()
+}
+
+
+/// Takes back a string the library handed out, `None` for null.
+pub(crate) fn take_ffi_string(string: *mut c_char) -> Option<String> {
+    if string.is_null() {
+        return None;
+    }
+    let value = unsafe { CStr::from_ptr(string) }.to_str().unwrap().to_string();
+    crate::free_ffi_string(string);
+    Some(value)
+}
+
+
+/// Set in the child process of `child_test_stderr`.
+const CHILD_TEST_ENV: &str = "EXTRACT_DAT_CHILD_TEST";
+
+
+/// Runs the test `test` (its path in the crate, e.g. `tests::name`) alone in a child process with `env` set,
+/// and returns what it printed to stderr, where the library's warnings go. The harness captures `eprintln!`
+/// in process, so this is how tests see them.
+pub(crate) fn child_test_stderr(test: &str, env: &[(&str, &str)]) -> String {
+    let output = Command::new(std::env::current_exe().unwrap())
+        .args([test, "--exact", "--nocapture", "--test-threads=1"])
+        .env(CHILD_TEST_ENV, "1")
+        .envs(env.iter().copied())
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(output.status.success(), "{}{}", String::from_utf8_lossy(&output.stdout), stderr);
+    stderr
+}
+
+
+/// Whether this test runs as the child process of `child_test_stderr`.
+pub(crate) fn in_child_test() -> bool {
+    std::env::var_os(CHILD_TEST_ENV).is_some()
+}
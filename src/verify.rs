@@ -0,0 +1,248 @@
+use crate::dat_repack::manifest_entries;
+use crate::pak_extract::check_pak_entries;
+use crate::{read_dat_listing, source_crc32, unwrap_zlib_dat, verify_dat_hashmap, ByteDataWrapper, ExtractOptions};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+
+/// The outcome of checking one entry. PAK entries inside a DAT are named `<pak name>/<index>.yax`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryCheck {
+    pub name: String,
+    /// What is wrong with the entry, `None` if it passed.
+    pub error: Option<String>,
+}
+
+
+/// What `verify_dat` and `verify_pak` found, without having written anything.
+#[derive(Debug, Clone, Default)]
+pub struct VerifyReport {
+    pub entries: Vec<EntryCheck>,
+    /// Whether the DAT's hash map table agrees with its names (see `verify_dat_hashmap`). `None` for a PAK or
+    /// if the table couldn't be read.
+    /// Not part of `passed`, since many tools write DATs without a hash map.
+    pub hash_map_valid: Option<bool>,
+}
+
+impl VerifyReport {
+    pub fn passed(&self) -> bool {
+        self.entries.iter().all(|entry| entry.error.is_none())
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &EntryCheck> {
+        self.entries.iter().filter(|entry| entry.error.is_some())
+    }
+}
+
+
+fn pak_entry_checks(pak_name: &str, data: Vec<u8>) -> Vec<EntryCheck> {
+    match check_pak_entries(data) {
+        Ok(checks) => checks
+            .into_iter()
+            .map(|(name, error)| EntryCheck { name: format!("{}/{}", pak_name, name), error })
+            .collect(),
+        Err(e) => vec![EntryCheck { name: pak_name.to_string(), error: Some(e.to_string()) }],
+    }
+}
+
+
+/// Checks a DAT like extracting it with its PAKs would, but in memory: every entry has to lie inside the file,
+/// and the entries of every `.pak` have to read, decompress to their stated size and convert to well-formed XML.
+/// Fails only if the DAT's tables can't be read, everything else is reported per entry.
+pub fn verify_dat(dat_path: &str) -> io::Result<VerifyReport> {
+    let options = ExtractOptions {
+        quiet: true,
+        ..Default::default()
+    };
+    let Some(listing) = read_dat_listing(dat_path, &options)? else {
+        return Ok(VerifyReport::default());
+    };
+
+    let mut report = VerifyReport {
+        entries: Vec::new(),
+        hash_map_valid: verify_dat_hashmap(dat_path).ok(),
+    };
+    for entry in &listing.entries {
+        match listing.bytes.slice(entry.offset as usize, entry.size as usize) {
+            Err(e) => report.entries.push(EntryCheck { name: entry.name.clone(), error: Some(e.to_string()) }),
+            Ok(data) => {
+                report.entries.push(EntryCheck { name: entry.name.clone(), error: None });
+                if entry.name.ends_with(".pak") {
+                    report.entries.extend(pak_entry_checks(&entry.name, data.to_vec()));
+                }
+            }
+        }
+    }
+    Ok(report)
+}
+
+
+/// `verify_dat` for a PAK file: reads, decompresses and converts every entry in memory.
+pub fn verify_pak(pak_path: &str) -> io::Result<VerifyReport> {
+    Ok(VerifyReport {
+        entries: check_pak_entries(fs::read(pak_path)?)?
+            .into_iter()
+            .map(|(name, error)| EntryCheck { name, error })
+            .collect(),
+        hash_map_valid: None,
+    })
+}
+
+
+/// Compares the `dat_info.json` of an extracted DAT with the files next to it, e.g. to catch an incomplete or
+/// tampered extraction before repacking. Returns the files the manifest lists that are missing, then the files
+/// present that it doesn't list, each sorted. The manifest, `dat_filelist.txt`, the `.hex` dumps of listed files
+/// and directories (like the one extracted PAKs go to) aren't entries and are ignored.
+pub fn verify_manifest(extract_dir: &str) -> io::Result<Vec<String>> {
+    let extract_dir = Path::new(extract_dir);
+    let manifest: Value = serde_json::from_slice(&fs::read(extract_dir.join("dat_info.json"))?)?;
+    let entry_files: HashSet<String> = manifest_entries(&manifest)?.into_iter().map(|(_, file)| file).collect();
+    let listed: HashSet<String> = if manifest["gzipped"].as_bool().unwrap_or(false) {
+        entry_files.iter().map(|file| format!("{}.gz", file)).collect()
+    } else {
+        entry_files.clone()
+    };
+
+    let mut present = HashSet::new();
+    for dir_entry in fs::read_dir(extract_dir)? {
+        let dir_entry = dir_entry?;
+        if !dir_entry.file_type()?.is_dir() {
+            present.insert(dir_entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+
+    let mut missing: Vec<String> = listed.iter().filter(|file| !present.contains(*file)).cloned().collect();
+    let mut unlisted: Vec<String> = present
+        .into_iter()
+        .filter(|file| !listed.contains(file) && file != "dat_info.json" && file != "dat_filelist.txt")
+        .filter(|file| file.strip_suffix(".hex").is_none_or(|dumped| !entry_files.contains(dumped)))
+        .collect();
+    missing.sort();
+    unlisted.sort();
+    missing.extend(unlisted);
+    Ok(missing)
+}
+
+
+/// Checks that the DAT at `dat_path` is the one the extraction in `extract_dir` came from, by the size and
+/// CRC-32 its `provenance` records (see `ExtractOptions::provenance`). A manifest without provenance is `NotFound`.
+pub fn verify_provenance(extract_dir: &str, dat_path: &str) -> io::Result<bool> {
+    let manifest_path = Path::new(extract_dir).join("dat_info.json");
+    let manifest: Value = serde_json::from_slice(&fs::read(&manifest_path)?)?;
+    let source = &manifest["provenance"]["source"];
+    let (Some(size), Some(crc32)) = (source["size"].as_u64(), source["crc32"].as_str()) else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} records no provenance, it has to be extracted with the provenance option", manifest_path.display()),
+        ));
+    };
+
+    let (bytes, _) = unwrap_zlib_dat(ByteDataWrapper::from_file(dat_path)?)?;
+    Ok(bytes.data.len() as u64 == size && source_crc32(&bytes.data) == crc32)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+
+    /// A DAT with a valid entry, a PAK holding a valid and a broken YAX, and an entry reaching past the end.
+    fn dat_with_corrupt_entries() -> Vec<u8> {
+        let yax = yax_bytes("<root><text>a</text></root>");
+        let pak = pak_bytes(&[TestPakEntry::raw(&yax), TestPakEntry::raw(b"not yax data")]);
+        let mut dat = dat_bytes(&[("a.bin", b"abc"), ("b.pak", &pak), ("c.bin", b"def")]);
+        let sizes_offset = u32::from_le_bytes(dat[20..24].try_into().unwrap()) as usize;
+        dat[sizes_offset + 8..sizes_offset + 12].copy_from_slice(&10_000u32.to_le_bytes());
+        dat
+    }
+
+    #[test]
+    fn corrupt_entries_fail_and_valid_ones_pass() {
+        let dir = temp_dir();
+        let dat_path = write_file(dir.path(), "test.dat", &dat_with_corrupt_entries());
+        let report = verify_dat(&dat_path).unwrap();
+
+        assert!(!report.passed());
+        let passed: Vec<&str> = report.entries.iter().filter(|entry| entry.error.is_none()).map(|entry| entry.name.as_str()).collect();
+        assert_eq!(passed, ["a.bin", "b.pak", "b.pak/0.yax"]);
+        let failed: Vec<&str> = report.failures().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(failed, ["b.pak/1.yax", "c.bin"]);
+        // Nothing but the DAT was written
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn pak_entries_are_checked_on_their_own() {
+        let dir = temp_dir();
+        let yax = yax_bytes("<root><text>a</text></root>");
+        let pak_path = write_file(dir.path(), "test.pak", &pak_bytes(&[TestPakEntry::raw(b"not yax data"), TestPakEntry::raw(&yax)]));
+        let report = verify_pak(&pak_path).unwrap();
+        assert_eq!(report.entries.len(), 2);
+        assert!(report.entries[0].error.as_deref().unwrap().contains("Not a YAX file"));
+        assert_eq!(report.entries[1], EntryCheck { name: "1.yax".to_string(), error: None });
+        assert_eq!(report.hash_map_valid, None);
+    }
+
+    /// Extracts a DAT with two entries, an unknown one dumped as `.hex`, and a PAK into `out` in `dir`.
+    fn extract_for_manifest(dir: &Path, options: crate::ExtractOptions) -> String {
+        let yax = yax_bytes("<root><text>a</text></root>");
+        let pak = pak_bytes(&[TestPakEntry::raw(&yax)]);
+        let dat_path = write_dat(dir, "test.dat", &[("a.bin", b"abc"), ("b.pak", &pak), ("c.zzz", b"unknown")]);
+        let extract_dir = path_str(&dir.join("out"));
+        let options = crate::ExtractOptions { quiet: true, dump_unknown: Some(16), write_filelist: true, ..options };
+        crate::extract_dat_files_detailed(&dat_path, &extract_dir, &options).unwrap();
+        extract_dir
+    }
+
+    #[test]
+    fn intact_extraction_matches_its_manifest() {
+        let dir = temp_dir();
+        let extract_dir = extract_for_manifest(dir.path(), crate::ExtractOptions { extract_pak_files: true, ..Default::default() });
+        assert!(Path::new(&extract_dir).join("c.zzz.hex").exists());
+        assert!(verify_manifest(&extract_dir).unwrap().is_empty());
+    }
+
+    #[test]
+    fn deleted_and_unlisted_files_are_reported() {
+        let dir = temp_dir();
+        let extract_dir = extract_for_manifest(dir.path(), Default::default());
+        fs::remove_file(Path::new(&extract_dir).join("a.bin")).unwrap();
+        write_file(Path::new(&extract_dir), "stray.bin", b"stray");
+        assert_eq!(verify_manifest(&extract_dir).unwrap(), ["a.bin", "stray.bin"]);
+    }
+
+    #[test]
+    fn gzipped_entries_are_expected_with_their_gz_suffix() {
+        let dir = temp_dir();
+        let extract_dir = extract_for_manifest(dir.path(), crate::ExtractOptions { gzip_entries: true, ..Default::default() });
+        assert!(verify_manifest(&extract_dir).unwrap().is_empty());
+        fs::remove_file(Path::new(&extract_dir).join("b.pak.gz")).unwrap();
+        assert_eq!(verify_manifest(&extract_dir).unwrap(), ["b.pak.gz"]);
+    }
+
+    #[test]
+    fn missing_manifest_is_not_found() {
+        let dir = temp_dir();
+        assert_eq!(verify_manifest(&path_str(dir.path())).unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn provenance_confirms_only_the_source_dat() {
+        let dir = temp_dir();
+        let dat_path = write_dat(dir.path(), "test.dat", &[("a.bin", b"abc")]);
+        let other_path = write_dat(dir.path(), "other.dat", &[("a.bin", b"abd")]);
+        let extract_dir = path_str(&dir.path().join("out"));
+        let options = crate::ExtractOptions { provenance: true, quiet: true, ..Default::default() };
+        crate::extract_dat_files_with_options(&dat_path, &extract_dir, &options).unwrap();
+        assert!(verify_provenance(&extract_dir, &dat_path).unwrap());
+        assert!(!verify_provenance(&extract_dir, &other_path).unwrap());
+
+        let plain_dir = path_str(&dir.path().join("plain"));
+        crate::extract_dat_files_with_options(&dat_path, &plain_dir, &Default::default()).unwrap();
+        assert_eq!(verify_provenance(&plain_dir, &dat_path).unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+}
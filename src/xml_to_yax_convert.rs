@@ -0,0 +1,323 @@
+use crate::yax_to_xml_convert::{collect_files_with_extension, ENCODING_ATTRIBUTE, HASH_ATTRIBUTE};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use encoding_rs::SHIFT_JIS;
+use memchr::memchr;
+use std::borrow::Cow;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// YAX tag names are stored as the CRC32 of the name.
+fn tag_name_hash(tag_name: &[u8]) -> u32 {
+    crc32fast::hash(tag_name)
+}
+
+#[derive(Debug)]
+struct XmlNode {
+    indentation: u8,
+    tag_name_hash: u32,
+    text: Option<String>,
+    /// Store the text as UTF-8 instead of SHIFT_JIS, see `ENCODING_ATTRIBUTE`.
+    utf8: bool,
+}
+
+fn invalid_xml(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// `value` as the 32 bit node count or string offset the YAX stores.
+fn yax_u32(value: usize) -> io::Result<u32> {
+    u32::try_from(value).map_err(|_| invalid_xml("XML is larger than the 32 bit node count and string offsets of a YAX can address"))
+}
+
+/// The hash of an element: the one its `data-hash` attribute (see `YaxConvertOptions::hash_attributes`) holds,
+/// as `0x` prefixed hex or decimal, or else the hash of its name.
+fn element_hash(element: &BytesStart) -> io::Result<u32> {
+    for attribute in element.attributes() {
+        let attribute = attribute.map_err(|e| invalid_xml(e.to_string()))?;
+        if attribute.key != HASH_ATTRIBUTE.as_bytes() {
+            continue;
+        }
+        let value = String::from_utf8_lossy(&attribute.value);
+        let parsed = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => value.parse(),
+        };
+        return parsed.map_err(|_| invalid_xml(format!("Invalid {} attribute {:?}", HASH_ATTRIBUTE, value)));
+    }
+    Ok(tag_name_hash(element.name()))
+}
+
+/// Whether the `data-encoding` attribute of an element (see `ENCODING_ATTRIBUTE`) asks for its text to be stored
+/// as UTF-8. Without it the text is stored as SHIFT_JIS.
+fn element_is_utf8(element: &BytesStart) -> io::Result<bool> {
+    for attribute in element.attributes() {
+        let attribute = attribute.map_err(|e| invalid_xml(e.to_string()))?;
+        if attribute.key != ENCODING_ATTRIBUTE.as_bytes() {
+            continue;
+        }
+        let value = String::from_utf8_lossy(&attribute.value);
+        return match value.to_ascii_lowercase().as_str() {
+            "utf-8" => Ok(true),
+            "shift_jis" => Ok(false),
+            _ => Err(invalid_xml(format!("Invalid {} attribute {:?}, expected utf-8 or shift_jis", ENCODING_ATTRIBUTE, value))),
+        };
+    }
+    Ok(false)
+}
+
+/// `text` as the string table stores it: UTF-8 for `utf8`, otherwise SHIFT_JIS. Characters SHIFT_JIS can't
+/// encode are an error instead of the numeric character references `encoding_rs` would put in their place.
+fn encode_text(text: &str, utf8: bool) -> io::Result<Cow<'_, [u8]>> {
+    if utf8 {
+        return Ok(Cow::Borrowed(text.as_bytes()));
+    }
+    let (encoded, _, unmappable) = SHIFT_JIS.encode(text);
+    if unmappable {
+        return Err(invalid_xml(format!(
+            "Text {:?} has characters SHIFT_JIS can't encode, give its element {}=\"utf-8\" to store it as UTF-8",
+            text, ENCODING_ATTRIBUTE
+        )));
+    }
+    Ok(encoded)
+}
+
+/// Whether `xml` looks like the output of `yax_to_xml`, i.e. its top level element is the `<root>` wrapper.
+pub fn is_nier_xml(xml: &[u8]) -> bool {
+    let mut reader = Reader::from_reader(xml);
+    let mut buffer = Vec::new();
+    loop {
+        match reader.read_event(&mut buffer) {
+            Ok(Event::Start(e)) | Ok(Event::Empty(e)) => return e.name() == b"root",
+            Ok(Event::Eof) | Err(_) => return false,
+            _ => {}
+        }
+        buffer.clear();
+    }
+}
+
+/// `yax_to_xml` can write SHIFT_JIS documents, quick-xml only reads UTF-8, so those are decoded first.
+fn decode_document(xml: &[u8]) -> Cow<'_, [u8]> {
+    let declaration_end = xml.iter().position(|&byte| byte == b'>').unwrap_or(0);
+    let declaration = String::from_utf8_lossy(&xml[..declaration_end]).to_ascii_lowercase();
+    if declaration.starts_with("<?xml") && declaration.contains("shift_jis") {
+        let (decoded, _, _) = SHIFT_JIS.decode(xml);
+        Cow::Owned(decoded.into_owned().into_bytes())
+    } else {
+        Cow::Borrowed(xml)
+    }
+}
+
+/// Whether the document was written with `LineEnding::CrLf`, told by its first line break. The `\r` that added
+/// to every line break, including those inside texts, is dropped again from the texts.
+fn uses_crlf(xml: &[u8]) -> bool {
+    memchr(b'\n', xml).is_some_and(|position| position > 0 && xml[position - 1] == b'\r')
+}
+
+fn read_nodes(xml: &[u8]) -> io::Result<Vec<XmlNode>> {
+    let xml = decode_document(xml);
+    let crlf = uses_crlf(&xml);
+    let read_text = |text: String| if crlf { text.replace("\r\n", "\n") } else { text };
+    let xml = xml.as_ref();
+    let mut reader = Reader::from_reader(xml);
+
+    let mut nodes: Vec<XmlNode> = Vec::new();
+    // Indices into `nodes` of the currently open elements, the wrapper element excluded
+    let mut open_nodes: Vec<usize> = Vec::new();
+    let mut depth = 0usize;
+    let mut buffer = Vec::new();
+    loop {
+        let event = reader
+            .read_event(&mut buffer)
+            .map_err(|e| invalid_xml(format!("Invalid XML at position {}: {}", reader.buffer_position(), e)))?;
+        match event {
+            Event::Start(ref e) | Event::Empty(ref e) => {
+                let is_empty = matches!(event, Event::Empty(_));
+                // The top level element is the wrapper `yax_to_xml` adds, whatever its name
+                if depth > 0 {
+                    let indentation = u8::try_from(depth - 1)
+                        .map_err(|_| invalid_xml("XML is nested deeper than the 255 levels YAX supports"))?;
+                    nodes.push(XmlNode {
+                        indentation,
+                        tag_name_hash: element_hash(e)?,
+                        text: None,
+                        utf8: element_is_utf8(e)?,
+                    });
+                    if !is_empty {
+                        open_nodes.push(nodes.len() - 1);
+                    }
+                }
+                if !is_empty {
+                    depth += 1;
+                }
+            }
+            Event::Text(e) => {
+                if let Some(&index) = open_nodes.last() {
+                    let text = e.unescape_and_decode(&reader).map_err(|e| invalid_xml(e.to_string()))?;
+                    // Texts are kept as they are, but whitespace-only ones are the indentation between elements:
+                    // `YaxConvertOptions::cdata_whitespace_text` writes whitespace-only texts as CDATA
+                    if !text.trim().is_empty() {
+                        nodes[index].text = Some(read_text(text));
+                    }
+                }
+            }
+            Event::CData(e) => {
+                if let Some(&index) = open_nodes.last() {
+                    let text = reader.decode(&e).map_err(|e| invalid_xml(e.to_string()))?;
+                    if !text.is_empty() {
+                        nodes[index].text = Some(read_text(text.to_string()));
+                    }
+                }
+            }
+            Event::End(_) => {
+                if depth > 1 {
+                    open_nodes.pop();
+                }
+                depth = depth.saturating_sub(1);
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buffer.clear();
+    }
+
+    Ok(nodes)
+}
+
+/// Converts XML produced by `yax_to_xml` back to YAX bytes.
+/// Texts are stored SHIFT_JIS encoded, or UTF-8 encoded for elements marked with `data-encoding="utf-8"`.
+/// Identical texts share one string table entry and nodes without text get string offset 0.
+pub fn xml_to_yax(xml: &[u8]) -> io::Result<Vec<u8>> {
+    let nodes = read_nodes(xml)?;
+
+    let strings_start = 4 + nodes.len() * 9;
+    let mut string_offsets: HashMap<(&str, bool), u32> = HashMap::new();
+    let mut string_table = Vec::new();
+    let mut yax = Vec::with_capacity(strings_start);
+    yax.extend_from_slice(&yax_u32(nodes.len())?.to_le_bytes());
+    for node in &nodes {
+        let string_offset = match &node.text {
+            Some(text) => match string_offsets.entry((text.as_str(), node.utf8)) {
+                Entry::Occupied(entry) => *entry.get(),
+                Entry::Vacant(entry) => {
+                    let offset = yax_u32(strings_start + string_table.len())?;
+                    string_table.extend_from_slice(&encode_text(text, node.utf8)?);
+                    string_table.push(0);
+                    *entry.insert(offset)
+                }
+            },
+            None => 0,
+        };
+        yax.push(node.indentation);
+        yax.extend_from_slice(&node.tag_name_hash.to_le_bytes());
+        yax.extend_from_slice(&string_offset.to_le_bytes());
+    }
+    yax.extend_from_slice(&string_table);
+
+    Ok(yax)
+}
+
+pub fn convert_xml_to_yax(xml_file_path: &str, yax_file_path: &str) -> io::Result<()> {
+    let yax_bytes = xml_to_yax(&fs::read(xml_file_path)?)?;
+    fs::write(yax_file_path, yax_bytes)
+}
+
+/// Converts every NieR `.xml` file below `dir` (recursively) back to a `.yax` file next to it and returns the YAX paths.
+/// XML files without the `<root>` wrapper aren't converted YAX files and are skipped.
+pub async fn convert_xml_tree(dir: &str) -> io::Result<Vec<String>> {
+    let mut xml_files = Vec::new();
+    collect_files_with_extension(Path::new(dir), "xml", &mut xml_files)?;
+    xml_files.sort();
+
+    let tasks: Vec<_> = xml_files
+        .into_iter()
+        .map(|xml_path| {
+            tokio::task::spawn_blocking(move || -> io::Result<Option<String>> {
+                let xml = fs::read(&xml_path)?;
+                if !is_nier_xml(&xml) {
+                    return Ok(None);
+                }
+                let yax_path = xml_path.with_extension("yax");
+                fs::write(&yax_path, xml_to_yax(&xml)?)?;
+                Ok(Some(yax_path.to_string_lossy().into_owned()))
+            })
+        })
+        .collect();
+
+    let mut yax_files = Vec::new();
+    for task in tasks {
+        yax_files.extend(task.await.map_err(io::Error::other)??);
+    }
+    Ok(yax_files)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+    use crate::yax_to_xml_convert::convert_yax_tree;
+
+    #[tokio::test]
+    async fn xml_tree_round_trips_to_the_same_yax() {
+        let dir = temp_dir();
+        fs::create_dir_all(dir.path().join("sub")).unwrap();
+        let top = yax_bytes("<root><text>top</text><value>1</value></root>");
+        let deep = yax_bytes("<root><name><id>7</id><size>  padded  </size></name></root>");
+        write_file(dir.path(), "top.yax", &top);
+        write_file(&dir.path().join("sub"), "deep.yax", &deep);
+        convert_yax_tree(&path_str(dir.path()), false).await.unwrap();
+        fs::remove_file(dir.path().join("top.yax")).unwrap();
+        fs::remove_file(dir.path().join("sub/deep.yax")).unwrap();
+
+        let yax_files = convert_xml_tree(&path_str(dir.path())).await.unwrap();
+        assert_eq!(yax_files, [path_str(&dir.path().join("sub/deep.yax")), path_str(&dir.path().join("top.yax"))]);
+        assert_eq!(fs::read(dir.path().join("top.yax")).unwrap(), top);
+        assert_eq!(fs::read(dir.path().join("sub/deep.yax")).unwrap(), deep);
+    }
+
+    #[tokio::test]
+    async fn xml_without_the_root_wrapper_is_skipped() {
+        let dir = temp_dir();
+        write_file(dir.path(), "config.xml", b"<config><text>a</text></config>");
+        assert!(convert_xml_tree(&path_str(dir.path())).await.unwrap().is_empty());
+        assert!(!dir.path().join("config.yax").exists());
+    }
+
+    #[test]
+    fn root_wrapper_detection() {
+        assert!(is_nier_xml(b"<?xml version=\"1.0\"?>\n<root><text>a</text></root>"));
+        assert!(is_nier_xml(b"<root/>"));
+        assert!(!is_nier_xml(b"<text>a</text>"));
+        assert!(!is_nier_xml(b"not xml"));
+    }
+
+    #[test]
+    fn hand_edited_crlf_xml_converts_like_lf_xml() {
+        let lf = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<root>\n  <text>line 1\nline 2</text>\n</root>\n";
+        let crlf = lf.replace('\n', "\r\n");
+        assert_eq!(xml_to_yax(crlf.as_bytes()).unwrap(), xml_to_yax(lf.as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn text_shift_jis_cannot_encode_is_an_error_unless_marked_utf8() {
+        let e = xml_to_yax("<root><text>caf\u{e9}</text></root>".as_bytes()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(e.to_string().contains("SHIFT_JIS can't encode"), "{}", e);
+
+        let yax = xml_to_yax("<root><text data-encoding=\"utf-8\">caf\u{e9}</text></root>".as_bytes()).unwrap();
+        assert!(yax.ends_with("caf\u{e9}\0".as_bytes()));
+        let e = xml_to_yax("<root><text data-encoding=\"latin1\">a</text></root>".as_bytes()).unwrap_err();
+        assert!(e.to_string().contains("Invalid data-encoding attribute"), "{}", e);
+    }
+
+    #[test]
+    fn counts_and_offsets_past_u32_are_invalid_data() {
+        assert_eq!(yax_u32(u32::MAX as usize).unwrap(), u32::MAX);
+        let e = yax_u32(u32::MAX as usize + 1).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+}
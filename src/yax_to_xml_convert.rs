@@ -1,161 +1,1204 @@
+use crate::{ffi_path, write_file_atomic, ByteDataWrapper, DEFAULT_BUFFER_SIZE};
+use siphasher::sip128::{Hasher128, SipHasher13};
+use std::hash::Hasher;
 use crate::hash_map::HASH_TO_STRING_MAP;
-use quick_xml::events::{BytesEnd, BytesStart, BytesText, Event};
-use quick_xml::Writer;
+use quick_xml::events::{BytesCData, BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::{Reader, Writer};
 use encoding_rs::SHIFT_JIS;
+use memchr::memchr;
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
-use std::io::{BufReader, BufWriter, Read, Seek, Write};
-use std::ffi::CStr;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 use std::os::raw::c_char;
 
+/// Errors from parsing a YAX file.
+#[derive(Debug)]
+pub enum YaxError {
+    Io(io::Error),
+    /// A node is nested more than one level deeper than the node before it, or isn't at indentation 0
+    /// although there is no root node yet. Indentation is a u8, so YAX trees are at most 256 levels deep.
+    InvalidIndentation { node_index: usize, indentation: u8 },
+    /// The node table the header announces doesn't fit in the file, so it isn't a YAX file.
+    NotYax { node_count: u32, file_len: u64 },
+    /// Strict mode only: non-null bytes follow the last string the string table was read up to.
+    TrailingStringData { offset: u64 },
+    /// The converted document isn't well-formed XML, e.g. because of a tag name from `tag_names` that isn't a
+    /// valid XML name or a control character in a string.
+    MalformedXml { message: String },
+}
+
+impl fmt::Display for YaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            YaxError::Io(e) => write!(f, "Failed to read YAX data: {}", e),
+            YaxError::InvalidIndentation { node_index, indentation } => write!(
+                f,
+                "YAX node {} has indentation {} which doesn't fit below the previous node",
+                node_index, indentation
+            ),
+            YaxError::NotYax { node_count, file_len } => write!(
+                f,
+                "Not a YAX file: {} nodes of 9 bytes don't fit in {} bytes",
+                node_count, file_len
+            ),
+            YaxError::TrailingStringData { offset } => write!(
+                f,
+                "YAX string table has unread non-null bytes at offset {}",
+                offset
+            ),
+            YaxError::MalformedXml { message } => write!(f, "YAX doesn't convert to well-formed XML: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for YaxError {}
+
+impl From<io::Error> for YaxError {
+    fn from(e: io::Error) -> Self {
+        YaxError::Io(e)
+    }
+}
+
+impl From<YaxError> for io::Error {
+    fn from(e: YaxError) -> Self {
+        match e {
+            YaxError::Io(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
+
+/// Attribute `YaxConvertOptions::hash_attributes` stores the tag name hash in.
+pub(crate) const HASH_ATTRIBUTE: &str = "data-hash";
+
+/// Attribute marking an element whose text the YAX stores as UTF-8, set to `utf-8`. `xml_to_yax` stores the texts
+/// of other elements as SHIFT_JIS, which encodes ASCII the same, so only texts with other characters are marked.
+pub(crate) const ENCODING_ATTRIBUTE: &str = "data-encoding";
+
 fn hash_to_string_map(hash: u32) -> Option<&'static str> {
     HASH_TO_STRING_MAP.get(&hash).copied()
 }
 
+/// Every built-in tag name with its hash, in no particular order, e.g. to search or export the dictionary.
+pub fn known_tag_names() -> impl Iterator<Item = (u32, &'static str)> {
+    HASH_TO_STRING_MAP.entries().map(|(&hash, &name)| (hash, name))
+}
+
+/// Number of built-in tag names, i.e. of items `known_tag_names` yields.
+pub fn known_tag_count() -> usize {
+    HASH_TO_STRING_MAP.len()
+}
+
+/// One node of the flat node table. The tag name is borrowed from the custom or the built-in names,
+/// so large files don't allocate a name per node.
 #[derive(Debug)]
-struct YaxNode {
+struct YaxNode<'a> {
     indentation: u8,
     tag_name_hash: u32,
+    /// Offset of the node's text from the start of the file. 0 means the node has no text, as `xml_to_yax` writes
+    /// it: offset 0 is the node count, which the string table never starts before.
     string_offset: u32,
-    tag_name: String,
-    text: Option<String>,
-    children: Vec<YaxNode>,
+    tag_name: &'a str,
 }
 
-impl YaxNode {
-    fn from_bytes(bytes: &mut impl Read) -> Self {
+impl<'a> YaxNode<'a> {
+    /// Tag names are looked up in `tag_names` first, then in the built-in map.
+    fn from_bytes(bytes: &mut impl Read, tag_names: &'a HashMap<u32, String>) -> io::Result<Self> {
         let mut buffer = [0; 1];
-        bytes.read_exact(&mut buffer).unwrap();
+        bytes.read_exact(&mut buffer)?;
         let indentation = buffer[0];
 
         let mut buffer = [0; 4];
-        bytes.read_exact(&mut buffer).unwrap();
+        bytes.read_exact(&mut buffer)?;
         let tag_name_hash = u32::from_le_bytes(buffer);
 
         let mut buffer = [0; 4];
-        bytes.read_exact(&mut buffer).unwrap();
+        bytes.read_exact(&mut buffer)?;
         let string_offset = u32::from_le_bytes(buffer);
 
-        let tag_name = hash_to_string_map(tag_name_hash).unwrap_or("UNKNOWN").to_string();
+        let tag_name = match tag_names.get(&tag_name_hash) {
+            Some(tag_name) => tag_name.as_str(),
+            None => hash_to_string_map(tag_name_hash).unwrap_or("UNKNOWN"),
+        };
 
-        YaxNode {
+        Ok(YaxNode {
             indentation,
             tag_name_hash,
             string_offset,
             tag_name,
-            text: None,
-            children: Vec::new(),
-        }
+        })
     }
 
-    fn to_xml(&self) -> BytesStart {
-        BytesStart::borrowed(self.tag_name.as_bytes(), self.tag_name.len())
+    fn to_xml(&self, text: Option<&TableString>, options: &YaxConvertOptions) -> BytesStart<'_> {
+        let mut start = BytesStart::borrowed(self.tag_name.as_bytes(), self.tag_name.len());
+        if options.hash_attributes {
+            start.push_attribute((HASH_ATTRIBUTE, format!("0x{:08X}", self.tag_name_hash).as_str()));
+        }
+        if text.is_some_and(|text| text.utf8 && !text.text.is_ascii()) {
+            start.push_attribute((ENCODING_ATTRIBUTE, "utf-8"));
+        }
+        start
     }
 
-    fn to_xml_events(&self, writer: &mut Writer<&mut Vec<u8>>) {
-        writer.write_event(Event::Start(self.to_xml())).unwrap();
+    /// Writes the start tag and the text of the node. Its end tag follows once all its children are written.
+    fn write_start(&self, writer: &mut Writer<&mut Vec<u8>>, text: Option<&TableString>, options: &YaxConvertOptions) -> Result<(), YaxError> {
+        if options.debug_offsets {
+            let comment = format!(" hash=0x{:08X} string_offset={} ", self.tag_name_hash, self.string_offset);
+            write_event(writer, Event::Comment(BytesText::from_escaped_str(comment)))?;
+        }
+        write_event(writer, Event::Start(self.to_xml(text, options)))?;
+
+        if let Some(TableString { text, .. }) = text {
+            let text = if text.contains("&quot;") { Cow::Owned(text.replace("&quot;", "\"\"")) } else { Cow::Borrowed(text) };
 
-        if let Some(text) = &self.text {
-            let mut text = text.clone();
-            if text.contains("&quot;") {
-                text = text.replace("&quot;", "\"\"");
+            let whitespace_only = options.cdata_whitespace_text && text.trim().is_empty();
+            if whitespace_only || options.cdata_text && text.contains(['<', '>', '&']) && !text.contains("]]>") {
+                write_event(writer, Event::CData(BytesCData::from_str(&text)))?;
+            } else {
+                write_event(writer, Event::Text(BytesText::from_plain_str(&text)))?;
             }
-
-            writer.write_event(Event::Text(BytesText::from_plain_str(&text))).unwrap();
         }
+        Ok(())
+    }
 
-        for child in &self.children {
-            child.to_xml_events(writer);
-        }
+    fn write_end(&self, writer: &mut Writer<&mut Vec<u8>>) -> Result<(), YaxError> {
+        write_event(writer, Event::End(BytesEnd::borrowed(self.tag_name.as_bytes())))
+    }
+}
+
+/// Writes `event`, with a quick-xml error returned as `YaxError` instead of unwrapped.
+fn write_event(writer: &mut Writer<&mut Vec<u8>>, event: Event) -> Result<(), YaxError> {
+    writer.write_event(event).map_err(|e| match e {
+        quick_xml::Error::Io(e) => YaxError::Io(e),
+        e => YaxError::MalformedXml { message: e.to_string() },
+    })
+}
 
-        writer.write_event(Event::End(BytesEnd::borrowed(self.tag_name.as_bytes()))).unwrap();
+/// A decoded string of the string table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TableString {
+    text: String,
+    /// The YAX stores the string as UTF-8, not SHIFT_JIS. Written as `ENCODING_ATTRIBUTE` so it converts back
+    /// to the same bytes.
+    utf8: bool,
+}
+
+/// Decodes one string of the string table, deciding per string: valid UTF-8, which includes plain ASCII
+/// identifiers, is taken as it is, anything else is decoded as SHIFT_JIS, which the game's Japanese text uses.
+/// SHIFT_JIS text is almost never valid UTF-8, as its lead bytes 0x81-0x9F are UTF-8 continuation bytes.
+fn decode_string(bytes: &[u8]) -> TableString {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => TableString { text: text.to_string(), utf8: true },
+        Err(_) => TableString { text: SHIFT_JIS.decode(bytes).0.into_owned(), utf8: false },
     }
 }
 
-fn read_string_zero_terminated(bytes: &mut impl Read) -> Option<String> {
-    let mut buffer = Vec::new();
-    let mut byte = [0; 1];
-    while let Ok(_) = bytes.read_exact(&mut byte) {
-        if byte[0] == 0 {
+/// Splits the string table into its null-terminated strings (see `decode_string`), keyed by their offset in the file.
+/// The table ends at the first empty string or at the end of the file. Also returns where the table ended.
+fn read_string_table(table: &[u8], table_offset: u64) -> (HashMap<u32, TableString>, usize) {
+    let mut strings = HashMap::new();
+    let mut position = 0;
+    while position < table.len() {
+        let end = memchr(0, &table[position..]).map_or(table.len(), |length| position + length);
+        if end == position {
             break;
         }
-        buffer.push(byte[0]);
+        strings.insert((table_offset + position as u64) as u32, decode_string(&table[position..end]));
+        position = end + 1;
+    }
+    (strings, position.min(table.len()))
+}
+
+/// Writes the flat node list, which is in document order, as nested elements: a node is a child of the last
+/// node one level above it. Only the chain of currently open nodes is kept, instead of building the whole tree.
+fn write_nodes(
+    writer: &mut Writer<&mut Vec<u8>>,
+    nodes: &[YaxNode],
+    strings: &HashMap<u32, TableString>,
+    options: &YaxConvertOptions,
+) -> Result<(), YaxError> {
+    let mut open_nodes: Vec<&YaxNode> = Vec::new();
+    for (node_index, node) in nodes.iter().enumerate() {
+        let indentation = node.indentation;
+        if usize::from(indentation) > open_nodes.len() {
+            return Err(YaxError::InvalidIndentation { node_index, indentation });
+        }
+        for open_node in open_nodes.drain(usize::from(indentation)..).rev() {
+            open_node.write_end(writer)?;
+        }
+        let text = (node.string_offset != 0).then(|| strings.get(&node.string_offset)).flatten();
+        node.write_start(writer, text, options)?;
+        open_nodes.push(node);
+    }
+    while let Some(node) = open_nodes.pop() {
+        node.write_end(writer)?;
     }
-    if buffer.is_empty() {
-        None
-    } else {
-        let (decoded_str, _, _) = SHIFT_JIS.decode(&buffer);
-        Some(decoded_str.into_owned())
+    Ok(())
+}
+
+/// Errors with the offset of the first non-null byte in `rest`, the bytes after the string table.
+fn check_fully_consumed(rest: &[u8], rest_offset: u64) -> Result<(), YaxError> {
+    match rest.iter().position(|&byte| byte != 0) {
+        Some(index) => Err(YaxError::TrailingStringData { offset: rest_offset + index as u64 }),
+        None => Ok(()),
     }
 }
 
-fn yax_to_xml<R: Read + Seek>(mut bytes: R) -> Vec<u8> {
+fn yax_to_xml<R: Read + Seek>(mut bytes: R, options: &YaxConvertOptions) -> Result<Vec<u8>, YaxError> {
     let mut buffer = [0; 4];
-    bytes.read_exact(&mut buffer).unwrap();
+    bytes.read_exact(&mut buffer)?;
     let node_count = u32::from_le_bytes(buffer);
 
-    let mut nodes = Vec::new();
+    let file_len = bytes.seek(SeekFrom::End(0))?;
+    bytes.seek(SeekFrom::Start(4))?;
+    if 4 + u64::from(node_count) * 9 > file_len {
+        return Err(YaxError::NotYax { node_count, file_len });
+    }
+
+    // The node table was checked to fit in the file, so `node_count` is no bogus size
+    let mut nodes = Vec::with_capacity(node_count as usize);
     for _ in 0..node_count {
-        nodes.push(YaxNode::from_bytes(&mut bytes));
+        nodes.push(YaxNode::from_bytes(&mut bytes, &options.tag_names)?);
     }
 
-    let mut strings = HashMap::new();
-    while let Ok(position) = bytes.stream_position() {
-        if let Some(string) = read_string_zero_terminated(&mut bytes) {
-            strings.insert(position as u32, string);
-        } else {
-            break;
+    let table_offset = bytes.stream_position()?;
+    let mut table = Vec::new();
+    bytes.read_to_end(&mut table)?;
+    nodes_to_xml(&nodes, &table, table_offset, options)
+}
+
+/// A reader counting the bytes read through it, for `yax_stream_to_xml`.
+struct CountingReader<R> {
+    inner: R,
+    position: u64,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+/// `yax_to_xml` for a reader that can't seek. The string table offset is counted from the bytes read instead of
+/// asked for, and since the file length isn't known up front, a node table longer than the file is only noticed
+/// when it runs out.
+fn yax_stream_to_xml<R: Read>(bytes: R, options: &YaxConvertOptions) -> Result<Vec<u8>, YaxError> {
+    let mut bytes = CountingReader { inner: bytes, position: 0 };
+    let mut buffer = [0; 4];
+    bytes.read_exact(&mut buffer)?;
+    let node_count = u32::from_le_bytes(buffer);
+
+    // `node_count` isn't checked against the file length yet, so it doesn't size the allocation
+    let mut nodes = Vec::with_capacity((node_count as usize).min(1 << 16));
+    for _ in 0..node_count {
+        match YaxNode::from_bytes(&mut bytes, &options.tag_names) {
+            Ok(node) => nodes.push(node),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                return Err(YaxError::NotYax { node_count, file_len: bytes.position });
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let table_offset = bytes.position;
+    let mut table = Vec::new();
+    bytes.read_to_end(&mut table)?;
+    nodes_to_xml(&nodes, &table, table_offset, options)
+}
+
+/// The part of `yax_to_xml` after reading: resolves the texts and writes the nodes inside the wrapper element.
+fn nodes_to_xml(nodes: &[YaxNode], table: &[u8], table_offset: u64, options: &YaxConvertOptions) -> Result<Vec<u8>, YaxError> {
+    let (strings, table_end) = read_string_table(table, table_offset);
+    if options.strict {
+        check_fully_consumed(&table[table_end..], table_offset + table_end as u64)?;
+    }
+
+    let mut buffer = Vec::new();
+    let mut writer = Writer::new_with_indent(&mut buffer, b'\t', 1);
+
+    let root_tag = options.root_tag.as_deref().unwrap_or("root").as_bytes();
+    if !options.strip_root {
+        if !is_xml_name(&String::from_utf8_lossy(root_tag)) {
+            return Err(YaxError::MalformedXml {
+                message: format!("invalid root tag name {:?}", String::from_utf8_lossy(root_tag)),
+            });
         }
+        write_event(&mut writer, Event::Start(BytesStart::borrowed(root_tag, root_tag.len())))?;
+    }
+    write_nodes(&mut writer, nodes, &strings, options)?;
+    if !options.strip_root {
+        write_event(&mut writer, Event::End(BytesEnd::borrowed(root_tag)))?;
     }
 
-    for node in &mut nodes {
-        node.text = strings.get(&node.string_offset).cloned();
+    Ok(buffer)
+}
+
+/// Encoding of the written XML, declared in its `<?xml ...?>` declaration.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum XmlEncoding {
+    #[default]
+    Utf8,
+    /// The text is re-encoded to SHIFT_JIS, the encoding YAX strings are stored in, for Japanese tools.
+    ShiftJis,
+}
+
+impl XmlEncoding {
+    fn declaration(&self) -> &'static [u8] {
+        match self {
+            XmlEncoding::Utf8 => b"<?xml version=\"1.0\" encoding=\"utf-8\"?>\n",
+            XmlEncoding::ShiftJis => b"<?xml version=\"1.0\" encoding=\"shift_jis\"?>\n",
+        }
     }
+}
+
+/// Line breaks of the written XML, including those inside texts. `xml_to_yax` reads both back to the same YAX.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+/// Options for the YAX to XML conversion.
+#[derive(Debug, Clone, Default)]
+pub struct YaxConvertOptions {
+    pub encoding: XmlEncoding,
+    /// Fail with `YaxError::TrailingStringData` when non-null bytes remain after the string table,
+    /// which usually means the file was misparsed.
+    pub strict: bool,
+    /// Precede every element with a comment holding the node's tag name hash and string offset, to correlate the
+    /// XML with the YAX bytes. `xml_to_yax` ignores comments, so such files still convert back.
+    pub debug_offsets: bool,
+    /// Directory caching converted documents by the content of the YAX (and these options), so converting
+    /// an unchanged YAX again just copies the cached XML.
+    pub cache_dir: Option<PathBuf>,
+    /// Extra tag names by hash, e.g. newly reversed ones. They win over the built-in names.
+    pub tag_names: HashMap<u32, String>,
+    /// Leave out the XML declaration and the `<root>` wrapper, for splicing the nodes into another document.
+    /// The result is only a well-formed document if the YAX has a single top level node, and `xml_to_yax`
+    /// needs the wrapper to convert it back.
+    pub strip_root: bool,
+    /// Write texts containing `<`, `>` or `&` as `<![CDATA[...]]>` sections instead of escaping them, which reads
+    /// better for dialogue. Texts containing `]]>` are still escaped, as a CDATA section can't hold it.
+    pub cdata_text: bool,
+    /// Write whitespace-only texts as CDATA. `xml_to_yax` takes whitespace-only text outside CDATA for the
+    /// indentation between elements, so without this such texts are lost on the way back.
+    pub cdata_whitespace_text: bool,
+    /// Name of the wrapper element, `None` for `root`. `xml_to_yax` accepts any wrapper name, but `convert_xml_tree`
+    /// only recognizes converted files by the `<root>` wrapper.
+    pub root_tag: Option<String>,
+    pub line_ending: LineEnding,
+    /// Give every element a `data-hash="0x..."` attribute with the node's tag name hash, which `xml_to_yax` uses
+    /// instead of hashing the tag name. This keeps `UNKNOWN` tags and names that don't hash to the hash they were
+    /// found under intact through a round trip. Off, the elements have no attributes.
+    pub hash_attributes: bool,
+}
 
-    let mut root_nodes = Vec::new();
-    for node in nodes {
-        if node.indentation == 0 {
-            root_nodes.push(node);
-        } else {
-            let parent_indent = node.indentation - 1;
-            let mut parent = root_nodes.last_mut().unwrap();
-            while parent.indentation != parent_indent {
-                parent = parent.children.last_mut().unwrap();
+impl YaxConvertOptions {
+    /// Cache file name of the document converting `yax` with these options produces. The key covers this
+    /// crate's version, since a fixed converter writes different XML for the same YAX, and every option
+    /// that changes the output.
+    fn cache_file_name(&self, yax: &[u8]) -> String {
+        let mut hasher = SipHasher13::new();
+        hasher.write(env!("CARGO_PKG_VERSION").as_bytes());
+        hasher.write_u8(0);
+        hasher.write_usize(yax.len());
+        hasher.write(yax);
+        let mut tag_names: Vec<_> = self.tag_names.iter().collect();
+        tag_names.sort();
+        hasher.write_usize(tag_names.len());
+        for (tag_name_hash, tag_name) in tag_names {
+            hasher.write_u32(*tag_name_hash);
+            hasher.write(tag_name.as_bytes());
+            hasher.write_u8(0);
+        }
+        match &self.root_tag {
+            Some(root_tag) => {
+                hasher.write_u8(1);
+                hasher.write(root_tag.as_bytes());
+                hasher.write_u8(0);
             }
-            parent.children.push(node);
+            None => hasher.write_u8(0),
+        }
+        hasher.write_u8(match self.encoding {
+            XmlEncoding::Utf8 => 0,
+            XmlEncoding::ShiftJis => 1,
+        });
+        hasher.write_u8(match self.line_ending {
+            LineEnding::Lf => 0,
+            LineEnding::CrLf => 1,
+        });
+        for flag in [self.strict, self.debug_offsets, self.strip_root, self.cdata_text, self.cdata_whitespace_text, self.hash_attributes] {
+            hasher.write_u8(flag as u8);
         }
+        let hash = hasher.finish128();
+        format!("{:016x}{:016x}.xml", hash.h1, hash.h2)
     }
+}
+
+/// Converts YAX bytes to a complete XML document (declaration included) in memory.
+pub fn yax_bytes_to_xml(yax: &[u8], options: &YaxConvertOptions) -> Result<Vec<u8>, YaxError> {
+    yax_to_xml_document(io::Cursor::new(yax), options)
+}
+
+/// Runs the full conversion of `yax` with the default options and checks that the result is well-formed XML,
+/// without writing anything, e.g. to scan a whole game for conversion failures before extracting it.
+pub fn yax_is_convertible(yax: &[u8]) -> Result<(), YaxError> {
+    check_well_formed(&yax_bytes_to_xml(yax, &YaxConvertOptions::default())?)
+}
+
+fn is_xml_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|first| first.is_alphabetic() || first == '_' || first == ':')
+        && chars.all(|c| c.is_alphanumeric() || matches!(c, '_' | ':' | '-' | '.'))
+}
 
+/// Checks that a UTF-8 `document` parses, has matching end tags, exactly one root element, valid element
+/// names and no characters in its text that XML 1.0 forbids.
+fn check_well_formed(document: &[u8]) -> Result<(), YaxError> {
+    let malformed = |message: String| YaxError::MalformedXml { message };
+    let mut reader = Reader::from_reader(document);
     let mut buffer = Vec::new();
-    let mut writer = Writer::new_with_indent(&mut buffer, b'\t', 1);
+    let (mut depth, mut root_elements) = (0usize, 0usize);
+    loop {
+        let event = reader
+            .read_event(&mut buffer)
+            .map_err(|e| malformed(format!("{} at byte {}", e, reader.buffer_position())))?;
+        match event {
+            Event::Start(ref e) | Event::Empty(ref e) => {
+                let name = String::from_utf8_lossy(e.name()).into_owned();
+                if !is_xml_name(&name) {
+                    return Err(malformed(format!("invalid element name {:?}", name)));
+                }
+                if depth == 0 {
+                    root_elements += 1;
+                }
+                if matches!(event, Event::Start(_)) {
+                    depth += 1;
+                }
+            }
+            Event::End(_) => depth = depth.saturating_sub(1),
+            Event::Text(ref e) => {
+                let text = String::from_utf8_lossy(e);
+                if let Some(c) = text.chars().find(|&c| c < ' ' && !matches!(c, '\t' | '\n' | '\r')) {
+                    return Err(malformed(format!("control character {:?} in text", c)));
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+        buffer.clear();
+    }
+    if depth != 0 {
+        return Err(malformed(format!("{} elements aren't closed", depth)));
+    }
+    if root_elements != 1 {
+        return Err(malformed(format!("{} root elements instead of one", root_elements)));
+    }
+    Ok(())
+}
+
+/// Like `yax_bytes_to_xml`, naming tags from `extra` before falling back to the built-in names.
+pub fn yax_to_xml_with_dictionary(yax: &[u8], extra: &HashMap<u32, String>) -> Result<Vec<u8>, YaxError> {
+    let options = YaxConvertOptions {
+        tag_names: extra.clone(),
+        ..Default::default()
+    };
+    yax_bytes_to_xml(yax, &options)
+}
+
+fn yax_to_xml_document<R: Read + Seek>(bytes: R, options: &YaxConvertOptions) -> Result<Vec<u8>, YaxError> {
+    xml_document(yax_to_xml(bytes, options)?, options)
+}
+
+/// Converts YAX read from `reader` to a complete XML document, for sources that can't seek, like a pipe or a
+/// decompressing reader. Files and byte slices are better converted with `convert_yax_to_xml_with_options`
+/// and `yax_bytes_to_xml`, which check the node count against the file length before reading the nodes.
+pub fn yax_reader_to_xml<R: Read>(reader: R, options: &YaxConvertOptions) -> Result<Vec<u8>, YaxError> {
+    xml_document(yax_stream_to_xml(reader, options)?, options)
+}
+
+/// Adds the declaration to the converted `xml_bytes` and applies the encoding and line endings of `options`.
+fn xml_document(xml_bytes: Vec<u8>, options: &YaxConvertOptions) -> Result<Vec<u8>, YaxError> {
+    let mut document = if options.strip_root { Vec::new() } else { options.encoding.declaration().to_vec() };
+    match options.encoding {
+        XmlEncoding::Utf8 => document.extend_from_slice(&xml_bytes),
+        XmlEncoding::ShiftJis => {
+            let xml = String::from_utf8(xml_bytes).map_err(|e| YaxError::MalformedXml { message: e.to_string() })?;
+            let (encoded, _, _) = SHIFT_JIS.encode(&xml);
+            document.extend_from_slice(&encoded);
+        }
+    }
+    if options.line_ending == LineEnding::CrLf {
+        // 0x0A is never part of a SHIFT_JIS character, so this works on both encodings
+        let mut crlf_document = Vec::with_capacity(document.len() + document.len() / 16);
+        for byte in document {
+            if byte == b'\n' {
+                crlf_document.push(b'\r');
+            }
+            crlf_document.push(byte);
+        }
+        document = crlf_document;
+    }
+    Ok(document)
+}
 
-    writer.write_event(Event::Start(BytesStart::borrowed(b"root", 4))).unwrap();
-    for root_node in root_nodes {
-        root_node.to_xml_events(&mut writer);
+pub fn convert_yax_to_xml_with_options(
+    yax_file_path: &str,
+    xml_file_path: &str,
+    options: &YaxConvertOptions,
+) -> io::Result<()> {
+    let Some(cache_dir) = &options.cache_dir else {
+        let document = yax_to_xml_document(ByteDataWrapper::from_file(yax_file_path)?, options)?;
+        return write_xml_file(xml_file_path, &document);
+    };
+
+    let yax = std::fs::read(yax_file_path)?;
+    let cache_path = cache_dir.join(options.cache_file_name(&yax));
+    if let Ok(document) = std::fs::read(&cache_path) {
+        return write_xml_file(xml_file_path, &document);
     }
-    writer.write_event(Event::End(BytesEnd::borrowed(b"root"))).unwrap();
 
-    buffer
+    let document = yax_bytes_to_xml(&yax, options)?;
+    std::fs::create_dir_all(cache_dir)?;
+    write_file_atomic(&cache_path, &document, DEFAULT_BUFFER_SIZE)?;
+    write_xml_file(xml_file_path, &document)
+}
+
+fn write_xml_file(xml_file_path: &str, document: &[u8]) -> io::Result<()> {
+    let mut xml_file = BufWriter::new(File::create(xml_file_path)?);
+    xml_file.write_all(document)?;
+    xml_file.flush()
+}
+
+pub fn convert_yax_to_xml(yax_file_path: &str, xml_file_path: &str) -> io::Result<()> {
+    convert_yax_to_xml_with_options(yax_file_path, xml_file_path, &YaxConvertOptions::default())
+        .map_err(|e| io::Error::new(e.kind(), format!("Failed to convert {} to XML: {}", yax_file_path, e)))
+}
+
+pub(crate) fn collect_files_with_extension(dir: &Path, extension: &str, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files_with_extension(&path, extension, files)?;
+        } else if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case(extension)) {
+            files.push(path);
+        }
+    }
+    Ok(())
 }
 
-pub fn convert_yax_to_xml(yax_file_path: &str, xml_file_path: &str) {
-    let yax_file = File::open(yax_file_path).expect("Failed to open YAX file");
-    let xml_bytes = yax_to_xml(BufReader::new(yax_file));
+/// Converts every `.yax` file below `dir` (recursively) to an `.xml` file next to it and returns the XML paths.
+/// With `skip_existing`, YAX files that already have an XML file next to them are left alone.
+/// Fails with the first YAX that doesn't convert, the others are still converted.
+pub async fn convert_yax_tree(dir: &str, skip_existing: bool) -> io::Result<Vec<String>> {
+    let mut yax_files = Vec::new();
+    collect_files_with_extension(Path::new(dir), "yax", &mut yax_files)?;
+    yax_files.sort();
+
+    let mut tasks = Vec::new();
+    let mut xml_files = Vec::new();
+    for yax_path in yax_files {
+        let xml_path = yax_path.with_extension("xml");
+        xml_files.push(xml_path.to_string_lossy().into_owned());
+        if skip_existing && xml_path.exists() {
+            continue;
+        }
+        tasks.push(tokio::task::spawn_blocking(move || -> io::Result<()> {
+            convert_yax_to_xml_with_options(&yax_path.to_string_lossy(), &xml_path.to_string_lossy(), &YaxConvertOptions::default())
+                .map_err(|e| io::Error::new(e.kind(), format!("Failed to convert {} to XML: {}", yax_path.display(), e)))
+        }));
+    }
+    for task in tasks {
+        task.await.map_err(io::Error::other)??;
+    }
 
-    let mut xml_file = BufWriter::new(File::create(xml_file_path).expect("Failed to create XML file"));
-    xml_file.write_all(b"<?xml version=\"1.0\" encoding=\"utf-8\"?>\n").unwrap();
-    xml_file.write_all(&xml_bytes).unwrap();
+    Ok(xml_files)
 }
 
 #[no_mangle]
 pub extern "C" fn yax_file_to_xml_file(yax_file_path: *const c_char, xml_file_path: *const c_char) {
-    let yax_file_path = unsafe { CStr::from_ptr(yax_file_path).to_str().unwrap() };
-    let xml_file_path = unsafe { CStr::from_ptr(xml_file_path).to_str().unwrap() };
+    let (yax_file_path, xml_file_path) = match (ffi_path(yax_file_path, "yax_file_path"), ffi_path(xml_file_path, "xml_file_path")) {
+        (Ok(yax_file_path), Ok(xml_file_path)) => (yax_file_path, xml_file_path),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("Failed to convert YAX file: {}", e);
+            return;
+        }
+    };
+
+    if let Err(e) = convert_yax_to_xml(yax_file_path, xml_file_path) {
+        eprintln!("{}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::*;
+    use std::fs;
 
-    let yax_file = File::open(yax_file_path).expect("Failed to open YAX file");
-    let xml_bytes = yax_to_xml(BufReader::new(yax_file));
+    #[tokio::test]
+    async fn yax_tree_converts_nested_files_next_to_them() {
+        let dir = temp_dir();
+        fs::create_dir_all(dir.path().join("a/b")).unwrap();
+        write_file(dir.path(), "top.yax", &yax_bytes("<root><text>top</text></root>"));
+        write_file(&dir.path().join("a/b"), "deep.yax", &yax_bytes("<root><name>deep</name></root>"));
+        write_file(dir.path(), "other.bin", b"not yax");
 
-    let mut xml_file = BufWriter::new(File::create(xml_file_path).expect("Failed to create XML file"));
-    xml_file.write_all(b"<?xml version=\"1.0\" encoding=\"utf-8\"?>\n").unwrap();
-    xml_file.write_all(&xml_bytes).unwrap();
-}
\ No newline at end of file
+        let xml_files = convert_yax_tree(&path_str(dir.path()), false).await.unwrap();
+        let mut expected = [path_str(&dir.path().join("a/b/deep.xml")), path_str(&dir.path().join("top.xml"))];
+        expected.sort();
+        assert_eq!(xml_files, expected);
+        assert!(fs::read_to_string(dir.path().join("top.xml")).unwrap().contains("<text>top</text>"));
+        assert!(fs::read_to_string(dir.path().join("a/b/deep.xml")).unwrap().contains("<name>deep</name>"));
+        assert!(!dir.path().join("other.xml").exists());
+    }
+
+    #[tokio::test]
+    async fn skip_existing_keeps_converted_files() {
+        let dir = temp_dir();
+        write_file(dir.path(), "a.yax", &yax_bytes("<root><text>a</text></root>"));
+        write_file(dir.path(), "a.xml", b"edited");
+
+        let xml_files = convert_yax_tree(&path_str(dir.path()), true).await.unwrap();
+        assert_eq!(xml_files, [path_str(&dir.path().join("a.xml"))]);
+        assert_eq!(fs::read(dir.path().join("a.xml")).unwrap(), b"edited");
+
+        convert_yax_tree(&path_str(dir.path()), false).await.unwrap();
+        assert!(fs::read_to_string(dir.path().join("a.xml")).unwrap().contains("<text>a</text>"));
+    }
+
+    #[tokio::test]
+    async fn invalid_yax_in_the_tree_is_an_error_naming_it() {
+        let dir = temp_dir();
+        write_file(dir.path(), "broken.yax", &[0xff; 16]);
+        let e = convert_yax_tree(&path_str(dir.path()), false).await.unwrap_err();
+        assert!(e.to_string().contains("broken.yax"), "{}", e);
+    }
+
+    /// A YAX whose nodes have the given indentations, no texts and a tag name hash of 0.
+    fn yax_with_indentations(indentations: &[u8]) -> Vec<u8> {
+        let mut yax = (indentations.len() as u32).to_le_bytes().to_vec();
+        for &indentation in indentations {
+            yax.push(indentation);
+            yax.extend_from_slice(&[0; 8]);
+        }
+        yax
+    }
+
+    fn indentation_error(indentations: &[u8]) -> Option<(usize, u8)> {
+        match yax_bytes_to_xml(&yax_with_indentations(indentations), &YaxConvertOptions::default()) {
+            Err(YaxError::InvalidIndentation { node_index, indentation }) => Some((node_index, indentation)),
+            Err(e) => panic!("{}", e),
+            Ok(_) => None,
+        }
+    }
+
+    #[test]
+    fn indented_first_node_is_invalid() {
+        assert_eq!(indentation_error(&[1]), Some((0, 1)));
+        assert_eq!(indentation_error(&[255]), Some((0, 255)));
+    }
+
+    #[test]
+    fn node_more_than_one_level_below_the_previous_is_invalid() {
+        assert_eq!(indentation_error(&[0, 1, 3]), Some((2, 3)));
+        assert_eq!(indentation_error(&[0, 1, 2, 0, 2]), Some((4, 2)));
+    }
+
+    #[test]
+    fn tree_of_the_maximum_depth_converts() {
+        let indentations: Vec<u8> = (0..=255).collect();
+        assert_eq!(indentation_error(&indentations), None);
+        assert_eq!(indentation_error(&[0, 1, 1, 0]), None);
+    }
+
+    #[test]
+    fn shift_jis_output_is_declared_and_encoded_as_shift_jis() {
+        let yax = yax_bytes("<root><text>宝箱</text></root>");
+        let options = YaxConvertOptions { encoding: XmlEncoding::ShiftJis, ..Default::default() };
+        let xml = yax_bytes_to_xml(&yax, &options).unwrap();
+
+        assert!(xml.starts_with(b"<?xml version=\"1.0\" encoding=\"shift_jis\"?>\n"));
+        let (encoded, _, _) = SHIFT_JIS.encode("<text>宝箱</text>");
+        assert!(xml.windows(encoded.len()).any(|window| window == &encoded[..]));
+        assert!(std::str::from_utf8(&xml).is_err());
+        // Reading the declaration, `xml_to_yax` gives back the same YAX
+        assert_eq!(crate::xml_to_yax_convert::xml_to_yax(&xml).unwrap(), yax);
+    }
+
+    #[test]
+    fn utf8_is_the_default_encoding() {
+        let xml = yax_bytes_to_xml(&yax_bytes("<root><text>宝箱</text></root>"), &YaxConvertOptions::default()).unwrap();
+        let xml = String::from_utf8(xml).unwrap();
+        assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n"));
+        assert!(xml.contains("<text>宝箱</text>"));
+    }
+
+    fn strict_conversion(yax: &[u8]) -> Result<Vec<u8>, YaxError> {
+        yax_bytes_to_xml(yax, &YaxConvertOptions { strict: true, ..Default::default() })
+    }
+
+    #[test]
+    fn strict_mode_rejects_garbage_after_the_string_table() {
+        let mut yax = yax_bytes("<root><text>a</text></root>");
+        let garbage_offset = yax.len() as u64 + 2;
+        yax.extend_from_slice(b"\0\0junk");
+        match strict_conversion(&yax) {
+            Err(YaxError::TrailingStringData { offset }) => assert_eq!(offset, garbage_offset),
+            result => panic!("{:?}", result.map(String::from_utf8)),
+        }
+        // Without strict mode the garbage is ignored
+        assert!(yax_bytes_to_xml(&yax, &YaxConvertOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn strict_mode_accepts_null_padding() {
+        let mut yax = yax_bytes("<root><text>a</text></root>");
+        yax.extend_from_slice(&[0; 3]);
+        assert!(strict_conversion(&yax).is_ok());
+        assert!(strict_conversion(&yax_bytes("<root><text>a</text></root>")).is_ok());
+    }
+
+    /// The start of a JFIF JPEG: SOI, then the APP0 segment.
+    const JPEG_BYTES: &[u8] = b"\xff\xd8\xff\xe0\x00\x10JFIF\x00\x01\x01\x00\x00\x01\x00\x01\x00\x00";
+
+    #[test]
+    fn jpeg_is_not_yax() {
+        match yax_bytes_to_xml(JPEG_BYTES, &YaxConvertOptions::default()) {
+            Err(YaxError::NotYax { node_count, file_len }) => {
+                assert_eq!(node_count, 0xe0ff_d8ff);
+                assert_eq!(file_len, JPEG_BYTES.len() as u64);
+            }
+            result => panic!("{:?}", result.map(String::from_utf8)),
+        }
+    }
+
+    #[test]
+    fn jpeg_streamed_is_not_yax() {
+        assert!(matches!(yax_reader_to_xml(JPEG_BYTES, &YaxConvertOptions::default()), Err(YaxError::NotYax { .. })));
+    }
+
+    #[test]
+    fn jpeg_file_conversion_fails_without_writing_xml() {
+        let dir = temp_dir();
+        let jpeg_path = write_file(dir.path(), "image.yax", JPEG_BYTES);
+        let xml_path = dir.path().join("image.xml");
+        let e = convert_yax_to_xml(&jpeg_path, &path_str(&xml_path)).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(!xml_path.exists());
+    }
+
+    #[test]
+    fn node_table_exactly_filling_the_file_is_yax() {
+        let yax = yax_with_indentations(&[0, 1]);
+        assert_eq!(yax.len(), 4 + 2 * 9);
+        assert!(yax_bytes_to_xml(&yax, &YaxConvertOptions::default()).is_ok());
+        assert!(matches!(yax_bytes_to_xml(&yax[..yax.len() - 1], &YaxConvertOptions::default()), Err(YaxError::NotYax { .. })));
+    }
+
+    #[test]
+    fn debug_offsets_comment_every_element_and_stay_well_formed() {
+        let yax = yax_bytes("<root><text>a</text><value>1</value></root>");
+        let options = YaxConvertOptions { debug_offsets: true, ..Default::default() };
+        let xml = yax_bytes_to_xml(&yax, &options).unwrap();
+        check_well_formed(&xml).unwrap();
+
+        let xml_text = String::from_utf8(xml.clone()).unwrap();
+        // The <root> wrapper is not a YAX node
+        assert_eq!(xml_text.matches("<!-- hash=0x").count(), 2);
+        // The strings follow the count and the 2 node entries, "a\0" then "1\0"
+        assert!(xml_text.contains(&format!(" string_offset={} -->\n\t<text>", 4 + 2 * 9)), "{}", xml_text);
+        assert!(xml_text.contains(&format!(" string_offset={} -->\n\t<value>", 4 + 2 * 9 + 2)), "{}", xml_text);
+        // Reading it back skips the comments
+        assert_eq!(crate::xml_to_yax_convert::xml_to_yax(&xml).unwrap(), yax);
+    }
+
+    #[test]
+    fn cache_serves_the_second_conversion() {
+        let dir = temp_dir();
+        let cache_dir = dir.path().join("cache");
+        let yax_path = write_file(dir.path(), "a.yax", &yax_bytes("<root><text>a</text></root>"));
+        let xml_path = path_str(&dir.path().join("a.xml"));
+        let options = YaxConvertOptions { cache_dir: Some(cache_dir.clone()), ..Default::default() };
+
+        convert_yax_to_xml_with_options(&yax_path, &xml_path, &options).unwrap();
+        let cached: Vec<_> = std::fs::read_dir(&cache_dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+        assert_eq!(cached.len(), 1);
+        assert_eq!(std::fs::read(&cached[0]).unwrap(), std::fs::read(&xml_path).unwrap());
+
+        // Only a cache hit can produce the marked document
+        std::fs::write(&cached[0], b"cached").unwrap();
+        convert_yax_to_xml_with_options(&yax_path, &xml_path, &options).unwrap();
+        assert_eq!(std::fs::read(&xml_path).unwrap(), b"cached");
+
+        // A changed YAX misses
+        std::fs::write(&yax_path, yax_bytes("<root><text>b</text></root>")).unwrap();
+        convert_yax_to_xml_with_options(&yax_path, &xml_path, &options).unwrap();
+        assert!(String::from_utf8(std::fs::read(&xml_path).unwrap()).unwrap().contains("<text>b</text>"));
+        assert_eq!(std::fs::read_dir(&cache_dir).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn cache_key_covers_the_output_options() {
+        let yax = yax_bytes("<root><text>a</text></root>");
+        let default = YaxConvertOptions::default().cache_file_name(&yax);
+        let debug_offsets = YaxConvertOptions { debug_offsets: true, ..Default::default() };
+        let hash_attributes = YaxConvertOptions { hash_attributes: true, ..Default::default() };
+        let tag_names = YaxConvertOptions { tag_names: HashMap::from([(1, "a".to_string())]), ..Default::default() };
+        for options in [debug_offsets, hash_attributes, tag_names] {
+            assert_ne!(options.cache_file_name(&yax), default);
+        }
+        // The cache directory itself doesn't change the output
+        let cache_dir = YaxConvertOptions { cache_dir: Some(PathBuf::from("elsewhere")), ..Default::default() };
+        assert_eq!(cache_dir.cache_file_name(&yax), default);
+    }
+
+    #[test]
+    fn dictionary_names_hashes_unknown_to_the_built_in_map() {
+        let unknown_hash = 0x1234_5678;
+        assert_eq!(hash_to_string_map(unknown_hash), None);
+        let mut yax = 1u32.to_le_bytes().to_vec();
+        yax.push(0);
+        yax.extend_from_slice(&unknown_hash.to_le_bytes());
+        yax.extend_from_slice(&0u32.to_le_bytes());
+
+        let xml = String::from_utf8(yax_bytes_to_xml(&yax, &YaxConvertOptions::default()).unwrap()).unwrap();
+        assert!(xml.contains("<UNKNOWN"), "{}", xml);
+
+        let extra = HashMap::from([(unknown_hash, "newlyReversed".to_string())]);
+        let xml = String::from_utf8(yax_to_xml_with_dictionary(&yax, &extra).unwrap()).unwrap();
+        assert!(xml.contains("<newlyReversed"), "{}", xml);
+        assert!(!xml.contains("UNKNOWN"));
+    }
+
+    #[test]
+    fn dictionary_entries_win_over_built_in_names() {
+        let yax = yax_bytes("<root><text>a</text></root>");
+        let text_hash = u32::from_le_bytes(yax[5..9].try_into().unwrap());
+        assert_eq!(hash_to_string_map(text_hash), Some("text"));
+
+        let extra = HashMap::from([(text_hash, "dialogue".to_string())]);
+        let xml = String::from_utf8(yax_to_xml_with_dictionary(&yax, &extra).unwrap()).unwrap();
+        assert!(xml.contains("<dialogue>a</dialogue>"), "{}", xml);
+    }
+
+    #[test]
+    fn strip_root_leaves_out_the_wrapper_and_declaration() {
+        let yax = yax_bytes("<root><text><value>1</value></text></root>");
+        let options = YaxConvertOptions { strip_root: true, ..Default::default() };
+        let xml = yax_bytes_to_xml(&yax, &options).unwrap();
+        let xml_text = String::from_utf8(xml.clone()).unwrap();
+        assert!(!xml_text.contains("<root>") && !xml_text.contains("</root>"), "{}", xml_text);
+        assert!(!xml_text.contains("<?xml"));
+        assert!(xml_text.starts_with("<text>"), "{}", xml_text);
+        // A single top level node is a document of its own
+        check_well_formed(&xml).unwrap();
+    }
+
+    /// The byte at a time scan `read_string_table` used before `memchr`.
+    fn read_string_table_bytewise(table: &[u8], table_offset: u64) -> (HashMap<u32, TableString>, usize) {
+        let mut strings = HashMap::new();
+        let mut position = 0;
+        while position < table.len() {
+            let mut end = position;
+            while end < table.len() && table[end] != 0 {
+                end += 1;
+            }
+            if end == position {
+                break;
+            }
+            strings.insert((table_offset + position as u64) as u32, decode_string(&table[position..end]));
+            position = end + 1;
+        }
+        (strings, position.min(table.len()))
+    }
+
+    #[test]
+    fn string_scan_matches_the_bytewise_scan() {
+        let long = "x".repeat(10_000);
+        let tables: [&[u8]; 7] = [
+            b"",
+            b"\0",
+            b"a\0bc\0def\0",
+            b"a\0\0ignored\0",
+            b"unterminated",
+            b"a\0unterminated",
+            b"\x82\xa0\x82\xa2\0utf8 \xc3\xa9\0",
+        ];
+        let long_table = format!("{}\0{}\0", long, long).into_bytes();
+        for table in tables.into_iter().chain([long_table.as_slice()]) {
+            assert_eq!(read_string_table(table, 40), read_string_table_bytewise(table, 40), "{:?}", table);
+        }
+    }
+
+    #[test]
+    fn convertible_yax_passes_the_check() {
+        assert!(yax_is_convertible(&yax_bytes("<root><text>a</text><value>1</value></root>")).is_ok());
+    }
+
+    #[test]
+    fn yax_producing_malformed_xml_fails_the_check() {
+        let mut yax = yax_bytes("<root><text>a</text></root>");
+        // Replace the text with a control character, which XML can't hold
+        yax[13] = 0x01;
+        assert!(matches!(yax_is_convertible(&yax), Err(YaxError::MalformedXml { .. })));
+        // It still converts, the check is what catches it
+        assert!(yax_bytes_to_xml(&yax, &YaxConvertOptions::default()).is_ok());
+
+        assert!(matches!(yax_is_convertible(&yax_with_indentations(&[0, 2])), Err(YaxError::InvalidIndentation { .. })));
+    }
+
+    #[test]
+    fn byte_data_wrapper_converts_like_a_buffered_file() {
+        let dir = temp_dir();
+        let yax = yax_bytes("<root><text>a</text><value>1</value></root>");
+        let yax_path = write_file(dir.path(), "a.yax", &yax);
+        let options = YaxConvertOptions::default();
+        let from_file = yax_to_xml(std::io::BufReader::new(File::open(&yax_path).unwrap()), &options).unwrap();
+        let from_wrapper = yax_to_xml(ByteDataWrapper { data: yax, position: 0 }, &options).unwrap();
+        assert_eq!(from_wrapper, from_file);
+    }
+
+    #[test]
+    fn known_tag_names_yield_the_whole_dictionary() {
+        // The entries of `hash_map.rs`
+        assert_eq!(known_tag_count(), 19201);
+        let names: HashMap<u32, &str> = known_tag_names().collect();
+        assert_eq!(names.len(), known_tag_count());
+
+        let yax = yax_bytes("<root><text>a</text></root>");
+        let text_hash = u32::from_le_bytes(yax[5..9].try_into().unwrap());
+        assert_eq!(names[&text_hash], "text");
+        assert!(names.iter().all(|(&hash, &name)| hash_to_string_map(hash) == Some(name)));
+    }
+
+    #[test]
+    fn strings_are_decoded_one_by_one() {
+        let string = |text: &str, utf8| TableString { text: text.to_string(), utf8 };
+        assert_eq!(decode_string(b"id_0001"), string("id_0001", true));
+        assert_eq!(decode_string("caf\u{e9}".as_bytes()), string("caf\u{e9}", true));
+        assert_eq!(decode_string(b"\x82\xa0\x82\xa2"), string("\u{3042}\u{3044}", false));
+    }
+
+    #[test]
+    fn ascii_and_shift_jis_strings_mix_in_one_yax() {
+        let mut yax = yax_bytes("<root><text>XXXX</text><value>id_0001</value></root>");
+        // Swap the placeholder for SHIFT_JIS "あい", which isn't valid UTF-8
+        let placeholder = yax.windows(4).position(|window| window == b"XXXX").unwrap();
+        yax[placeholder..placeholder + 4].copy_from_slice(b"\x82\xa0\x82\xa2");
+        let xml = String::from_utf8(yax_bytes_to_xml(&yax, &YaxConvertOptions::default()).unwrap()).unwrap();
+        assert!(xml.contains("<text>\u{3042}\u{3044}</text>"), "{}", xml);
+        assert!(xml.contains("<value>id_0001</value>"), "{}", xml);
+    }
+
+    #[test]
+    fn cdata_text_wraps_special_characters_and_converts_back() {
+        let yax = yax_bytes("<root><text>a &lt;b&gt; &amp; c</text><value>plain</value></root>");
+        let options = YaxConvertOptions { cdata_text: true, ..Default::default() };
+        let xml = yax_bytes_to_xml(&yax, &options).unwrap();
+        let xml_text = String::from_utf8(xml.clone()).unwrap();
+        assert!(xml_text.contains("<text><![CDATA[a <b> & c]]></text>"), "{}", xml_text);
+        // Text without special characters stays plain
+        assert!(xml_text.contains("<value>plain</value>"), "{}", xml_text);
+        assert_eq!(crate::xml_to_yax_convert::xml_to_yax(&xml).unwrap(), yax);
+
+        let escaped = String::from_utf8(yax_bytes_to_xml(&yax, &YaxConvertOptions::default()).unwrap()).unwrap();
+        assert!(escaped.contains("<text>a &lt;b&gt; &amp; c</text>"), "{}", escaped);
+    }
+
+    #[test]
+    fn cdata_text_escapes_text_containing_the_cdata_end() {
+        let yax = yax_bytes("<root><text>a ]]&gt; &lt;b</text></root>");
+        let options = YaxConvertOptions { cdata_text: true, ..Default::default() };
+        let xml = yax_bytes_to_xml(&yax, &options).unwrap();
+        let xml_text = String::from_utf8(xml.clone()).unwrap();
+        assert!(!xml_text.contains("CDATA"), "{}", xml_text);
+        assert_eq!(crate::xml_to_yax_convert::xml_to_yax(&xml).unwrap(), yax);
+    }
+
+    #[test]
+    fn whitespace_only_texts_are_cdata_only_when_asked_for() {
+        let yax = yax_bytes("<root><text><![CDATA[ \t ]]></text></root>");
+        let xml = yax_bytes_to_xml(&yax, &YaxConvertOptions::default()).unwrap();
+        let xml_text = String::from_utf8(xml).unwrap();
+        assert!(xml_text.contains("<text> \t </text>"), "{}", xml_text);
+
+        let options = YaxConvertOptions { cdata_whitespace_text: true, ..Default::default() };
+        let xml = yax_bytes_to_xml(&yax, &options).unwrap();
+        let xml_text = String::from_utf8(xml.clone()).unwrap();
+        assert!(xml_text.contains("<text><![CDATA[ \t ]]></text>"), "{}", xml_text);
+        assert_eq!(crate::xml_to_yax_convert::xml_to_yax(&xml).unwrap(), yax);
+    }
+
+    #[test]
+    fn root_tag_names_the_wrapper_and_any_wrapper_converts_back() {
+        let yax = yax_bytes("<root><text>a</text></root>");
+        let options = YaxConvertOptions { root_tag: Some("dialogue".to_string()), ..Default::default() };
+        let xml = yax_bytes_to_xml(&yax, &options).unwrap();
+        let xml_text = String::from_utf8(xml.clone()).unwrap();
+        assert!(xml_text.contains("<dialogue>") && xml_text.trim_end().ends_with("</dialogue>"), "{}", xml_text);
+        assert!(!xml_text.contains("<root>"));
+        assert_eq!(crate::xml_to_yax_convert::xml_to_yax(&xml).unwrap(), yax);
+
+        let default = String::from_utf8(yax_bytes_to_xml(&yax, &YaxConvertOptions::default()).unwrap()).unwrap();
+        assert!(default.contains("<root>"), "{}", default);
+    }
+
+    #[test]
+    fn large_yax_converts_identically_from_a_reader_and_back() {
+        // 20,000 wide entries, then a chain nested as deep as indentation allows
+        let mut xml = String::from("<root>");
+        for i in 0..20_000 {
+            xml.push_str(&format!("<text><value>{}</value></text>", i));
+        }
+        xml.push_str(&"<text>".repeat(255));
+        xml.push_str(&"</text>".repeat(255));
+        xml.push_str("</root>");
+        let yax = yax_bytes(&xml);
+        assert_eq!(u32::from_le_bytes(yax[..4].try_into().unwrap()), 40_255);
+
+        let options = YaxConvertOptions::default();
+        let converted = yax_bytes_to_xml(&yax, &options).unwrap();
+        assert_eq!(yax_reader_to_xml(yax.as_slice(), &options).unwrap(), converted);
+        check_well_formed(&converted).unwrap();
+        assert_eq!(crate::xml_to_yax_convert::xml_to_yax(&converted).unwrap(), yax);
+    }
+
+    #[test]
+    fn crlf_line_ending_writes_only_crlf_and_converts_back() {
+        let yax = yax_bytes("<root><text>line 1\nline 2</text><value>1</value></root>");
+        for encoding in [XmlEncoding::Utf8, XmlEncoding::ShiftJis] {
+            let options = YaxConvertOptions { encoding, line_ending: LineEnding::CrLf, ..Default::default() };
+            let xml = yax_bytes_to_xml(&yax, &options).unwrap();
+            let line_breaks = xml.iter().filter(|&&byte| byte == b'\n').count();
+            assert!(line_breaks > 1);
+            assert_eq!(xml.windows(2).filter(|pair| pair == b"\r\n").count(), line_breaks);
+            assert_eq!(crate::xml_to_yax_convert::xml_to_yax(&xml).unwrap(), yax);
+
+            let lf_options = YaxConvertOptions { encoding, ..Default::default() };
+            let lf_xml = yax_bytes_to_xml(&yax, &lf_options).unwrap();
+            assert!(!lf_xml.contains(&b'\r'));
+            assert_eq!(xml.iter().copied().filter(|&byte| byte != b'\r').collect::<Vec<_>>(), lf_xml);
+        }
+    }
+
+    #[test]
+    fn string_offset_0_means_no_text() {
+        // xml_to_yax writes nodes without text with offset 0
+        let empty = yax_bytes("<root><value></value></root>");
+        assert_eq!(u32::from_le_bytes(empty[9..13].try_into().unwrap()), 0);
+
+        let mut yax = yax_bytes("<root><text>a</text><value>b</value></root>");
+        // The second node's string offset, its text was "b"
+        yax[4 + 9 + 5..4 + 9 + 9].copy_from_slice(&0u32.to_le_bytes());
+        let xml = String::from_utf8(yax_bytes_to_xml(&yax, &YaxConvertOptions::default()).unwrap()).unwrap();
+        assert!(xml.contains("<text>a</text>"), "{}", xml);
+        let value = xml.split("<value>").nth(1).and_then(|rest| rest.split("</value>").next()).unwrap();
+        assert!(value.trim().is_empty(), "{}", xml);
+    }
+
+    /// `yax` with the tag name hash of its first node replaced by `hash`.
+    fn with_first_hash(mut yax: Vec<u8>, hash: u32) -> Vec<u8> {
+        yax[5..9].copy_from_slice(&hash.to_le_bytes());
+        yax
+    }
+
+    #[test]
+    fn hash_attributes_keep_unknown_and_aliased_hashes_through_a_round_trip() {
+        let unknown = with_first_hash(yax_bytes("<root><text>a</text><value>1</value></root>"), 0x1234_5678);
+        // A second hash named `text`, as aliased entries of the dictionary are
+        let alias_hash = 0x0BAD_F00D;
+        let aliased = with_first_hash(yax_bytes("<root><text>a</text></root>"), alias_hash);
+        let tag_names = HashMap::from([(alias_hash, "text".to_string())]);
+
+        for (yax, tag_names) in [(unknown, HashMap::new()), (aliased, tag_names)] {
+            let options = YaxConvertOptions { hash_attributes: true, tag_names: tag_names.clone(), ..Default::default() };
+            let xml = yax_bytes_to_xml(&yax, &options).unwrap();
+            let hash = u32::from_le_bytes(yax[5..9].try_into().unwrap());
+            assert!(String::from_utf8_lossy(&xml).contains(&format!("data-hash=\"0x{:08X}\"", hash)));
+            assert_eq!(crate::xml_to_yax_convert::xml_to_yax(&xml).unwrap(), yax);
+
+            // The clean output has no attributes and hashes the tag names again
+            let clean = yax_bytes_to_xml(&yax, &YaxConvertOptions { tag_names, ..Default::default() }).unwrap();
+            assert!(!String::from_utf8_lossy(&clean).contains(HASH_ATTRIBUTE));
+            assert_ne!(crate::xml_to_yax_convert::xml_to_yax(&clean).unwrap(), yax);
+        }
+    }
+
+    #[test]
+    fn hash_attribute_takes_hex_or_decimal_and_rejects_anything_else() {
+        let yax = with_first_hash(yax_bytes("<root><text>a</text></root>"), 0x1234_5678);
+        let decimal = format!("<root><text {}=\"{}\">a</text></root>", HASH_ATTRIBUTE, 0x1234_5678);
+        assert_eq!(crate::xml_to_yax_convert::xml_to_yax(decimal.as_bytes()).unwrap(), yax);
+        let invalid = format!("<root><text {}=\"0xZZ\">a</text></root>", HASH_ATTRIBUTE);
+        let e = crate::xml_to_yax_convert::xml_to_yax(invalid.as_bytes()).unwrap_err();
+        assert!(e.to_string().contains("Invalid data-hash attribute"), "{}", e);
+    }
+
+    /// A reader that can't seek and hands out at most 3 bytes per read, like a slow pipe.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let count = buf.len().min(3).min(self.0.len());
+            buf[..count].copy_from_slice(&self.0[..count]);
+            self.0 = &self.0[count..];
+            Ok(count)
+        }
+    }
+
+    #[test]
+    fn non_seekable_readers_convert_like_bytes() {
+        let yax = yax_bytes("<root><text>a</text><value>1</value><name><id>7</id></name></root>");
+        let debug_offsets = YaxConvertOptions { debug_offsets: true, ..Default::default() };
+        for options in [YaxConvertOptions::default(), debug_offsets] {
+            let converted = yax_bytes_to_xml(&yax, &options).unwrap();
+            assert_eq!(yax_reader_to_xml(Trickle(&yax), &options).unwrap(), converted);
+            let zlib_yax = zlib(&yax);
+            let decoder = flate2::read::ZlibDecoder::new(zlib_yax.as_slice());
+            assert_eq!(yax_reader_to_xml(decoder, &options).unwrap(), converted);
+        }
+    }
+
+    #[test]
+    fn non_seekable_reader_reports_trailing_data_at_the_counted_offset() {
+        let mut yax = yax_bytes("<root><text>a</text></root>");
+        yax.extend_from_slice(b"\0junk");
+        let options = YaxConvertOptions { strict: true, ..Default::default() };
+        let from_bytes = yax_bytes_to_xml(&yax, &options).unwrap_err();
+        let from_reader = yax_reader_to_xml(Trickle(&yax), &options).unwrap_err();
+        assert!(matches!(from_reader, YaxError::TrailingStringData { .. }), "{}", from_reader);
+        assert_eq!(from_reader.to_string(), from_bytes.to_string());
+    }
+
+    #[test]
+    fn non_seekable_reader_notices_a_node_table_longer_than_the_file() {
+        let mut yax = yax_bytes("<root><text>a</text></root>");
+        yax[..4].copy_from_slice(&1000u32.to_le_bytes());
+        let e = yax_reader_to_xml(Trickle(&yax), &YaxConvertOptions::default()).unwrap_err();
+        assert!(matches!(e, YaxError::NotYax { node_count: 1000, file_len } if file_len == yax.len() as u64), "{}", e);
+    }
+
+    #[test]
+    fn utf8_and_shift_jis_labels_convert_back_to_the_same_bytes() {
+        let mut yax = yax_bytes("<root><text>XXXXXX</text><value>XXXX</value><name>id_0001</name></root>");
+        // A UTF-8 "日本" and a SHIFT_JIS "あい" in the placeholders
+        let placeholder = yax.windows(6).position(|window| window == b"XXXXXX").unwrap();
+        yax[placeholder..placeholder + 6].copy_from_slice("\u{65E5}\u{672C}".as_bytes());
+        let placeholder = yax.windows(4).position(|window| window == b"XXXX").unwrap();
+        yax[placeholder..placeholder + 4].copy_from_slice(b"\x82\xa0\x82\xa2");
+
+        let xml = yax_bytes_to_xml(&yax, &YaxConvertOptions::default()).unwrap();
+        let xml_text = String::from_utf8(xml.clone()).unwrap();
+        assert!(xml_text.contains("<text data-encoding=\"utf-8\">\u{65E5}\u{672C}</text>"), "{}", xml_text);
+        assert!(xml_text.contains("<value>\u{3042}\u{3044}</value>"), "{}", xml_text);
+        assert!(xml_text.contains("<name>id_0001</name>"), "{}", xml_text);
+        assert_eq!(crate::xml_to_yax_convert::xml_to_yax(&xml).unwrap(), yax);
+    }
+}
@@ -0,0 +1,102 @@
+//! Runs the `extract_dat` binary and checks its `--json` output.
+
+use extract_dat_files::dat_repack::{repack_dat_files, DatRepackOptions};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+/// Builds a DAT holding `entries` at `dat_path` by repacking a directory of them.
+async fn write_dat(dat_path: &Path, entries: &[(&str, &[u8])]) {
+    let source_dir = tempfile::tempdir().unwrap();
+    for (name, data) in entries {
+        fs::write(source_dir.path().join(name), data).unwrap();
+    }
+    let files: Vec<&str> = entries.iter().map(|(name, _)| *name).collect();
+    fs::write(source_dir.path().join("dat_info.json"), json!({ "files": files }).to_string()).unwrap();
+    let options = DatRepackOptions::default();
+    repack_dat_files(source_dir.path().to_str().unwrap(), dat_path.to_str().unwrap(), &options).await.unwrap();
+}
+
+fn extract_dat(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_extract_dat")).args(args).output().unwrap()
+}
+
+fn stdout_json(output: &Output) -> Value {
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+#[tokio::test]
+async fn json_output_lists_the_extracted_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let dat_path = dir.path().join("test.dat");
+    write_dat(&dat_path, &[("a.bin", b"aaa"), ("b.txt", b"bb")]).await;
+    let extract_dir = dir.path().join("out");
+
+    let output = extract_dat(&[dat_path.to_str().unwrap(), extract_dir.to_str().unwrap(), "--json"]);
+    assert!(output.status.success());
+    let result = stdout_json(&output);
+    assert_eq!(result["count"], 2);
+    let mut files: Vec<&str> = result["files"].as_array().unwrap().iter().map(|file| file.as_str().unwrap()).collect();
+    files.sort();
+    assert_eq!(files, [extract_dir.join("a.bin").to_str().unwrap(), extract_dir.join("b.txt").to_str().unwrap()]);
+}
+
+#[test]
+fn json_output_of_a_failure_is_the_error_envelope() {
+    let dir = tempfile::tempdir().unwrap();
+    let missing = dir.path().join("missing.dat");
+    let output = extract_dat(&[missing.to_str().unwrap(), dir.path().join("out").to_str().unwrap(), "--json"]);
+    assert!(!output.status.success());
+    let result = stdout_json(&output);
+    assert_eq!(result["error"]["kind"], "NotFound");
+    assert!(result["error"]["message"].is_string());
+}
+
+#[test]
+fn json_output_of_an_empty_dat_has_no_warning_text() {
+    let dir = tempfile::tempdir().unwrap();
+    let dat_path = dir.path().join("empty.dat");
+    fs::write(&dat_path, b"").unwrap();
+    let output = extract_dat(&[dat_path.to_str().unwrap(), dir.path().join("out").to_str().unwrap(), "--json"]);
+    assert!(output.status.success());
+    assert_eq!(stdout_json(&output), json!({ "files": [], "count": 0 }));
+}
+
+#[tokio::test]
+async fn verify_only_json_reports_every_entry() {
+    let dir = tempfile::tempdir().unwrap();
+    let dat_path = dir.path().join("test.dat");
+    write_dat(&dat_path, &[("a.bin", b"aaa")]).await;
+    let output = extract_dat(&[dat_path.to_str().unwrap(), "--verify-only", "--json"]);
+    assert!(output.status.success());
+    let result = stdout_json(&output);
+    assert_eq!(result["passed"], true);
+    assert_eq!(result["entries"], json!([{ "name": "a.bin", "ok": true, "error": null }]));
+}
+
+#[test]
+fn unknown_flag_prints_the_usage() {
+    let output = extract_dat(&["--frobnicate"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("Usage:"));
+}
+
+#[tokio::test]
+async fn verify_only_fails_on_a_corrupt_entry() {
+    let dir = tempfile::tempdir().unwrap();
+    let dat_path = dir.path().join("test.dat");
+    write_dat(&dat_path, &[("a.bin", b"aaa"), ("b.bin", b"bbb")]).await;
+    // Make b.bin reach past the end of the file
+    let mut dat = fs::read(&dat_path).unwrap();
+    let sizes_offset = u32::from_le_bytes(dat[20..24].try_into().unwrap()) as usize;
+    dat[sizes_offset + 4..sizes_offset + 8].copy_from_slice(&10_000u32.to_le_bytes());
+    fs::write(&dat_path, dat).unwrap();
+
+    let output = extract_dat(&[dat_path.to_str().unwrap(), "--verify-only", "--json"]);
+    assert_eq!(output.status.code(), Some(1));
+    let result = stdout_json(&output);
+    assert_eq!(result["passed"], false);
+    assert_eq!(result["entries"][0], json!({ "name": "a.bin", "ok": true, "error": null }));
+    assert_eq!(result["entries"][1]["ok"], false);
+}